@@ -0,0 +1,6 @@
+mod client;
+mod runs;
+mod serve;
+mod structs;
+pub use client::request;
+pub use structs::{ControlRequest, ControlResponse, ControlServer, RunId, Runs, Target};
@@ -0,0 +1,17 @@
+use super::structs::{ControlRequest, ControlResponse};
+use crate::errors::control_errors::control_error::ControlError;
+use std::path::Path;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::UnixStream,
+};
+
+/// Sends a request to a running instance over its control socket, returning its answer.
+pub async fn request(socket: &Path, request: &ControlRequest) -> Result<ControlResponse, ControlError> {
+    let (reader, mut writer) = UnixStream::connect(socket).await?.into_split();
+    let mut line = serde_json::to_vec(request)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    let answer = BufReader::new(reader).lines().next_line().await?.ok_or(ControlError::NoResponse)?;
+    Ok(serde_json::from_str(&answer)?)
+}
@@ -0,0 +1,207 @@
+use super::structs::{Paused, RunEntry, RunId, RunListing, RunState, Runs, Target};
+use crate::scripts::structs::Script;
+use crate::utilities::{path_key::PathKey, timer::TimerController};
+use opentelemetry::Context;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc, MutexGuard, RwLockReadGuard},
+};
+use tokio::{sync::Notify, task::AbortHandle};
+
+impl Runs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a newly subscribed path, returning its run's id and what's notified should it be cancelled while it
+    /// waits out its debounce.
+    pub fn subscribed(&self, path: &Path) -> (RunId, Arc<Notify>) {
+        let cancel = Arc::new(Notify::new());
+        let state = RunState::Debouncing { timer: None, cancel: cancel.clone() };
+        (self.insert(path, state, false), cancel)
+    }
+
+    /// Records the timer a debouncing run is waiting on, so it can be listed and flushed.
+    pub fn timer_started(&self, run_id: RunId, controller: TimerController) {
+        if let Some(RunEntry { state: RunState::Debouncing { timer, .. }, .. }) = self.entries().get_mut(&run_id) {
+            *timer = Some(controller);
+        }
+    }
+
+    /// Marks a run as sent to the runner, returning false if it was cancelled in the meantime and shouldn't be sent.
+    pub fn queued(&self, run_id: RunId) -> bool {
+        match self.entries().get_mut(&run_id) {
+            Some(entry) => {
+                entry.state = RunState::Queued;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registers a run triggered by hand, which goes straight to the runner.
+    pub fn triggered(&self, path: &Path) -> RunId {
+        self.insert(path, RunState::Queued, true)
+    }
+
+    /// Called by the runner as it takes a run, returning the run's id and whether it was triggered by hand, or `None` if it
+    /// was cancelled while queued. Runs that never went through the subscriber, i.e. resumed ones, are registered here.
+    pub fn start(&self, cx: &Context, path: &Path) -> Option<(RunId, bool)> {
+        match cx.get::<RunId>() {
+            Some(run_id) => self.entries().get(run_id).map(|entry| (*run_id, entry.manual)),
+            None => Some((self.insert(path, RunState::Queued, false), false)),
+        }
+    }
+
+    /// Records how to kill a run's scripts. If it was cancelled between being started and getting here, it's killed now.
+    pub fn running(&self, run_id: RunId, abort: AbortHandle) {
+        match self.entries().get_mut(&run_id) {
+            Some(entry) => entry.state = RunState::Running { abort },
+            None => abort.abort(),
+        }
+    }
+
+    pub fn finished(&self, run_id: RunId) {
+        self.entries().remove(&run_id);
+    }
+
+    /// Cancels a run wherever it's at, returning what it was doing, or `None` if there's no such run. A debouncing run's
+    /// wait is cut short, a queued one is skipped by the runner and a running one has its scripts killed.
+    pub fn cancel(&self, run_id: RunId) -> Option<&'static str> {
+        let entry = self.entries().remove(&run_id)?;
+        Some(match entry.state {
+            RunState::Debouncing { cancel, .. } => {
+                // a permit is kept if the wait hasn't started listening yet
+                cancel.notify_one();
+                "debouncing"
+            }
+            RunState::Queued => "queued",
+            RunState::Running { abort } => {
+                abort.abort();
+                "running"
+            }
+        })
+    }
+
+    /// Runs out the timer of every debouncing run, returning how many there were.
+    pub async fn flush(&self) -> usize {
+        let mut flushed = 0;
+        for controller in self.timers().into_values() {
+            let mut controller = controller.lock().await;
+            // the timer breaks once more than its duration has passed since it was last reset
+            controller.1 = chrono::Utc::now() - controller.0 - chrono::Duration::seconds(1);
+            flushed += 1;
+        }
+        flushed
+    }
+
+    pub async fn list(&self) -> Vec<RunListing> {
+        let timers = self.timers();
+        let listed = self
+            .entries()
+            .iter()
+            .map(|(run_id, entry)| {
+                let state = match entry.state {
+                    RunState::Debouncing { .. } => "debouncing",
+                    RunState::Queued => "queued",
+                    RunState::Running { .. } => "running",
+                };
+                (*run_id, entry.path.clone(), state.to_string())
+            })
+            .collect::<Vec<_>>();
+        let mut runs = vec![];
+        for (run_id, path, state) in listed {
+            let remaining_secs = match timers.get(&run_id) {
+                Some(controller) => {
+                    let (duration, from) = *controller.lock().await;
+                    let remaining = duration - (chrono::Utc::now() - from);
+                    Some((remaining.num_milliseconds().max(0) as f64) / 1000.0)
+                }
+                None => None,
+            };
+            runs.push(RunListing { run_id, path, state, remaining_secs });
+        }
+        runs
+    }
+
+    /// Pauses a script or watch path, returning false if it already was.
+    pub fn pause(&self, target: &Target) -> bool {
+        let mut paused = self.paused.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match target {
+            Target::Script(name) => paused.scripts.insert(name.clone()),
+            Target::WatchPath(path) => paused.watch_paths.insert(PathKey::new(path).into_path_buf()),
+        }
+    }
+
+    /// Resumes a script or watch path, returning false if it wasn't paused.
+    pub fn resume(&self, target: &Target) -> bool {
+        let mut paused = self.paused.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match target {
+            Target::Script(name) => paused.scripts.remove(name),
+            Target::WatchPath(path) => paused.watch_paths.remove(&PathKey::new(path).into_path_buf()),
+        }
+    }
+
+    pub fn paused(&self) -> RwLockReadGuard<'_, Paused> {
+        self.paused.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Whether the script is paused, by name or through its watch path.
+    pub fn is_paused(&self, script: &Script) -> bool {
+        let paused = self.paused();
+        paused.scripts.contains(&script.name)
+            || paused.watch_paths.contains(&PathKey::new(&script.watch_path).into_path_buf())
+    }
+
+    fn insert(&self, path: &Path, state: RunState, manual: bool) -> RunId {
+        let run_id = RunId(self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let entry = RunEntry { path: PathBuf::from(path), state, manual };
+        self.entries().insert(run_id, entry);
+        run_id
+    }
+
+    /// The timers of debouncing runs, taken out so the entries aren't locked while the timers are.
+    fn timers(&self) -> BTreeMap<RunId, TimerController> {
+        self.entries()
+            .iter()
+            .filter_map(|(run_id, entry)| match &entry.state {
+                RunState::Debouncing { timer: Some(timer), .. } => Some((*run_id, timer.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<RunId, RunEntry>> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::timer::Timer;
+
+    #[tokio::test]
+    async fn cancels_and_flushes_runs_wherever_they_are() {
+        let runs = Runs::new();
+        let (debouncing, cancel) = runs.subscribed(Path::new("/watched/a"));
+        let timer = Timer::new(60);
+        runs.timer_started(debouncing, timer.controller.clone());
+        let (queued, _) = runs.subscribed(Path::new("/watched/b"));
+        assert!(runs.queued(queued));
+
+        let listed = runs.list().await;
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].remaining_secs.is_some_and(|remaining| remaining > 55.0));
+        assert_eq!(runs.flush().await, 1);
+        assert!(timer.time_to_break().unwrap());
+
+        assert_eq!(runs.cancel(debouncing), Some("debouncing"));
+        // notified before anything waited on it, which still counts
+        cancel.notified().await;
+        assert_eq!(runs.cancel(queued), Some("queued"));
+        assert_eq!(runs.start(&Context::new().with_value(queued), Path::new("/watched/b")), None);
+        assert_eq!(runs.cancel(queued), None);
+    }
+}
@@ -0,0 +1,183 @@
+use super::structs::{
+    ControlRequest, ControlResponse, ControlServer, Listing, ScriptListing, WatchPathListing,
+};
+use crate::errors::control_errors::control_error::ControlError;
+use crate::shutdown::Shutdown;
+use crate::telemetry::spans;
+use crate::utilities::path_key::PathKey;
+use crate::watcher::structs::{WatchPathStatus, Watcher};
+use itertools::Itertools;
+use log::{debug, info, warn};
+use opentelemetry::{trace::TraceContextExt, KeyValue};
+use std::{os::unix::fs::FileTypeExt, path::{Path, PathBuf}};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+impl ControlServer {
+    /// Answers requests on the control socket until shutdown is requested, then removes the socket. One left behind by an
+    /// earlier run is replaced.
+    pub async fn serve(self, path: PathBuf, shutdown: Shutdown) -> Result<(), ControlError> {
+        if path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            warn!("removing stale control socket at {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        info!("taking control requests at {}", path.display());
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    let server = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = server.answer(stream).await {
+                            debug!("control connection closed: {}", e);
+                        }
+                    });
+                },
+                _ = shutdown.requested() => break,
+            }
+        }
+        Ok(std::fs::remove_file(&path)?)
+    }
+
+    /// Answers each request on a connection, one line in and one line out, until the client hangs up.
+    async fn answer(&self, stream: UnixStream) -> Result<(), ControlError> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str::<ControlRequest>(&line) {
+                Ok(request) => {
+                    debug!("control request: {:?}", request);
+                    self.respond(request).await
+                }
+                Err(e) => ControlResponse::Error { message: format!("unable to read request: {}", e) },
+            };
+            let mut answer = serde_json::to_vec(&response)?;
+            answer.push(b'\n');
+            writer.write_all(&answer).await?;
+        }
+        Ok(())
+    }
+
+    async fn respond(&self, request: ControlRequest) -> ControlResponse {
+        match request {
+            ControlRequest::List => ControlResponse::Listing(self.list().await),
+            ControlRequest::Pause { target } => match self.runs.pause(&target) {
+                true => Self::ok(format!("paused {}", target)),
+                false => Self::ok(format!("{} was already paused", target)),
+            },
+            ControlRequest::Resume { target } => match self.runs.resume(&target) {
+                true => Self::ok(format!("resumed {}", target)),
+                false => Self::ok(format!("{} wasn't paused", target)),
+            },
+            ControlRequest::Trigger { script, path } => self.trigger(&script, &path),
+            ControlRequest::Flush => Self::ok(format!("ran out {} timers", self.runs.flush().await)),
+            ControlRequest::Cancel { run_id } => match self.runs.cancel(run_id) {
+                Some(state) => Self::ok(format!("cancelled run {}, which was {}", run_id, state)),
+                None => ControlResponse::Error { message: format!("there's no run {}", run_id) },
+            },
+        }
+    }
+
+    async fn list(&self) -> Listing {
+        let paused = self.runs.paused().clone();
+        let watch_paths = {
+            let statuses = self.statuses.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            Watcher::current_scripts(&self.scripts)
+                .iter()
+                .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                .map(|(watch_path, scripts)| {
+                    let scripts = scripts
+                        .registry
+                        .all()
+                        .into_iter()
+                        .map(|script| ScriptListing {
+                            paused: self.runs.is_paused(&script),
+                            name: script.name,
+                            event_triggers: script.event_triggers,
+                            priority: script.priority,
+                        })
+                        .collect();
+                    WatchPathListing {
+                        path: watch_path.to_path_buf(),
+                        status: statuses.get(watch_path.as_path()).unwrap_or(&WatchPathStatus::Missing).to_string(),
+                        paused: paused.watch_paths.contains(watch_path.as_path()),
+                        scripts,
+                    }
+                })
+                .collect()
+        };
+        Listing { watch_paths, runs: self.runs.list().await, paused }
+    }
+
+    /// Sends a script to the runner to run on a path, as if the path's timer had just run out. The path has to be within
+    /// one of the watch paths the script is configured for.
+    fn trigger(&self, name: &str, path: &Path) -> ControlResponse {
+        let target = PathKey::new(path);
+        let script = Watcher::current_scripts(&self.scripts)
+            .iter()
+            .filter(|(watch_path, _)| watch_path.contains(&target))
+            .find_map(|(_, scripts)| scripts.registry.find(name));
+        let Some(script) = script else {
+            return ControlResponse::Error {
+                message: format!("there's no script {} watching a path that {} is within", name, path.display()),
+            };
+        };
+        let path = target.into_path_buf();
+        let run_id = self.runs.triggered(&path);
+        let cx = spans::root(
+            "trigger",
+            vec![
+                KeyValue::new("script.name", script.name.clone()),
+                KeyValue::new("path", path.display().to_string()),
+            ],
+        );
+        cx.span().end();
+        match self.spawn_channel.send((path.clone(), vec![script], None, spans::queued(&cx).with_value(run_id))) {
+            Ok(_) => Self::ok(format!("triggered {} on {} as run {}", name, path.display(), run_id)),
+            Err(e) => {
+                self.runs.finished(run_id);
+                ControlResponse::Error { message: format!("unable to send the run to the runner: {}", e) }
+            }
+        }
+    }
+
+    fn ok(message: String) -> ControlResponse {
+        ControlResponse::Ok { message }
+    }
+}
+
+impl std::fmt::Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let paused = |paused: bool| match paused {
+            true => ", paused",
+            false => "",
+        };
+        for watch_path in &self.watch_paths {
+            writeln!(f, "{} ({}{})", watch_path.path.display(), watch_path.status, paused(watch_path.paused))?;
+            for script in &watch_path.scripts {
+                writeln!(
+                    f,
+                    "  {} on {}, priority {}{}",
+                    script.name,
+                    script.event_triggers.join(", "),
+                    script.priority,
+                    paused(script.paused)
+                )?;
+            }
+        }
+        if self.runs.is_empty() {
+            return writeln!(f, "no runs debouncing, queued or running");
+        }
+        writeln!(f, "runs:")?;
+        for run in &self.runs {
+            match run.remaining_secs {
+                Some(remaining) => writeln!(f, "  {} {} {}, {:.1}s left", run.run_id, run.state, run.path.display(), remaining)?,
+                None => writeln!(f, "  {} {} {}", run.run_id, run.state, run.path.display())?,
+            }
+        }
+        Ok(())
+    }
+}
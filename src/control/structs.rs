@@ -0,0 +1,141 @@
+use crate::runner::types::SpawnMessage;
+use crate::utilities::{thread_types::BroadcastSender, timer::TimerController};
+use crate::watcher::types::{SharedScripts, SharedWatchPathStatuses};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+    sync::{atomic::AtomicU64, Arc, Mutex, RwLock},
+};
+use tokio::{sync::Notify, task::AbortHandle};
+
+/// Identifies one run of a path's scripts from the moment its path is subscribed to, or it's triggered by hand, until its
+/// scripts are done. Carried along in the run's trace context, the way [`crate::telemetry::spans::queued`] stamps it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RunId(pub u64);
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Every run between being subscribed to and finishing, along with what's paused. Shared by the subscriber, the runner and
+/// the control socket, so runs can be listed, cut short or cancelled from outside.
+#[derive(Debug, Clone, Default)]
+pub struct Runs {
+    pub next_id: Arc<AtomicU64>,
+    pub entries: Arc<Mutex<BTreeMap<RunId, RunEntry>>>,
+    pub paused: Arc<RwLock<Paused>>,
+}
+
+#[derive(Debug)]
+pub struct RunEntry {
+    pub path: PathBuf,
+    pub state: RunState,
+    /// Triggered through the control socket, which runs its script even while it's paused.
+    pub manual: bool,
+}
+
+/// Where a run is at, along with whatever it takes to cut it short there.
+#[derive(Debug)]
+pub enum RunState {
+    /// Waiting out its debounce. The timer is only there once the wait has started.
+    Debouncing { timer: Option<TimerController>, cancel: Arc<Notify> },
+    /// Sent to the runner, which hasn't started it yet. Cancelling it is just a matter of forgetting it.
+    Queued,
+    Running { abort: AbortHandle },
+}
+
+/// Scripts paused by name, and watch paths whose scripts are all paused. Paused scripts are left out of runs when the runner
+/// gets to them, so paths keep being debounced as usual in the meantime.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Paused {
+    pub scripts: BTreeSet<String>,
+    pub watch_paths: BTreeSet<PathBuf>,
+}
+
+/// What `pause` and `resume` apply to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Script(String),
+    WatchPath(PathBuf),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Script(name) => write!(f, "script {}", name),
+            Target::WatchPath(path) => write!(f, "watch path {}", path.display()),
+        }
+    }
+}
+
+/// A request sent over the control socket, one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    List,
+    Pause { target: Target },
+    Resume { target: Target },
+    Trigger { script: String, path: PathBuf },
+    /// Runs out every pending timer now.
+    Flush,
+    Cancel { run_id: RunId },
+}
+
+/// The answer to a [`ControlRequest`], also one JSON object per line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Ok { message: String },
+    Listing(Listing),
+    Error { message: String },
+}
+
+/// What `list` answers with.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub watch_paths: Vec<WatchPathListing>,
+    pub runs: Vec<RunListing>,
+    pub paused: Paused,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchPathListing {
+    pub path: PathBuf,
+    /// See [`crate::watcher::structs::WatchPathStatus`].
+    pub status: String,
+    pub paused: bool,
+    pub scripts: Vec<ScriptListing>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScriptListing {
+    pub name: String,
+    pub event_triggers: Vec<String>,
+    pub priority: i32,
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunListing {
+    pub run_id: RunId,
+    pub path: PathBuf,
+    /// debouncing, queued or running.
+    pub state: String,
+    /// Seconds until the debounce timer runs out, for runs still debouncing.
+    pub remaining_secs: Option<f64>,
+}
+
+/// Answers requests on the control socket, with access to everything the requests act on.
+#[derive(Debug, Clone)]
+pub struct ControlServer {
+    pub runs: Runs,
+    pub scripts: SharedScripts,
+    pub statuses: SharedWatchPathStatuses,
+    /// Triggered runs go to the runner the same way paths whose timers ran out do.
+    pub spawn_channel: BroadcastSender<SpawnMessage>,
+}
@@ -0,0 +1,4 @@
+mod fork;
+mod stop;
+mod structs;
+pub use structs::{Daemon, StopOutcome};
@@ -0,0 +1,103 @@
+use super::structs::Daemon;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    os::fd::{AsRawFd, FromRawFd},
+    path::Path,
+};
+
+/// What the daemon writes to the pipe once it's up, followed by its pid.
+const READY: &str = "ready";
+
+impl Daemon {
+    /// Forks into the background: the calling process waits to hear whether the daemon came up and exits accordingly,
+    /// while the daemon, in a session of its own and forked a second time so it can never pick up a controlling terminal,
+    /// returns. Its stdin is /dev/null and its stdout and stderr go to `output`, or /dev/null. It stays in the current
+    /// directory, since scripts and the log config are found relative to it.
+    ///
+    /// Has to be called before any threads are started, as only the forking thread carries on in the child.
+    pub fn start(output: Option<&Path>) -> std::io::Result<Daemon> {
+        let (mut status_reader, status_writer) = Self::pipe()?;
+        match Self::fork()? {
+            0 => {}
+            _ => {
+                drop(status_writer);
+                std::process::exit(Self::wait_until_up(&mut status_reader, output));
+            }
+        }
+        drop(status_reader);
+        if unsafe { libc::setsid() } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        if Self::fork()? != 0 {
+            // the session leader bows out, leaving the daemon orphaned and without a way back to a terminal
+            unsafe { libc::_exit(0) };
+        }
+        Self::redirect_stdio(output)?;
+        Ok(Daemon { status: status_writer })
+    }
+
+    /// Tells the process that started the daemon that it's up, letting it exit.
+    pub fn ready(mut self) {
+        let _ = write!(self.status, "{} {}", READY, std::process::id());
+    }
+
+    /// Tells the process that started the daemon why it didn't come up, for it to print before exiting.
+    pub fn failed(mut self, message: &str) {
+        let _ = write!(self.status, "{}", message);
+    }
+
+    /// Returns the exit code of the process that started the daemon, once the daemon has said how starting went, or
+    /// closed the pipe without saying.
+    fn wait_until_up(status_reader: &mut File, output: Option<&Path>) -> i32 {
+        let mut status = String::new();
+        let _ = status_reader.read_to_string(&mut status);
+        match status.strip_prefix(READY) {
+            Some(pid) => {
+                println!("rusty hooks started in the background as pid {}", pid.trim());
+                0
+            }
+            None if !status.is_empty() => {
+                eprintln!("{}", status);
+                1
+            }
+            None => {
+                let look_in = output.map_or("".to_string(), |output| format!(", see {}", output.display()));
+                eprintln!("rusty hooks exited before it was up{}", look_in);
+                1
+            }
+        }
+    }
+
+    fn redirect_stdio(output: Option<&Path>) -> std::io::Result<()> {
+        let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+        let output = match output {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+            None => null.try_clone()?,
+        };
+        for (file, fd) in [(&null, libc::STDIN_FILENO), (&output, libc::STDOUT_FILENO), (&output, libc::STDERR_FILENO)] {
+            if unsafe { libc::dup2(file.as_raw_fd(), fd) } == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn pipe() -> std::io::Result<(File, File)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // closed on exec, so scripts can't hold the pipe open and keep the starting process waiting
+        unsafe { libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC) };
+        unsafe { libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC) };
+        Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+    }
+
+    fn fork() -> std::io::Result<libc::pid_t> {
+        match unsafe { libc::fork() } {
+            -1 => Err(std::io::Error::last_os_error()),
+            pid => Ok(pid),
+        }
+    }
+}
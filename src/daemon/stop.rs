@@ -0,0 +1,30 @@
+use super::structs::{Daemon, StopOutcome};
+use crate::utilities::set_process_lockfile::Lockfile;
+use log::debug;
+use std::{
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+impl Daemon {
+    /// Sends SIGTERM to the instance holding the lock on the pid file, the same as stopping it any other way, then waits
+    /// for it to exit for up to `timeout`.
+    pub fn stop(pid_file: &Path, timeout: Duration) -> std::io::Result<StopOutcome> {
+        let Some(pid) = Lockfile::holder(pid_file) else {
+            return Ok(StopOutcome::NotRunning);
+        };
+        debug!("sending SIGTERM to pid {}", pid);
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let sent_at = Instant::now();
+        while Lockfile::is_alive(pid) {
+            if sent_at.elapsed() > timeout {
+                return Ok(StopOutcome::TimedOut(pid));
+            }
+            sleep(Duration::from_millis(100));
+        }
+        Ok(StopOutcome::Stopped(pid))
+    }
+}
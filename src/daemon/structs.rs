@@ -0,0 +1,20 @@
+use std::fs::File;
+
+/// Held by the daemon once it's forked off, to tell the process that started it whether it came up. That process waits
+/// on it, and exits 0 once the daemon is ready, or 1 if the daemon fails first, see [`Daemon::start`].
+#[derive(Debug)]
+pub struct Daemon {
+    /// Write end of the pipe the starting process reads from.
+    pub status: File,
+}
+
+/// How `rusty-hooks stop` went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOutcome {
+    /// It stopped, having run as this pid.
+    Stopped(u32),
+    /// Nothing holds the lock on the pid file, so there was nothing to stop.
+    NotRunning,
+    /// Still running once the timeout ran out.
+    TimedOut(u32),
+}
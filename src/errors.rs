@@ -1,5 +1,9 @@
 pub mod command_line_errors;
+pub mod control_errors;
+pub mod lockfile_errors;
+pub mod logging_errors;
 pub mod runtime_error;
 pub mod script_errors;
 pub mod shared_errors;
+pub mod telemetry_errors;
 pub mod watcher_errors;
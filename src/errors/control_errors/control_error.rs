@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("io error: `{0}`")]
+    IoError(#[from] std::io::Error),
+    #[error("unable to read or write a control message: `{0}`")]
+    JsonError(#[from] serde_json::Error),
+    #[error("the connection closed before an answer came back")]
+    NoResponse,
+}
@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LockfileError {
+    #[error("rusty hooks is already running{}, holding the lock on {}. stop it first, or give this one its own --instance name", .pid.map(|pid| format!(" as pid {}", pid)).unwrap_or_default(), .path.display())]
+    AlreadyRunning { pid: Option<u32>, path: PathBuf },
+    #[error("unable to tell where the pid file goes, provide --pid-file")]
+    NoDefaultPath,
+    #[error("io error with the pid file at {}: `{source}`", .path.display())]
+    IoError { path: PathBuf, source: std::io::Error },
+}
@@ -0,0 +1 @@
+pub mod logging_error;
@@ -0,0 +1,19 @@
+use crate::logging::LogSink;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LoggingError {
+    #[error("unable to load the log config at {}: `{source}`", .path.display())]
+    ConfigFileError { path: PathBuf, source: anyhow::Error },
+    #[error("unable to write logs to {}: `{source}`", .path.display())]
+    IoError { path: PathBuf, source: std::io::Error },
+    #[error("unable to roll the logs in {}: `{source}`", .path.display())]
+    RollerError { path: PathBuf, source: anyhow::Error },
+    #[error("unable to log to {sink}: `{source}`")]
+    SinkError { sink: LogSink, source: anyhow::Error },
+    #[error("invalid logging config: `{0}`")]
+    ConfigError(#[from] log4rs::config::runtime::ConfigErrors),
+    #[error("logging was already set up: `{0}`")]
+    SetLoggerError(#[from] log::SetLoggerError),
+}
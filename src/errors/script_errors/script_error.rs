@@ -20,6 +20,14 @@ pub enum ScriptConfigError {
     JsonError(#[from] serde_json::Error),
     #[error("error parsing scripts.yml: `{0}`")]
     YAMLError(#[from] serde_yaml::Error),
+    #[error("unable to expand watch path `{0}`: {1}")]
+    WatchPathError(String, String),
+    #[error("error with config file `{0}`: {1}")]
+    ConfigFileError(String, String),
+    #[error("error parsing config file `{0}` at line {1}, column {2}: {3}")]
+    ParseError(String, usize, usize, String),
+    #[error("script name `{0}` is used in both `{1}` and `{2}`, script names have to be unique")]
+    DuplicateScriptName(String, String, String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
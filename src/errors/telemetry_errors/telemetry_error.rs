@@ -0,0 +1,14 @@
+use opentelemetry::trace::TraceError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TelemetryError {
+    #[error("unable to set up trace exporting: `{0}`")]
+    TraceError(#[from] TraceError),
+    #[error("error serving metrics or health checks: `{0}`")]
+    ServeError(#[from] hyper::Error),
+    #[error("io error: `{0}`")]
+    IoError(#[from] std::io::Error),
+    #[error("unable to build a health check request: `{0}`")]
+    RequestError(#[from] hyper::http::Error),
+}
@@ -1,7 +1,22 @@
+use itertools::Itertools;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum EventError {
     #[error("error while watching file system events: `{0}`")]
-    NotifyError(#[from] notify::Error),
+    NotifyError(notify::Error),
+    #[error("ran out of inotify watches while watching `{0}`. Raise the limit with `sysctl fs.inotify.max_user_watches=<n>`, or watch less of the path with `recursive: false` or `max_depth`")]
+    WatchLimitReached(String),
+}
+
+impl From<notify::Error> for EventError {
+    fn from(error: notify::Error) -> Self {
+        match error.kind {
+            // inotify reports running out of watches as ENOSPC, which notify translates into this
+            notify::ErrorKind::MaxFilesWatch => EventError::WatchLimitReached(
+                error.paths.iter().map(|path| path.display()).join(", "),
+            ),
+            _ => EventError::NotifyError(error),
+        }
+    }
 }
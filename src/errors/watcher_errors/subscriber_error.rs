@@ -6,7 +6,7 @@ use crate::{
         runtime_error::enums::RuntimeError,
         shared_errors::thread_errors::{ThreadError, UnexpectedAnyhowError},
     },
-    scripts::structs::Script,
+    runner::types::SpawnMessage,
 };
 use thiserror::Error;
 use tokio::sync::{broadcast::error::SendError, TryLockError};
@@ -20,7 +20,7 @@ pub enum SubscriptionError {
     #[error("error with threads spawned to wait on watched paths `${0}`")]
     RuntimeError(#[from] RuntimeError),
     #[error("error with sending path and scripts to spawn thread: `${0}`")]
-    SpawnSendError(#[from] SendError<(PathBuf, Vec<Script>)>),
+    SpawnSendError(#[from] SendError<SpawnMessage>),
     #[error("error managing timer: `${0}`")]
     TimerError(#[from] TimerError),
     #[error("`${0}`")]
@@ -1,2 +1,8 @@
+mod check;
 mod init;
+mod serve;
+mod status;
+mod structs;
 pub use init::HealthReporter;
+pub use status::request_readiness;
+pub use structs::{Health, HealthEndpoint};
@@ -0,0 +1,197 @@
+use super::structs::{Health, HealthCheck, HealthReport, Probe};
+use crate::{metrics::Metrics, watcher::types::SharedWatchPathStatuses};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Tasks that have to keep running for events to turn into script runs.
+pub const EXPECTED_TASKS: [&str; 4] = ["events", "subscribe", "unsubscribe", "runner"];
+/// How long a notify error keeps rusty hooks from being ready, as events may have been missed around it.
+pub const NOTIFY_ERROR_WINDOW: Duration = Duration::from_secs(300);
+
+impl Health {
+    pub fn new(metrics: Metrics, statuses: SharedWatchPathStatuses, stall_after: Duration) -> Self {
+        Health {
+            metrics,
+            statuses,
+            stall_after,
+        }
+    }
+
+    /// Whether every task has been started, and with that every watch path that could be attached, attached.
+    pub fn started(&self) -> bool {
+        EXPECTED_TASKS.iter().all(|task| self.metrics.task_up(task).is_some())
+    }
+
+    /// Resolves once every task has been started, see [`Health::started`].
+    pub async fn until_started(&self) {
+        while !self.started() {
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// A line on what rusty hooks is up to, e.g. for systemd's status.
+    pub fn summary(&self) -> String {
+        let (attached, total) = self.watch_path_counts();
+        format!(
+            "watching {} of {} watch paths, {} scripts running",
+            attached,
+            total,
+            self.metrics.scripts_running()
+        )
+    }
+
+    pub fn check(&self) -> HealthReport {
+        let mut checks = EXPECTED_TASKS.iter().map(|task| self.check_task(task)).collect::<Vec<_>>();
+        checks.extend(self.check_queues());
+        checks.push(self.check_notify_errors());
+        checks.push(self.check_watch_paths());
+        let live = checks.iter().all(|check| check.ok || check.required_for != Probe::Live);
+        HealthReport {
+            live,
+            ready: live && checks.iter().all(|check| check.ok),
+            checks,
+        }
+    }
+
+    fn check_task(&self, task: &'static str) -> HealthCheck {
+        let name = format!("task:{}", task);
+        match self.metrics.task_up(task) {
+            Some(true) => HealthCheck::new(name, true, Probe::Live, "running"),
+            Some(false) => HealthCheck::new(name, false, Probe::Live, "stopped"),
+            None => HealthCheck::new(name, false, Probe::Ready, "not started yet"),
+        }
+    }
+
+    /// A channel is stalled when messages are waiting on it right now, and nothing has been received from it for the
+    /// stall period. Those sent since the last receive count, so a consumer stuck before its next one is caught too.
+    fn check_queues(&self) -> Vec<HealthCheck> {
+        self.metrics
+            .queue_activity()
+            .into_iter()
+            .map(|(channel, depth, received_at)| {
+                let idle = received_at.elapsed();
+                let name = format!("queue:{}", channel);
+                match depth > 0 && idle >= self.stall_after {
+                    true => HealthCheck::new(
+                        name,
+                        false,
+                        Probe::Live,
+                        format!("{} messages waiting, none received for {}s", depth, idle.as_secs()),
+                    ),
+                    false => HealthCheck::new(name, true, Probe::Live, "moving"),
+                }
+            })
+            .collect()
+    }
+
+    fn check_notify_errors(&self) -> HealthCheck {
+        match self.metrics.last_notify_error().map(|at| at.elapsed()) {
+            Some(since) if since < NOTIFY_ERROR_WINDOW => HealthCheck::new(
+                "notify",
+                false,
+                Probe::Ready,
+                format!("notify error {}s ago", since.as_secs()),
+            ),
+            _ => HealthCheck::new("notify", true, Probe::Ready, "no recent errors"),
+        }
+    }
+
+    fn check_watch_paths(&self) -> HealthCheck {
+        let (attached, total) = self.watch_path_counts();
+        HealthCheck::new(
+            "watch_paths",
+            attached > 0,
+            Probe::Ready,
+            format!("watching {} of {} watch paths", attached, total),
+        )
+    }
+
+    fn watch_path_counts(&self) -> (usize, usize) {
+        let statuses = match self.statuses.read() {
+            Ok(statuses) => statuses,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let attached = statuses.values().filter(|status| status.is_attached()).count();
+        (attached, statuses.len())
+    }
+}
+
+impl HealthCheck {
+    fn new(name: impl Into<String>, ok: bool, required_for: Probe, detail: impl Into<String>) -> Self {
+        HealthCheck {
+            name: name.into(),
+            ok,
+            required_for,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{utilities::lag_counter::LagCounter, watcher::structs::WatchPathStatus};
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{Arc, RwLock},
+    };
+
+    #[test]
+    fn ready_once_every_task_runs_and_alive_until_one_stops() {
+        let statuses = HashMap::from([(PathBuf::from("/watched"), WatchPathStatus::Watching)]);
+        let health = Health::new(
+            Metrics::new(LagCounter::new()),
+            Arc::new(RwLock::new(statuses)),
+            Duration::from_secs(60),
+        );
+        let report = health.check();
+        assert!(report.live && !report.ready);
+
+        EXPECTED_TASKS.iter().for_each(|task| health.metrics.task_started(task));
+        health.metrics.set_queue_depth("spawn", 3);
+        let report = health.check();
+        assert!(report.live && report.ready);
+
+        health.metrics.notify_error();
+        let report = health.check();
+        assert!(report.live && !report.ready);
+
+        health.metrics.task_stopped("runner");
+        let report = health.check();
+        assert!(!report.live && !report.ready);
+    }
+
+    #[test]
+    fn a_queue_left_waiting_is_stalled() {
+        let health = Health::new(
+            Metrics::new(LagCounter::new()),
+            Arc::new(RwLock::new(HashMap::new())),
+            Duration::ZERO,
+        );
+        health.metrics.set_queue_depth("events", 0);
+        assert!(health.check().live);
+        health.metrics.set_queue_depth("events", 2);
+        assert!(!health.check().live);
+    }
+
+    #[test]
+    fn a_queue_filling_up_behind_a_stuck_consumer_is_stalled() {
+        let health = Health::new(
+            Metrics::new(LagCounter::new()),
+            Arc::new(RwLock::new(HashMap::new())),
+            Duration::ZERO,
+        );
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(16);
+        health.metrics.track_queue("spawn", &receiver);
+        sender.send(1).unwrap();
+        receiver.try_recv().unwrap();
+        health.metrics.set_queue_depth("spawn", receiver.len());
+        assert!(health.check().live);
+
+        // the consumer never comes back to receive these, so the depth recorded at its last receive stays at 0
+        (2..5).for_each(|n| drop(sender.send(n)));
+        assert!(!health.check().live);
+        assert_eq!(health.metrics.queue_activity()[0].1, 3);
+    }
+}
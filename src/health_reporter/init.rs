@@ -1,28 +1,101 @@
-use crate::{errors::shared_errors::thread_errors::ThreadError, utilities::traits::Utilities};
-use log::info;
+use super::structs::Health;
+use crate::{
+    errors::shared_errors::thread_errors::ThreadError,
+    utilities::{lag_counter::LagCounter, traits::Utilities},
+};
+use crate::systemd::SystemdNotifier;
+use crate::watcher::types::SharedWatchPathStatuses;
+use itertools::Itertools;
+use log::{error, info, warn};
+use std::{net::SocketAddr, path::PathBuf};
 use tokio::{task::JoinHandle, time::sleep};
 
 #[derive(Debug)]
 pub struct HealthReporter {
     pub runtime: tokio::runtime::Runtime,
+    pub lag_counter: LagCounter,
+    pub health: Health,
 }
 
 impl Utilities for HealthReporter {}
 
 impl HealthReporter {
-    pub fn new() -> Result<Self, ThreadError> {
-        let script_runtime = <Self as Utilities>::new_runtime(1, &"health_check".to_string())?;
+    pub fn new(lag_counter: LagCounter, health: Health) -> Result<Self, ThreadError> {
+        // unlike the other runtimes this one needs io, to serve the health checks
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("health_check")
+            .enable_all()
+            .build()?;
         Ok(HealthReporter {
-            runtime: script_runtime,
+            runtime,
+            lag_counter,
+            health,
         })
     }
 
     pub fn begin_reporting(&self) -> JoinHandle<()> {
+        let lag_counter = self.lag_counter.clone();
+        let health = self.health.clone();
         self.runtime.spawn(async move {
             loop {
-                info!(target: "health_report", "rusty hooks is healthy");
+                let report = health.check();
+                match report.live && report.ready {
+                    true => info!(target: "health_report", "rusty hooks is healthy"),
+                    false => {
+                        for check in report.checks.iter().filter(|check| !check.ok) {
+                            warn!(target: "health_report", "health check {} failed: {}", check.name, check.detail);
+                        }
+                    }
+                }
+                Self::report_watch_paths(&health.statuses);
+                for (channel, num_dropped) in lag_counter.dropped() {
+                    info!(target: "health_report", "{} messages dropped on the {} channel since startup", num_dropped, channel);
+                }
                 sleep(tokio::time::Duration::from_secs(30)).await;
             }
         })
     }
+
+    /// Serves metrics and health checks over http and/or a unix socket, whichever were asked for. Failing to serve them
+    /// is logged rather than stopping anything, they're there to look in on the watching, not to do it.
+    pub fn serve(&self, http_address: Option<SocketAddr>, socket_path: Option<PathBuf>) {
+        if let Some(address) = http_address {
+            let health = self.health.clone();
+            self.runtime.spawn(async move {
+                if let Err(e) = health.serve_http(address).await {
+                    error!("{}", e);
+                }
+            });
+        }
+        if let Some(path) = socket_path {
+            let health = self.health.clone();
+            self.runtime.spawn(async move {
+                if let Err(e) = health.serve_socket(path).await {
+                    error!("{}", e);
+                }
+            });
+        }
+    }
+
+    /// Keeps systemd up to date on readiness and liveness, if it's listening.
+    pub fn notify_systemd(&self, notifier: SystemdNotifier) {
+        if notifier.is_enabled() {
+            self.runtime.spawn(notifier.supervise(self.health.clone()));
+        }
+    }
+
+    fn report_watch_paths(statuses: &SharedWatchPathStatuses) {
+        let statuses = match statuses.read() {
+            Ok(statuses) => statuses.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        let (watching, missing): (Vec<_>, Vec<_>) = statuses
+            .iter()
+            .partition(|(_, status)| status.is_attached());
+        info!(target: "health_report", "watching {} of {} watch paths", watching.len(), statuses.len());
+        for (watch_path, status) in missing.into_iter().sorted_by_key(|(watch_path, _)| *watch_path) {
+            info!(target: "health_report", "watch path {} is {}", watch_path.display(), status);
+        }
+    }
 }
@@ -0,0 +1,99 @@
+use super::structs::{Health, HealthReport};
+use crate::errors::telemetry_errors::telemetry_error::TelemetryError;
+use hyper::{
+    header::{HeaderValue, CONTENT_TYPE},
+    server::{
+        accept::{self, Accept},
+        conn::AddrIncoming,
+    },
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{info, warn};
+use std::{
+    convert::Infallible, net::SocketAddr, os::unix::fs::FileTypeExt, path::PathBuf, task::Poll,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::UnixListener,
+};
+
+impl Health {
+    /// Serves `/metrics`, `/healthz` and `/readyz` on the provided address until the server fails.
+    pub async fn serve_http(self, address: SocketAddr) -> Result<(), TelemetryError> {
+        let incoming = AddrIncoming::bind(&address)?;
+        info!("serving metrics and health checks at http://{}", incoming.local_addr());
+        self.serve(incoming).await
+    }
+
+    /// Serves the same routes as [`Health::serve_http`] over a unix socket, replacing one left behind by an earlier run.
+    pub async fn serve_socket(self, path: PathBuf) -> Result<(), TelemetryError> {
+        if path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            warn!("removing stale health socket at {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        info!("serving metrics and health checks at {}", path.display());
+        let incoming = accept::poll_fn(move |cx| match listener.poll_accept(cx) {
+            Poll::Ready(accepted) => Poll::Ready(Some(accepted.map(|(stream, _)| stream))),
+            Poll::Pending => Poll::Pending,
+        });
+        self.serve(incoming).await
+    }
+
+    async fn serve<I>(self, incoming: I) -> Result<(), TelemetryError>
+    where
+        I: Accept,
+        I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let make_service = make_service_fn(move |_: &I::Conn| {
+            let health = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = health.respond(request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        Ok(Server::builder(incoming).serve(make_service).await?)
+    }
+
+    fn respond(&self, request: Request<Body>) -> Response<Body> {
+        match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => with_content_type(
+                Response::new(Body::from(self.metrics.render())),
+                "text/plain; version=0.0.4",
+            ),
+            (&Method::GET, "/healthz") => {
+                let report = self.check();
+                Self::report_response(report.live, &report)
+            }
+            (&Method::GET, "/readyz") => {
+                let report = self.check();
+                Self::report_response(report.ready, &report)
+            }
+            _ => {
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                response
+            }
+        }
+    }
+
+    fn report_response(ok: bool, report: &HealthReport) -> Response<Body> {
+        let body = serde_json::to_string_pretty(report).unwrap_or_default();
+        let mut response = with_content_type(Response::new(Body::from(body)), "application/json");
+        if !ok {
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        response
+    }
+}
+
+fn with_content_type(mut response: Response<Body>, content_type: &'static str) -> Response<Body> {
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
@@ -0,0 +1,35 @@
+use super::structs::HealthEndpoint;
+use crate::errors::telemetry_errors::telemetry_error::TelemetryError;
+use hyper::{client::conn, header::HOST, Body, Request, StatusCode};
+use log::debug;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, UnixStream},
+};
+
+/// Asks a running instance whether it's ready, returning the status code and the health report it answered with.
+pub async fn request_readiness(endpoint: &HealthEndpoint) -> Result<(StatusCode, String), TelemetryError> {
+    match endpoint {
+        HealthEndpoint::Http(address) => {
+            get(TcpStream::connect(address).await?, "/readyz", &address.to_string()).await
+        }
+        HealthEndpoint::Socket(path) => get(UnixStream::connect(path).await?, "/readyz", "localhost").await,
+    }
+}
+
+async fn get<S>(stream: S, path: &str, host: &str) -> Result<(StatusCode, String), TelemetryError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("health check connection closed: {}", e);
+        }
+    });
+    let request = Request::get(path).header(HOST, host).body(Body::empty())?;
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
@@ -0,0 +1,54 @@
+use crate::{metrics::Metrics, watcher::types::SharedWatchPathStatuses};
+use serde::Serialize;
+use std::{fmt, net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Works out whether rusty hooks is alive and ready from what its components have recorded in the metrics.
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub metrics: Metrics,
+    pub statuses: SharedWatchPathStatuses,
+    /// How long a channel can go without being received from, while messages wait on it, before it's considered stuck.
+    pub stall_after: Duration,
+}
+
+/// The outcome of every health check, served at `/healthz` and `/readyz`.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// Whether every check needed to be alive passed. A dead instance won't recover without a restart.
+    pub live: bool,
+    /// Whether every check passed, i.e. file system events are being turned into script runs.
+    pub ready: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheck {
+    pub name: String,
+    pub ok: bool,
+    pub required_for: Probe,
+    pub detail: String,
+}
+
+/// Which of the probes a failed check fails. Failing liveness fails readiness too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Probe {
+    Live,
+    Ready,
+}
+
+/// Where a running instance serves its health checks, for `rusty-hooks status` to ask.
+#[derive(Debug, Clone)]
+pub enum HealthEndpoint {
+    Http(SocketAddr),
+    Socket(PathBuf),
+}
+
+impl fmt::Display for HealthEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthEndpoint::Http(address) => write!(f, "http://{}", address),
+            HealthEndpoint::Socket(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+mod builtin;
+mod fields;
+mod init;
+mod journald;
+mod structs;
+mod syslog;
+pub use structs::{LogSink, Logging, LoggingConfig};
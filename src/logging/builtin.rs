@@ -0,0 +1,102 @@
+use super::structs::{JournaldAppender, LogSink, Logging, SyslogAppender, SyslogConfig};
+use crate::errors::logging_errors::logging_error::LoggingError;
+use directories::BaseDirs;
+use log::LevelFilter;
+use log4rs::{
+    append::{
+        console::ConsoleAppender,
+        Append,
+        rolling_file::{
+            policy::compound::{roll::fixed_window::FixedWindowRoller, trigger::size::SizeTrigger, CompoundPolicy},
+            RollingFileAppender,
+        },
+    },
+    config::{Appender, Config, Logger, Root},
+    encode::{json::JsonEncoder, pattern::PatternEncoder},
+};
+use std::path::{Path, PathBuf};
+
+/// What's printed to stdout, one line per record.
+const STDOUT_PATTERN: &str = "level={({l}):5.5} | message=\"{m}\" | src=\"{f}:{L}\" \n";
+/// Size a log file grows to before it's rolled over.
+const ROLL_SIZE_BYTES: u64 = 50 * 1024 * 1024;
+/// Number of rolled over, gzipped, log files kept of each log.
+const ROLLED_FILES: u32 = 5;
+/// Loggers with files of their own, by their targets, and the level each logs at.
+const OWN_FILE_LOGGERS: [(&str, LevelFilter); 2] =
+    [("health_report", LevelFilter::Info), ("script_output", LevelFilter::Debug)];
+
+impl Logging {
+    /// Where the built-in config writes its log files when neither the config file nor --log-config say otherwise.
+    /// Named instances each write their own.
+    pub fn default_directory(instance: Option<&str>) -> Option<PathBuf> {
+        let dir_name = match instance {
+            Some(instance) => format!("logs-{}", instance),
+            None => "logs".to_string(),
+        };
+        BaseDirs::new().map(|dirs| dirs.data_local_dir().join("rusty-hooks").join(dir_name))
+    }
+
+    /// Logs to stdout and, given a directory, to rolling JSON files in it: rusty-hooks.log at `level`, and the health
+    /// report and script output in files of their own, rusty-hooks-health-report.log and rusty-hooks-script-output.log.
+    /// Everything goes to the sinks as well, leaving out stdout when it goes to the journal anyway.
+    pub fn builtin(
+        directory: Option<&Path>,
+        level: LevelFilter,
+        sinks: &[LogSink],
+        syslog: &SyslogConfig,
+    ) -> Result<Config, LoggingError> {
+        let stdout = ConsoleAppender::builder().encoder(Box::new(PatternEncoder::new(STDOUT_PATTERN))).build();
+        let mut config = Config::builder().appender(Appender::builder().build("stdout", Box::new(stdout)));
+        let mut root = Root::builder();
+        if !(sinks.contains(&LogSink::Journald) && JournaldAppender::owns_stdout()) {
+            root = root.appender("stdout");
+        }
+        let mut sink_names = vec![];
+        for sink in sinks {
+            config = config.appender(Appender::builder().build(sink.to_string(), Self::sink(*sink, syslog)?));
+            sink_names.push(sink.to_string());
+        }
+        root = root.appenders(sink_names.clone());
+        if let Some(directory) = directory {
+            config = config.appender(Appender::builder().build("default", Box::new(Self::rolling_file(directory, "rusty-hooks")?)));
+            root = root.appender("default");
+            for (target, logger_level) in OWN_FILE_LOGGERS {
+                let file_stem = format!("rusty-hooks-{}", target.replace('_', "-"));
+                config = config
+                    .appender(Appender::builder().build(target, Box::new(Self::rolling_file(directory, &file_stem)?)))
+                    .logger(
+                        Logger::builder()
+                            .appender(target)
+                            .appenders(sink_names.clone())
+                            .additive(false)
+                            .build(target, logger_level),
+                    );
+            }
+        }
+        Ok(config.build(root.build(level))?)
+    }
+
+    fn sink(sink: LogSink, syslog: &SyslogConfig) -> Result<Box<dyn Append>, LoggingError> {
+        let appender: anyhow::Result<Box<dyn Append>> = match sink {
+            LogSink::Journald => JournaldAppender::connect().map(|appender| Box::new(appender) as _).map_err(Into::into),
+            LogSink::Syslog => SyslogAppender::connect(syslog).map(|appender| Box::new(appender) as _),
+        };
+        appender.map_err(|source| LoggingError::SinkError { sink, source })
+    }
+
+    /// `<file_stem>.log` in the directory, rolled over into `<file_stem>_1.gz` and onwards once it's grown too big.
+    fn rolling_file(directory: &Path, file_stem: &str) -> Result<RollingFileAppender, LoggingError> {
+        let path = directory.join(format!("{}.log", file_stem));
+        let rolled = directory.join(format!("{}_{{}}.gz", file_stem));
+        let roller = FixedWindowRoller::builder()
+            .base(1)
+            .build(&rolled.to_string_lossy(), ROLLED_FILES)
+            .map_err(|source| LoggingError::RollerError { path: directory.to_path_buf(), source })?;
+        let policy = CompoundPolicy::new(Box::new(SizeTrigger::new(ROLL_SIZE_BYTES)), Box::new(roller));
+        RollingFileAppender::builder()
+            .encoder(Box::new(JsonEncoder::new()))
+            .build(&path, Box::new(policy))
+            .map_err(|source| LoggingError::IoError { path, source })
+    }
+}
@@ -0,0 +1,39 @@
+use super::structs::{IsNull, RecordFields};
+use log::{
+    kv::{Error, Key, Value, VisitSource, VisitValue},
+    Record,
+};
+
+impl RecordFields {
+    /// The key-values of a record, in the order they were logged with. Script runs are logged with `script_name`,
+    /// `watch_path`, `path`, `run_id` and, once they've exited, `exit_code`.
+    pub fn of(record: &Record) -> Self {
+        let mut fields = RecordFields::default();
+        // collecting into a vec can't fail
+        let _ = record.key_values().visit(&mut fields);
+        fields
+    }
+}
+
+impl<'kvs> VisitSource<'kvs> for RecordFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        // fields logged as `None`, e.g. the exit code of a script killed by a signal, are left out
+        let mut is_null = IsNull(false);
+        value.visit(&mut is_null)?;
+        if !is_null.0 {
+            self.0.push((key.to_string(), value.to_string()));
+        }
+        Ok(())
+    }
+}
+
+impl<'v> VisitValue<'v> for IsNull {
+    fn visit_any(&mut self, _: Value) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), Error> {
+        self.0 = true;
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use super::structs::{
+    JournaldAppenderDeserializer, LogLevel, Logging, LoggingConfig, SyslogAppenderDeserializer, SyslogConfig,
+    DEFAULT_LOG_LEVEL,
+};
+use crate::errors::logging_errors::logging_error::LoggingError;
+use crate::scripts::structs::Scripts;
+use crate::utilities::cli_args::CommandLineArgs;
+use log::{info, warn, LevelFilter};
+use itertools::Itertools;
+use log4rs::config::{Config, Deserializers};
+use std::path::Path;
+
+impl Logging {
+    /// Sets up the global logger from --log-config, or else the config file's `logging.config`, or else the built-in
+    /// config, see [`Logging::builtin`]. --log-level, or else `logging.level`, sets the level of the root logger,
+    /// whichever config it's from. A config file that can't be read is left to loading to report, with the defaults
+    /// used meanwhile. --log-sink, or else `logging.sinks`, adds sinks to the built-in config, while log4rs config files
+    /// can have `kind: journald` and `kind: syslog` appenders of their own.
+    pub fn init(args: &CommandLineArgs, config_path: Option<&Path>) -> Result<(), LoggingError> {
+        let section = config_path.map(Self::section).unwrap_or_default();
+        let relative_to_config = |path: &str| config_path.and_then(Path::parent).unwrap_or(Path::new(".")).join(path);
+        let level = args.log_level.or(section.level.map(LevelFilter::from));
+        let log_config = args.log_config.clone().or(section.config.as_deref().map(relative_to_config));
+        let directory = section
+            .directory
+            .as_deref()
+            .map(relative_to_config)
+            .or_else(|| Self::default_directory(args.instance.as_deref()));
+        let sinks = match args.log_sink.is_empty() {
+            true => section.sinks.clone(),
+            false => args.log_sink.clone(),
+        };
+        let section_syslog = section.syslog.clone().unwrap_or_default();
+        let syslog = SyslogConfig {
+            socket: args.syslog_socket.clone().or(section_syslog.socket),
+            facility: args.syslog_facility.clone().or(section_syslog.facility),
+        };
+        let config = match &log_config {
+            Some(path) => Self::from_file(path, level)?,
+            None => Self::builtin(directory.as_deref(), level.unwrap_or(DEFAULT_LOG_LEVEL), &sinks, &syslog)?,
+        };
+        log4rs::init_config(config)?;
+        match (&log_config, directory) {
+            (Some(path), _) if !sinks.is_empty() => warn!(
+                "log sinks are only added to the built-in config, add `kind: journald` or `kind: syslog` appenders to {} instead",
+                path.display()
+            ),
+            (Some(path), _) => info!("logging as configured by {}", path.display()),
+            (None, Some(directory)) => info!("logging to {}", directory.display()),
+            (None, None) => warn!("unable to tell where log files go, only logging to stdout"),
+        }
+        if log_config.is_none() && !sinks.is_empty() {
+            info!("logging to {} as well", sinks.iter().join(" and "));
+        }
+        Ok(())
+    }
+
+    /// Loads a log4rs config file, its root logger set to `level` if there is one. It's read once, so its refresh rate
+    /// doesn't apply.
+    fn from_file(path: &Path, level: Option<LevelFilter>) -> Result<Config, LoggingError> {
+        let mut config = log4rs::config::load_config_file(path, Self::deserializers())
+            .map_err(|source| LoggingError::ConfigFileError { path: path.to_path_buf(), source })?;
+        if let Some(level) = level {
+            config.root_mut().set_level(level);
+        }
+        Ok(config)
+    }
+
+    /// log4rs' own appenders, encoders and the like, along with the sinks as `kind: journald` and `kind: syslog`.
+    fn deserializers() -> Deserializers {
+        let mut deserializers = Deserializers::default();
+        deserializers.insert("journald", JournaldAppenderDeserializer);
+        deserializers.insert("syslog", SyslogAppenderDeserializer);
+        deserializers
+    }
+
+    /// The main config file's `logging` section. Included files and fragments don't get a say.
+    fn section(config_path: &Path) -> LoggingConfig {
+        Scripts::read_config_file(config_path)
+            .ok()
+            .and_then(|config| config.logging)
+            .unwrap_or_default()
+    }
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempdir::TempDir;
+
+    #[test]
+    fn builtin_config_logs_to_the_directory_and_log_level_overrides_config_files() {
+        let dir = TempDir::new("logging").unwrap();
+        let logs = dir.path().join("logs");
+        let config = Logging::builtin(Some(&logs), LevelFilter::Warn, &[], &SyslogConfig::default()).unwrap();
+        assert_eq!(config.root().level(), LevelFilter::Warn);
+        assert_eq!(config.root().appenders(), ["stdout", "default"]);
+        assert_eq!(config.loggers().len(), 2);
+        assert!(logs.join("rusty-hooks.log").is_file());
+        assert!(logs.join("rusty-hooks-script-output.log").is_file());
+        assert!(Logging::builtin(None, LevelFilter::Info, &[], &SyslogConfig::default()).unwrap().loggers().is_empty());
+
+        let log_config = dir.path().join("log4rs.yml");
+        fs::write(&log_config, "appenders:\n  stdout:\n    kind: console\nroot:\n  level: debug\n  appenders: [stdout]\n").unwrap();
+        assert_eq!(Logging::from_file(&log_config, None).unwrap().root().level(), LevelFilter::Debug);
+        assert_eq!(Logging::from_file(&log_config, Some(LevelFilter::Error)).unwrap().root().level(), LevelFilter::Error);
+        assert!(matches!(
+            Logging::from_file(&dir.path().join("missing.yml"), None),
+            Err(LoggingError::ConfigFileError { .. })
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+use super::structs::{JournaldAppender, JournaldAppenderDeserializer, JournaldConfig, RecordFields};
+use log::{Level, Record};
+use log4rs::{append::Append, config::{Deserialize, Deserializers}};
+use std::{env, os::unix::net::UnixDatagram};
+
+/// Where journald takes entries over its native protocol.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+impl JournaldAppender {
+    pub fn connect() -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(JournaldAppender { socket })
+    }
+
+    /// Whether stdout already goes to the journal, as it does for systemd services, in which case logging to stdout as
+    /// well would put everything in the journal twice.
+    pub fn owns_stdout() -> bool {
+        let Some((dev, ino)) = env::var("JOURNAL_STREAM").ok().and_then(|stream| {
+            let (dev, ino) = stream.split_once(':')?;
+            Some((dev.parse::<u64>().ok()?, ino.parse::<u64>().ok()?))
+        }) else {
+            return false;
+        };
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(libc::STDOUT_FILENO, &mut stat) } == -1 {
+            return false;
+        }
+        stat.st_dev as u64 == dev && stat.st_ino as u64 == ino
+    }
+
+    /// A record as a journal entry: its message and priority, where it was logged from, and each of its key-values as a
+    /// field of its own, named in upper case, e.g. `script_name` as `SCRIPT_NAME`.
+    fn entry(record: &Record) -> Vec<u8> {
+        let mut entry = vec![];
+        Self::field(&mut entry, "MESSAGE", &record.args().to_string());
+        Self::field(&mut entry, "PRIORITY", Self::priority(record.level()));
+        Self::field(&mut entry, "SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+        Self::field(&mut entry, "TARGET", record.target());
+        if let Some(file) = record.file() {
+            Self::field(&mut entry, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            Self::field(&mut entry, "CODE_LINE", &line.to_string());
+        }
+        for (key, value) in RecordFields::of(record).0 {
+            Self::field(&mut entry, &Self::field_name(&key), &value);
+        }
+        entry
+    }
+
+    /// Adds a field the way the native protocol has it: `KEY=value` on a line of its own, or for values spanning lines,
+    /// the key on a line followed by the value's length as a little-endian u64, the value and a newline.
+    fn field(entry: &mut Vec<u8>, key: &str, value: &str) {
+        entry.extend_from_slice(key.as_bytes());
+        match value.contains('\n') {
+            true => {
+                entry.push(b'\n');
+                entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            }
+            false => entry.push(b'='),
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+
+    /// Journal field names are upper case letters, digits and underscores, and can't start with an underscore, which is
+    /// kept for fields journald adds itself.
+    fn field_name(key: &str) -> String {
+        let name: String = key
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        name.trim_start_matches('_').to_string()
+    }
+
+    /// Whether an entry was too big to be sent as a datagram of its own, which e.g. a script printing a lot can make it.
+    fn too_big(error: &std::io::Error) -> bool {
+        matches!(error.raw_os_error(), Some(libc::EMSGSIZE) | Some(libc::ENOBUFS))
+    }
+
+    /// Hands an entry too big for a datagram over the way sd_journal_send does: written to a memfd, sealed so journald
+    /// can read it knowing it won't change underneath it, and sent as a file descriptor in a datagram otherwise empty.
+    #[cfg(target_os = "linux")]
+    fn send_sealed(&self, entry: &[u8]) -> std::io::Result<()> {
+        use std::{
+            fs::File,
+            io::Write,
+            os::fd::{AsRawFd, FromRawFd},
+        };
+        let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+        let fd = unsafe { libc::memfd_create(c"rusty-hooks-journal".as_ptr(), flags) };
+        if fd == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut memfd = unsafe { File::from_raw_fd(fd) };
+        memfd.write_all(entry)?;
+        let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let fd_len = std::mem::size_of::<libc::c_int>() as libc::c_uint;
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_len) } as usize];
+        let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = control.len() as _;
+        unsafe {
+            let header = libc::CMSG_FIRSTHDR(&message);
+            (*header).cmsg_level = libc::SOL_SOCKET;
+            (*header).cmsg_type = libc::SCM_RIGHTS;
+            (*header).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(header).cast::<libc::c_int>(), memfd.as_raw_fd());
+        }
+        // journald takes its own copy of the descriptor, so this one is closed once sent
+        match unsafe { libc::sendmsg(self.socket.as_raw_fd(), &message, 0) } {
+            -1 => Err(std::io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn send_sealed(&self, _: &[u8]) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "entries too big for a datagram need memfds, which are linux only",
+        ))
+    }
+
+    /// The syslog priority journald files a record under.
+    fn priority(level: Level) -> &'static str {
+        match level {
+            Level::Error => "3",
+            Level::Warn => "4",
+            Level::Info => "6",
+            Level::Debug | Level::Trace => "7",
+        }
+    }
+}
+
+impl Append for JournaldAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let entry = Self::entry(record);
+        match self.socket.send(&entry) {
+            Ok(_) => Ok(()),
+            Err(e) if Self::too_big(&e) => Ok(self.send_sealed(&entry)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl Deserialize for JournaldAppenderDeserializer {
+    type Trait = dyn Append;
+    type Config = JournaldConfig;
+
+    fn deserialize(&self, _: JournaldConfig, _: &Deserializers) -> anyhow::Result<Box<dyn Append>> {
+        Ok(Box::new(JournaldAppender::connect()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_carry_the_fields_of_script_runs() {
+        let fields: [(&str, log::kv::Value); 3] =
+            [("script_name", "backup".into()), ("run_id", 7u64.into()), ("exit_code", 1i32.into())];
+        let entry = JournaldAppender::entry(
+            &Record::builder()
+                .args(format_args!("backup failed\nwith two lines"))
+                .level(Level::Warn)
+                .target("script_output")
+                .key_values(&fields)
+                .build(),
+        );
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&28u64.to_le_bytes());
+        expected.extend_from_slice(b"backup failed\nwith two lines\nPRIORITY=4\nSYSLOG_IDENTIFIER=rusty-hooks\n");
+        expected.extend_from_slice(b"TARGET=script_output\nSCRIPT_NAME=backup\nRUN_ID=7\nEXIT_CODE=1\n");
+        assert_eq!(String::from_utf8_lossy(&entry), String::from_utf8_lossy(&expected));
+        assert_eq!(JournaldAppender::field_name("_watch.path"), "WATCH_PATH");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn entries_too_big_for_a_datagram_are_sent_in_a_sealed_memfd() {
+        use std::{
+            fs::File,
+            io::{Read, Seek},
+            os::fd::{AsRawFd, FromRawFd},
+        };
+        let (sent_from, journald) = UnixDatagram::pair().unwrap();
+        let appender = JournaldAppender { socket: sent_from };
+        let output = "x".repeat(8 << 20);
+        let append = |record: &Record| {
+            appender.append(record).unwrap();
+            JournaldAppender::entry(record)
+        };
+        let sent = append(&Record::builder().args(format_args!("{}", output)).level(Level::Info).build());
+
+        let fd_len = std::mem::size_of::<libc::c_int>() as libc::c_uint;
+        let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fd_len) } as usize];
+        let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = control.len() as _;
+        assert_eq!(unsafe { libc::recvmsg(journald.as_raw_fd(), &mut message, 0) }, 0);
+        let mut memfd = unsafe {
+            let header = libc::CMSG_FIRSTHDR(&message);
+            assert_eq!(((*header).cmsg_level, (*header).cmsg_type), (libc::SOL_SOCKET, libc::SCM_RIGHTS));
+            File::from_raw_fd(std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<libc::c_int>()))
+        };
+        let seals = unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_GET_SEALS) };
+        assert_eq!(seals & libc::F_SEAL_WRITE, libc::F_SEAL_WRITE);
+        // the descriptor shares the offset writing left at the end, which journald reads without, but this needs rewinding
+        let mut entry = vec![];
+        memfd.rewind().unwrap();
+        memfd.read_to_end(&mut entry).unwrap();
+        assert!(entry == sent, "the memfd didn't hold the entry");
+    }
+}
@@ -0,0 +1,104 @@
+use clap::ValueEnum;
+use log::LevelFilter;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fmt, os::unix::net::UnixDatagram, path::PathBuf, sync::Mutex};
+use syslog::{Formatter5424, LoggerBackend};
+
+/// Level of the main log, the health report and script output loggers keeping their own.
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// How rusty-hooks logs. Only the main config file's is read, once, when rusty-hooks starts.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct LoggingConfig {
+    /// Level of the main log, unless --log-level is provided. Defaults to info.
+    pub level: Option<LogLevel>,
+    /// Directory the built-in logging config writes its log files to, relative to this file. Defaults to
+    /// rusty-hooks/logs in the local data directory, e.g. ~/.local/share.
+    pub directory: Option<String>,
+    /// log4rs config file to log with instead of the built-in one, relative to this file. --log-config takes precedence.
+    pub config: Option<String>,
+    /// Where else the built-in config logs to, besides stdout and the log files. --log-sink takes precedence.
+    #[serde(default)]
+    pub sinks: Vec<LogSink>,
+    /// How to log to syslog, with the `syslog` sink.
+    pub syslog: Option<SyslogConfig>,
+}
+
+/// Log sinks rusty-hooks has built in, to send logs to without tailing the log files.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    /// the systemd journal, with the fields of script runs, e.g. SCRIPT_NAME and EXIT_CODE, as journal fields
+    Journald,
+    /// the local syslog daemon, as RFC 5424 messages with the fields of script runs as structured data
+    Syslog,
+}
+
+/// Where the `syslog` sink sends to, and as what. Also the config of `kind: syslog` appenders in log4rs config files.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SyslogConfig {
+    /// Unix socket the syslog daemon listens on. Defaults to the first of /dev/log, /var/run/syslog and /var/run/log
+    /// that exists.
+    pub socket: Option<PathBuf>,
+    /// Facility to log as, e.g. daemon, user or local0 through local7. Defaults to daemon.
+    pub facility: Option<String>,
+}
+
+/// The config of `kind: journald` appenders in log4rs config files, which there's nothing to configure about.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct JournaldConfig {}
+
+/// Levels a config file's `logging.level` can name.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Sets up the global logger, see [`Logging::init`].
+#[derive(Debug)]
+pub struct Logging;
+
+/// Sends log records to the systemd journal over its native protocol, see [`JournaldAppender::entry`].
+#[derive(Debug)]
+pub struct JournaldAppender {
+    pub socket: UnixDatagram,
+}
+
+/// Sends log records to the local syslog daemon as RFC 5424 messages.
+pub struct SyslogAppender {
+    pub logger: Mutex<syslog::Logger<LoggerBackend, Formatter5424>>,
+}
+
+/// Creates `kind: journald` appenders from log4rs config files.
+#[derive(Debug)]
+pub struct JournaldAppenderDeserializer;
+
+/// Creates `kind: syslog` appenders from log4rs config files.
+#[derive(Debug)]
+pub struct SyslogAppenderDeserializer;
+
+/// The key-values a record was logged with, e.g. `script_name`, as strings.
+#[derive(Debug, Default)]
+pub struct RecordFields(pub Vec<(String, String)>);
+
+/// Whether a key-value's value is null, see [`RecordFields::of`].
+#[derive(Debug)]
+pub struct IsNull(pub bool);
+
+impl fmt::Display for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogSink::Journald => write!(f, "journald"),
+            LogSink::Syslog => write!(f, "syslog"),
+        }
+    }
+}
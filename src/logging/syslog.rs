@@ -0,0 +1,108 @@
+use super::structs::{RecordFields, SyslogAppender, SyslogAppenderDeserializer, SyslogConfig};
+use ::syslog::{Facility, Formatter5424};
+use anyhow::anyhow;
+use log::{Level, Record};
+use log4rs::{append::Append, config::{Deserialize, Deserializers}};
+use std::{collections::HashMap, fmt, str::FromStr, sync::Mutex};
+
+/// SD-ID of the structured data element a record's key-values are sent in. Private SD-IDs are named after an IANA
+/// enterprise number, this being the one set aside for documentation and examples.
+const STRUCTURED_DATA_ID: &str = "rusty-hooks@32473";
+/// MSGID of every message, the syslog crate only taking numbers.
+const MESSAGE_ID: u32 = 0;
+
+impl SyslogAppender {
+    /// Connects to the syslog daemon's socket, or the first of the usual sockets there is.
+    pub fn connect(config: &SyslogConfig) -> anyhow::Result<Self> {
+        let facility = match &config.facility {
+            Some(name) => Facility::from_str(name).map_err(|_| anyhow!("there's no syslog facility {}", name))?,
+            None => Facility::LOG_DAEMON,
+        };
+        let formatter = Formatter5424 { facility, ..Default::default() };
+        let logger = match &config.socket {
+            Some(socket) => ::syslog::unix_custom(formatter, socket),
+            None => ::syslog::unix(formatter),
+        };
+        // the syslog crate's errors aren't Sync, so can't be kept as they are
+        let logger = logger.map_err(|e| anyhow!("unable to connect to syslog: {}", e))?;
+        Ok(SyslogAppender { logger: Mutex::new(logger) })
+    }
+
+    /// A record's target and key-values, as the parameters of a single structured data element, keyed by its SD-ID.
+    fn structured_data(record: &Record) -> HashMap<String, HashMap<String, String>> {
+        let mut params: HashMap<String, String> = RecordFields::of(record)
+            .0
+            .into_iter()
+            .map(|(key, value)| (key, Self::escape(&value)))
+            .collect();
+        params.insert("target".to_string(), Self::escape(record.target()));
+        HashMap::from([(STRUCTURED_DATA_ID.to_string(), params)])
+    }
+
+    /// Escapes the characters RFC 5424 doesn't allow unescaped in parameter values, which the syslog crate leaves as is.
+    fn escape(value: &str) -> String {
+        value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+    }
+}
+
+impl Append for SyslogAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let message = (MESSAGE_ID, Self::structured_data(record), record.args());
+        let mut logger = self.logger.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let sent = match record.level() {
+            Level::Error => logger.err(message),
+            Level::Warn => logger.warning(message),
+            Level::Info => logger.info(message),
+            Level::Debug | Level::Trace => logger.debug(message),
+        };
+        sent.map_err(|e| anyhow!("unable to send to syslog: {}", e))
+    }
+
+    fn flush(&self) {}
+}
+
+impl fmt::Debug for SyslogAppender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyslogAppender").finish_non_exhaustive()
+    }
+}
+
+impl Deserialize for SyslogAppenderDeserializer {
+    type Trait = dyn Append;
+    type Config = SyslogConfig;
+
+    fn deserialize(&self, config: SyslogConfig, _: &Deserializers) -> anyhow::Result<Box<dyn Append>> {
+        Ok(Box::new(SyslogAppender::connect(&config)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+    use tempdir::TempDir;
+
+    #[test]
+    fn sends_rfc5424_messages_with_the_fields_as_structured_data() {
+        let dir = TempDir::new("syslog").unwrap();
+        let socket = dir.path().join("log");
+        let daemon = UnixDatagram::bind(&socket).unwrap();
+        let config = SyslogConfig { socket: Some(socket), facility: Some("local3".to_string()) };
+        let appender = SyslogAppender::connect(&config).unwrap();
+
+        let fields: [(&str, log::kv::Value); 2] = [("script_name", "say \"hi\"".into()), ("exit_code", 0i32.into())];
+        appender
+            .append(&Record::builder().args(format_args!("ran")).level(Level::Warn).target("script_output").key_values(&fields).build())
+            .unwrap();
+        let mut received = [0; 1024];
+        let len = daemon.recv(&mut received).unwrap();
+        let received = String::from_utf8_lossy(&received[..len]).to_string();
+        // local3 is facility 19, warning severity 4
+        assert!(received.starts_with("<156>1 "), "{}", received);
+        assert!(received.ends_with(" ran"), "{}", received);
+        for param in ["[rusty-hooks@32473 ", "script_name=\"say \\\"hi\\\"\"", "exit_code=\"0\"", "target=\"script_output\""] {
+            assert!(received.contains(param), "{} isn't in {}", param, received);
+        }
+        assert!(SyslogAppender::connect(&SyslogConfig { facility: Some("nope".to_string()), ..config }).is_err());
+    }
+}
@@ -1,137 +1,483 @@
 #![feature(io_error_more)]
+mod control;
+mod daemon;
 mod errors;
 mod health_reporter;
+mod logging;
+mod metrics;
 mod runner;
 mod scripts;
+mod shutdown;
+mod systemd;
+mod telemetry;
 mod utilities;
 mod watcher;
 
 use clap::Parser;
+use control::{ControlRequest, ControlResponse, ControlServer, RunId, Runs};
+use daemon::{Daemon, StopOutcome};
+use errors::lockfile_errors::lockfile_error::LockfileError;
 use errors::watcher_errors::watcher_error::WatcherError;
-use futures::future::try_join_all;
-use log::{debug, error, info};
+use health_reporter::{Health, HealthReporter};
+use futures::future::{FusedFuture, FutureExt};
+use log::{debug, error, info, warn};
+use logging::Logging;
+use metrics::Metrics;
 use runner::structs::Runner;
-use scripts::structs::Scripts;
-use std::path::{Path, PathBuf};
+use scripts::structs::{ScriptConfig, Scripts, ValidationReport};
+use shutdown::{Shutdown, ShutdownSignals, UnfinishedRuns, EXIT_SCRIPTS_KILLED, EXIT_TASK_FAILED};
+use std::{
+    path::Path,
+    sync::{Arc, RwLock},
+};
+use systemd::{SystemdNotifier, UnitOptions};
+use telemetry::Telemetry;
 use utilities::{
-    cli_args::CommandLineArgs,
+    cli_args::{Command, CommandLineArgs},
+    lag_counter::LagCounter,
     thread_types::{SpawnSender, UnsubscribeSender},
 };
-use watcher::structs::Watcher;
+use watcher::{structs::Watcher, types::SharedScripts};
 
 use crate::utilities::set_process_lockfile::Lockfile;
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let args = CommandLineArgs::parse();
-    log4rs::init_file("log4rs.yml", Default::default()).unwrap();
+    // forking has to happen before the runtime starts any threads
+    let daemon = match &args.command {
+        Some(Command::Stop { timeout }) => std::process::exit(stop(&args, *timeout)),
+        Some(Command::Restart { timeout }) => match stop(&args, *timeout) {
+            0 => Some(daemonize(&args)),
+            exit_code => std::process::exit(exit_code),
+        },
+        None if args.daemon => Some(daemonize(&args)),
+        _ => None,
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap(); // nothing runs without it
+    runtime.block_on(run(args, daemon));
+}
+
+/// Runs whichever subcommand was asked for, or else watches. `daemon` is set when running in the background, to tell the
+/// process that started it once it's up.
+async fn run(args: CommandLineArgs, mut daemon: Option<Daemon>) {
+    match &args.command {
+        Some(Command::Schema) => {
+            println!("{}", ScriptConfig::schema_json());
+            return;
+        }
+        Some(Command::Validate { json }) => std::process::exit(validate(&args, *json)),
+        Some(Command::Status) => std::process::exit(status(&args).await),
+        Some(Command::SystemdUnit { user }) => std::process::exit(systemd_unit(&args, *user)),
+        Some(Command::List { json }) => std::process::exit(control(&args, ControlRequest::List, *json).await),
+        Some(Command::Pause(target)) => {
+            std::process::exit(control(&args, ControlRequest::Pause { target: target.target() }, false).await)
+        }
+        Some(Command::Resume(target)) => {
+            std::process::exit(control(&args, ControlRequest::Resume { target: target.target() }, false).await)
+        }
+        Some(Command::Trigger { script, path }) => {
+            // resolved here, as the running instance may well have a different working directory
+            let path = utilities::path_key::PathKey::new(path).into_path_buf();
+            let request = ControlRequest::Trigger { script: script.clone(), path };
+            std::process::exit(control(&args, request, false).await)
+        }
+        Some(Command::Flush) => std::process::exit(control(&args, ControlRequest::Flush, false).await),
+        Some(Command::Cancel { run_id }) => {
+            let request = ControlRequest::Cancel { run_id: RunId(*run_id) };
+            std::process::exit(control(&args, request, false).await)
+        }
+        // stopping is done by now, and restarting carries on into watching
+        Some(Command::Stop { .. }) | Some(Command::Restart { .. }) | None => {}
+    }
+    // found first, as the config file can say how to log
+    let config_path = args.get_config_path();
+    if let Err(e) = Logging::init(&args, config_path.as_deref().ok()) {
+        eprintln!("{}", e);
+        report_failure(&mut daemon, &e.to_string());
+        std::process::exit(1);
+    }
     info!("starting rusty hooks....");
-    let config_path = match args.get_config_path() {
+    if let Err(e) = Telemetry::init(&args, config_path.as_deref().ok()) {
+        // traces are nice to have, not a reason to stop watching
+        error!("{}", e);
+    }
+    let config_path = match config_path {
         Ok(c) => c,
         Err(e) => {
             debug!("{}", e.to_string());
+            report_failure(&mut daemon, &e.to_string());
             panic!()
         }
     };
     let config_path_clone = config_path.as_path();
 
-    if let Err(e) = Lockfile::set(None, None) {
-        debug!("{}", e.to_string());
-        error!("unable to get a lock on the pid file. this is done to ensure only one instance is running at a time");
-        panic!()
+    // taken before anything else is set up, so a second instance doesn't get as far as touching the control socket
+    let lockfile = match args.pid_file().ok_or(LockfileError::NoDefaultPath).and_then(|path| Lockfile::set(&path)) {
+        Ok(lockfile) => lockfile,
+        Err(e) => {
+            error!("{}", e);
+            eprintln!("{}", e);
+            report_failure(&mut daemon, &e.to_string());
+            std::process::exit(1);
+        }
     };
+    let mut signals = ShutdownSignals::listen().unwrap(); // without them there's no stopping cleanly
+    let shutdown = Shutdown::new(args.shutdown_grace_period(), args.flush_on_shutdown);
+    let unfinished_file = args.unfinished_file();
 
-    let runner = Runner::new().unwrap(); // if we cant get a runner up we should panic
+    let systemd = SystemdNotifier::from_env();
+    let capacities = args.channel_capacities();
+    let lag_counter = LagCounter::new();
+    let metrics = Metrics::new(lag_counter.clone());
+
+    let runs = Runs::new();
+    let runner = Runner::new(capacities, lag_counter.clone(), metrics.clone(), shutdown.clone(), runs.clone()).unwrap(); // if we cant get a runner up we should panic
     let script_task_spawn_channel = runner.spawn_channel.0.clone();
     let unsub_from_folder_channel = runner.unsubscribe_broadcast_channel.0.clone();
 
-    let runner_task = runner.init();
-
-    let watch_paths = match Scripts::all_watch_paths(&config_path) {
-        Ok(p) => p,
+    let scripts_by_watch_path = match Scripts::load_all(&config_path) {
+        Ok(s) => s,
         Err(e) => {
             error!("{}", e.to_string());
+            // loading stops at the first problem, validating lists all of them
+            for problem in ValidationReport::for_config(&config_path).problems {
+                error!("{}", problem);
+            }
+            report_failure(&mut daemon, &e.to_string());
             panic!()
         }
     };
 
-    let watchers: Vec<_> = watch_paths
-        .iter()
-        .map(|watch_path| {
-            initialize_watchers(
-                watch_path,
-                config_path_clone,
-                script_task_spawn_channel.clone(),
-                unsub_from_folder_channel.clone(),
-            )
-        })
-        .collect();
+    let resumed = match &unfinished_file {
+        Some(path) => UnfinishedRuns::resume(path, &scripts_by_watch_path).unwrap_or_else(|e| {
+            error!("unable to resume script runs left unfinished by the last shutdown: {}", e);
+            vec![]
+        }),
+        None => vec![],
+    };
+    let mut runner_task = Box::pin(runner.init(resumed).fuse());
+
+    let watcher = match Watcher::new(
+        capacities,
+        lag_counter.clone(),
+        metrics.clone(),
+        args.watch_path_poll_interval(),
+        shutdown.clone(),
+        runs.clone(),
+    ) {
+        Ok(w) => w,
+        Err(e) => {
+            error!("{}", e.to_string());
+            panic!()
+        }
+    };
 
-    let awaited_watchers = try_join_all(watchers);
+    let scripts_by_watch_path: SharedScripts = Arc::new(RwLock::new(scripts_by_watch_path));
+    match args.control_socket() {
+        Some(socket) => {
+            let control_server = ControlServer {
+                runs,
+                scripts: scripts_by_watch_path.clone(),
+                statuses: watcher.statuses.clone(),
+                spawn_channel: script_task_spawn_channel.clone(),
+            };
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if let Err(e) = control_server.serve(socket, shutdown).await {
+                    error!("control socket stopped taking requests: {}", e);
+                }
+            });
+        }
+        None => warn!("nowhere to put the control socket, provide --control-socket to be able to control rusty hooks"),
+    }
 
-    let health_reporter = health_reporter::HealthReporter::new().unwrap();
-    let report_task = health_reporter.begin_reporting();
+    let mut awaited_watchers = Box::pin(
+        initialize_watcher(
+            &watcher,
+            scripts_by_watch_path,
+            config_path_clone,
+            script_task_spawn_channel,
+            unsub_from_folder_channel,
+        )
+        .fuse(),
+    );
 
-    utilities::set_process_lockfile::Lockfile::set(None, None).unwrap();
+    let health = Health::new(metrics, watcher.statuses.clone(), args.queue_stall_timeout());
+    let health_reporter = HealthReporter::new(lag_counter, health.clone()).unwrap();
+    let mut report_task = health_reporter.begin_reporting();
+    health_reporter.serve(args.http_address, args.health_socket.clone());
+    health_reporter.notify_systemd(systemd.clone());
 
-    tokio::select! {
-        b = awaited_watchers => {
-            match b {
-                Ok(_) => {
-                    info!("event watcher tasks exited, cleaning up other tasks and exiting")
-                },
-                Err(e) => {
-                    error!("event watchers failed: {}", e);
-                    info!("cleaning up other tasks and exiting");
+    let mut exit_code = loop {
+        tokio::select! {
+            // the process that started the daemon only hears it's up once every watch path that could be is attached
+            _ = health.until_started(), if daemon.is_some() => {
+                if let Some(daemon) = daemon.take() {
+                    daemon.ready();
                 }
-            }
-        },
-        b = report_task => {
-            match b {
-                Ok(_) => {
-                    info!("health report task exited.")
-                },
-                Err(e) => {
-                    error!("health report task exited with an error: {}", e);
+            },
+            signal = signals.recv() => {
+                info!("received {}, shutting down", signal);
+                break 0;
+            },
+            b = &mut awaited_watchers => {
+                match b {
+                    Ok(_) => {
+                        info!("event watcher tasks exited, cleaning up other tasks and exiting")
+                    },
+                    Err(e) => {
+                        error!("event watchers failed: {}", e);
+                        report_failure(&mut daemon, &format!("event watchers failed: {}", e));
+                        info!("cleaning up other tasks and exiting");
+                    }
                 }
-            }
-        },
-        a = runner_task => {
-            match a {
-                Ok(_) => {
-                    info!("runner task exited, cleaning up other tasks and exiting")
-                },
-                Err(e) => {
-                    error!("runner task failed: {}", e);
-                    info!("cleaning up other tasks and exiting");
+                break EXIT_TASK_FAILED;
+            },
+            b = &mut report_task => {
+                match b {
+                    Ok(_) => {
+                        info!("health report task exited.")
+                    },
+                    Err(e) => {
+                        error!("health report task exited with an error: {}", e);
+                    }
+                }
+                break EXIT_TASK_FAILED;
+            },
+            a = &mut runner_task => {
+                match a {
+                    Ok(_) => {
+                        info!("runner task exited, cleaning up other tasks and exiting")
+                    },
+                    Err(e) => {
+                        error!("runner task failed: {}", e);
+                        info!("cleaning up other tasks and exiting");
+                    }
                 }
+                break EXIT_TASK_FAILED;
             }
         }
+    };
+    report_failure(&mut daemon, "rusty hooks stopped before it was up");
+
+    // however it ended, whatever's still running is asked to wrap up
+    shutdown.request();
+    systemd.stopping();
+    let watchers_stopped = async {
+        match awaited_watchers.is_terminated() {
+            true => Ok(()),
+            false => (&mut awaited_watchers).await,
+        }
+    };
+    let runner_stopped = async {
+        match runner_task.is_terminated() {
+            true => Ok(()),
+            false => (&mut runner_task).await,
+        }
+    };
+    tokio::select! {
+        (watched, ran) = async {
+            let stopped = tokio::join!(watchers_stopped, runner_stopped);
+            // paths still debouncing are set aside by wait tasks of their own, which aren't among those awaited
+            shutdown.debounced().await;
+            stopped
+        } => {
+            if let Err(e) = watched {
+                error!("event watchers failed while shutting down: {}", e);
+                exit_code = EXIT_TASK_FAILED;
+            }
+            if let Err(e) = ran {
+                error!("runner task failed while shutting down: {}", e);
+                exit_code = EXIT_TASK_FAILED;
+            }
+        },
+        signal = signals.recv() => {
+            warn!("received {} again, exiting without waiting on running scripts", signal);
+            shutdown.scripts_killed();
+        },
+    }
+    if shutdown.were_scripts_killed() && exit_code == 0 {
+        exit_code = EXIT_SCRIPTS_KILLED;
     }
+
+    let unfinished = UnfinishedRuns::from_set_aside(shutdown.take_set_aside());
+    match (&unfinished_file, unfinished.is_empty()) {
+        (_, true) => {}
+        (Some(path), false) => match unfinished.save(path) {
+            Ok(_) => info!("saved {} unfinished script runs to {}, they'll be picked up on the next start", unfinished.runs.len(), path.display()),
+            Err(e) => error!("unable to save unfinished script runs to {}: {}", path.display(), e),
+        },
+        (None, false) => error!("nowhere to save {} unfinished script runs, they won't be run", unfinished.runs.len()),
+    }
+    if let Err(e) = lockfile.release() {
+        error!("unable to release the pid file: {}", e);
+    }
+    Telemetry::shutdown();
+
+    // the other runtimes can't be dropped from within this one, and anything left on them is done with
+    drop(awaited_watchers);
+    drop(runner_task);
+    runner.runtime.shutdown_background();
+    watcher.runtime.shutdown_background();
+    health_reporter.runtime.shutdown_background();
+    info!("rusty hooks stopped");
+    std::process::exit(exit_code);
 }
 
-async fn initialize_watchers(
-    watch_path: &PathBuf,
+async fn initialize_watcher(
+    watcher: &Watcher,
+    scripts_by_watch_path: SharedScripts,
     scripts_config_path: &Path,
     spawn_channel: SpawnSender,
     unsubscribe_channel: UnsubscribeSender,
 ) -> Result<(), WatcherError> {
-    let watcher_scripts = match Scripts::by_watch_path(watch_path, scripts_config_path) {
-        Ok(s) => s,
-        Err(e) => {
-            error!("error loading configs: {}", e);
-            panic!()
-        }
-    };
-
-    let watcher = Watcher::new()?;
-
     Ok(watcher
         .start(
             spawn_channel,
             unsubscribe_channel,
-            watch_path.clone(),
-            &watcher_scripts,
+            scripts_config_path.to_path_buf(),
+            scripts_by_watch_path,
         )
         .await?)
 }
+
+/// Runs `rusty-hooks validate`, returning the exit code: 0 if the config would load, 1 if it wouldn't, and 2 if there's
+/// no config to validate in the first place.
+fn validate(args: &CommandLineArgs, json: bool) -> i32 {
+    let config_path = match args.get_config_path() {
+        Ok(config_path) => config_path,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    let report = ValidationReport::for_config(&config_path);
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default()),
+        false => println!("{}", report),
+    }
+    match report.is_valid() {
+        true => 0,
+        false => 1,
+    }
+}
+
+/// Runs `rusty-hooks status`, returning the exit code: 0 if the running instance is ready, 1 if it isn't, and 2 if it
+/// couldn't be asked.
+async fn status(args: &CommandLineArgs) -> i32 {
+    let endpoint = match args.health_endpoint() {
+        Some(endpoint) => endpoint,
+        None => {
+            eprintln!("neither --health-socket nor --http-address was provided, so there's nowhere to ask");
+            return 2;
+        }
+    };
+    match health_reporter::request_readiness(&endpoint).await {
+        Ok((status, report)) => {
+            println!("{}", report);
+            match status.is_success() {
+                true => 0,
+                false => 1,
+            }
+        }
+        Err(e) => {
+            eprintln!("unable to reach rusty hooks at {}: {}", endpoint, e);
+            2
+        }
+    }
+}
+
+/// Runs `rusty-hooks systemd-unit`, returning the exit code: 0 once the unit's printed, 2 if there's no config for it.
+fn systemd_unit(args: &CommandLineArgs, user_unit: bool) -> i32 {
+    match UnitOptions::new(args, user_unit) {
+        Ok(options) => {
+            print!("{}", options.render());
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    }
+}
+
+/// Sends a request over the control socket for `rusty-hooks list`, `pause`, `resume`, `trigger`, `flush` and `cancel`,
+/// returning the exit code: 0 if it was carried out, 1 if the running instance refused it, and 2 if it couldn't be asked.
+async fn control(args: &CommandLineArgs, request: ControlRequest, json: bool) -> i32 {
+    let socket = match args.control_socket() {
+        Some(socket) => socket,
+        None => {
+            eprintln!("unable to tell where the control socket is, provide --control-socket");
+            return 2;
+        }
+    };
+    match control::request(&socket, &request).await {
+        Ok(ControlResponse::Ok { message }) => {
+            println!("{}", message);
+            0
+        }
+        Ok(ControlResponse::Listing(listing)) => {
+            match json {
+                true => println!("{}", serde_json::to_string_pretty(&listing).unwrap_or_default()),
+                false => print!("{}", listing),
+            }
+            0
+        }
+        Ok(ControlResponse::Error { message }) => {
+            eprintln!("{}", message);
+            1
+        }
+        Err(e) => {
+            eprintln!("unable to reach rusty hooks at {}: {}", socket.display(), e);
+            2
+        }
+    }
+}
+
+/// Forks into the background for --daemon and `restart`, see [`Daemon::start`]. Only the daemon returns.
+fn daemonize(args: &CommandLineArgs) -> Daemon {
+    match Daemon::start(args.daemon_output.as_deref()) {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            eprintln!("unable to start in the background: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Passes on why rusty hooks isn't coming up to the process that started it in the background, if it was.
+fn report_failure(daemon: &mut Option<Daemon>, message: &str) {
+    if let Some(daemon) = daemon.take() {
+        daemon.failed(message);
+    }
+}
+
+/// Runs `rusty-hooks stop`, and the stopping half of `restart`, returning the exit code: 0 if the instance stopped, or
+/// wasn't running, and 1 if it couldn't be stopped.
+fn stop(args: &CommandLineArgs, timeout: Option<u64>) -> i32 {
+    let Some(pid_file) = args.pid_file() else {
+        eprintln!("{}", LockfileError::NoDefaultPath);
+        return 1;
+    };
+    match Daemon::stop(&pid_file, args.stop_timeout(timeout)) {
+        Ok(StopOutcome::Stopped(pid)) => {
+            println!("stopped rusty hooks, which was running as pid {}", pid);
+            0
+        }
+        Ok(StopOutcome::NotRunning) => {
+            println!("rusty hooks isn't running, nothing holds the lock on {}", pid_file.display());
+            0
+        }
+        Ok(StopOutcome::TimedOut(pid)) => {
+            eprintln!("rusty hooks is still running as pid {}, gave up waiting for it to stop", pid);
+            1
+        }
+        Err(e) => {
+            eprintln!("unable to stop rusty hooks: {}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,4 @@
+mod record;
+mod render;
+mod structs;
+pub use structs::Metrics;
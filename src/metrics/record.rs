@@ -0,0 +1,198 @@
+use super::structs::{Histogram, Labels, Metrics, QueueProbe, Samples, ScriptRunning};
+use crate::utilities::lag_counter::LagCounter;
+use crate::utilities::thread_types::BroadcastReceiver;
+use notify::EventKind;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+pub const EVENTS_RECEIVED: &str = "rusty_hooks_events_received_total";
+pub const EVENTS_IGNORED: &str = "rusty_hooks_events_ignored_total";
+pub const ACTIVE_SUBSCRIPTIONS: &str = "rusty_hooks_active_subscriptions";
+pub const TIMER_RESETS: &str = "rusty_hooks_timer_resets_total";
+pub const NOTIFY_ERRORS: &str = "rusty_hooks_notify_errors_total";
+pub const SCRIPT_RUNS: &str = "rusty_hooks_script_runs_total";
+pub const SCRIPT_DURATION: &str = "rusty_hooks_script_duration_seconds";
+pub const SCRIPTS_RUNNING: &str = "rusty_hooks_scripts_running";
+pub const QUEUE_DEPTH: &str = "rusty_hooks_queue_depth";
+pub const DROPPED_MESSAGES: &str = "rusty_hooks_dropped_messages_total";
+pub const TASKS_UP: &str = "rusty_hooks_task_up";
+pub const UPTIME: &str = "rusty_hooks_uptime_seconds";
+
+/// Name, type and help text of every metric, in the order they're rendered in.
+pub const DESCRIPTIONS: [(&str, &str, &str); 12] = [
+    (EVENTS_RECEIVED, "counter", "File system events received, by kind and the watch path they were routed to."),
+    (EVENTS_IGNORED, "counter", "File system events that subscribed to nothing, by reason."),
+    (ACTIVE_SUBSCRIPTIONS, "gauge", "Paths currently waiting out their timer, or having their scripts run."),
+    (TIMER_RESETS, "counter", "Times a subscribed path's timer was reset by another event at it."),
+    (NOTIFY_ERRORS, "counter", "Errors reported by the file system watcher instead of events."),
+    (SCRIPT_RUNS, "counter", "Script runs, by script name and outcome."),
+    (SCRIPT_DURATION, "histogram", "How long scripts took to run, by script name. Run delays aren't included."),
+    (SCRIPTS_RUNNING, "gauge", "Scripts currently running, past their run delay."),
+    (QUEUE_DEPTH, "gauge", "Messages waiting on a channel, as of the last one received from it."),
+    (DROPPED_MESSAGES, "counter", "Messages dropped because a channel's receiver fell behind, by channel."),
+    (TASKS_UP, "gauge", "Whether each long running task is running, 1, or has exited, 0."),
+    (UPTIME, "gauge", "Seconds since rusty-hooks started."),
+];
+
+/// Upper bounds, in seconds, of the buckets script run durations are counted into.
+pub const DURATION_BUCKETS: [f64; 12] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+impl Metrics {
+    pub fn new(lag_counter: LagCounter) -> Self {
+        Metrics {
+            samples: Arc::new(Mutex::new(Samples::default())),
+            lag_counter,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn event_received(&self, kind: &EventKind, watch_path: &Path) {
+        let labels = vec![
+            ("kind", Self::kind_label(kind).to_string()),
+            ("watch_path", watch_path.display().to_string()),
+        ];
+        self.increment(EVENTS_RECEIVED, labels);
+    }
+
+    pub fn event_ignored(&self, reason: &'static str) {
+        self.increment(EVENTS_IGNORED, vec![("reason", reason.to_string())]);
+    }
+
+    pub fn set_active_subscriptions(&self, num_subscriptions: usize) {
+        self.set(ACTIVE_SUBSCRIPTIONS, vec![], num_subscriptions as i64);
+    }
+
+    pub fn timer_reset(&self) {
+        self.increment(TIMER_RESETS, vec![]);
+    }
+
+    pub fn script_started(&self) -> ScriptRunning {
+        self.adjust(SCRIPTS_RUNNING, vec![], 1);
+        ScriptRunning(self.clone())
+    }
+
+    /// Records a finished script run. `outcome` is `success`, `failure` for a non-zero exit, or `error` if the script
+    /// couldn't be run at all.
+    pub fn script_ran(&self, running: ScriptRunning, script_name: &str, outcome: &'static str, duration: Duration) {
+        drop(running);
+        let name = ("script", script_name.to_string());
+        self.increment(SCRIPT_RUNS, vec![name.clone(), ("outcome", outcome.to_string())]);
+        self.observe(SCRIPT_DURATION, vec![name], duration.as_secs_f64());
+    }
+
+    pub fn notify_error(&self) {
+        self.increment(NOTIFY_ERRORS, vec![]);
+        self.samples().last_notify_error = Some(Instant::now());
+    }
+
+    /// Records a message having been received from a channel, with how many more are waiting on it.
+    pub fn set_queue_depth(&self, channel: &'static str, depth: usize) {
+        self.set(QUEUE_DEPTH, vec![("channel", channel.to_string())], depth as i64);
+        let mut samples = self.samples();
+        let counted = samples.probes.get(channel).map_or(0, |probe| (probe.0)());
+        samples.received.insert(channel, (depth, counted, Instant::now()));
+    }
+
+    /// Probes the channel the receiver is on, so that messages sent after the last one received count as waiting even
+    /// if its consumer never comes back to receive them, e.g. because it's stuck. See [`Metrics::queue_activity`].
+    pub fn track_queue<T: Clone + Send + 'static>(&self, channel: &'static str, receiver: &BroadcastReceiver<T>) {
+        let probe = receiver.resubscribe();
+        let mut samples = self.samples();
+        samples.probes.insert(channel, QueueProbe(Box::new(move || probe.len())));
+        samples.received.entry(channel).or_insert((receiver.len(), 0, Instant::now()));
+    }
+
+    pub fn task_started(&self, task: &'static str) {
+        self.set(TASKS_UP, vec![("task", task.to_string())], 1);
+    }
+
+    pub fn task_stopped(&self, task: &'static str) {
+        self.set(TASKS_UP, vec![("task", task.to_string())], 0);
+    }
+
+    /// Whether a task is running, `None` if it hasn't been started yet.
+    pub fn task_up(&self, task: &'static str) -> Option<bool> {
+        let labels = vec![("task", task.to_string())];
+        self.samples().gauges.get(&(TASKS_UP, labels)).map(|up| *up == 1)
+    }
+
+    /// Every channel tracked or received from so far, with how many messages are waiting on it and when it was last
+    /// received from. Those waiting are the ones left at the last receive, plus any its probe has counted since.
+    pub fn queue_activity(&self) -> Vec<(&'static str, usize, Instant)> {
+        let samples = self.samples();
+        samples
+            .received
+            .iter()
+            .map(|(channel, (depth, counted, received_at))| {
+                let sent_since = samples
+                    .probes
+                    .get(channel)
+                    .map_or(0, |probe| (probe.0)().saturating_sub(*counted));
+                (*channel, depth + sent_since, *received_at)
+            })
+            .collect()
+    }
+
+    pub fn last_notify_error(&self) -> Option<Instant> {
+        self.samples().last_notify_error
+    }
+
+    pub fn scripts_running(&self) -> i64 {
+        self.samples().gauges.get(&(SCRIPTS_RUNNING, vec![])).copied().unwrap_or(0)
+    }
+
+    /// The lock is only ever held to update or copy a few numbers, so a poisoned one still holds sensible values.
+    pub(super) fn samples(&self) -> MutexGuard<'_, Samples> {
+        match self.samples.lock() {
+            Ok(samples) => samples,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn increment(&self, name: &'static str, labels: Labels) {
+        *self.samples().counters.entry((name, labels)).or_insert(0) += 1;
+    }
+
+    fn set(&self, name: &'static str, labels: Labels, value: i64) {
+        self.samples().gauges.insert((name, labels), value);
+    }
+
+    fn adjust(&self, name: &'static str, labels: Labels, by: i64) {
+        *self.samples().gauges.entry((name, labels)).or_insert(0) += by;
+    }
+
+    fn observe(&self, name: &'static str, labels: Labels, value: f64) {
+        let mut samples = self.samples();
+        let histogram = samples.histograms.entry((name, labels)).or_insert_with(|| Histogram {
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            ..Default::default()
+        });
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    fn kind_label(kind: &EventKind) -> &'static str {
+        match kind {
+            EventKind::Any => "any",
+            EventKind::Access(_) => "access",
+            EventKind::Create(_) => "create",
+            EventKind::Modify(_) => "modify",
+            EventKind::Remove(_) => "remove",
+            EventKind::Other => "other",
+        }
+    }
+}
+
+impl Drop for ScriptRunning {
+    fn drop(&mut self) {
+        self.0.adjust(SCRIPTS_RUNNING, vec![], -1);
+    }
+}
@@ -0,0 +1,112 @@
+use super::record::{DESCRIPTIONS, DROPPED_MESSAGES, DURATION_BUCKETS, UPTIME};
+use super::structs::{Labels, Metrics};
+use itertools::Itertools;
+use std::fmt::Write;
+
+impl Metrics {
+    /// Every metric in the Prometheus text exposition format, as served at `/metrics`.
+    pub fn render(&self) -> String {
+        let mut lines: Vec<(&str, String)> = vec![];
+        {
+            let samples = self.samples();
+            for ((name, labels), value) in &samples.counters {
+                lines.push((name, format!("{}{} {}", name, Self::labels(labels, None), value)));
+            }
+            for ((name, labels), value) in &samples.gauges {
+                lines.push((name, format!("{}{} {}", name, Self::labels(labels, None), value)));
+            }
+            for ((name, labels), histogram) in &samples.histograms {
+                let bounds = DURATION_BUCKETS.iter().map(|bound| bound.to_string()).chain(["+Inf".to_string()]);
+                let counts = histogram.buckets.iter().chain([&histogram.count]);
+                for (bound, count) in bounds.zip(counts) {
+                    let labels = Self::labels(labels, Some(&bound));
+                    lines.push((name, format!("{}_bucket{} {}", name, labels, count)));
+                }
+                let labels = Self::labels(labels, None);
+                lines.push((name, format!("{}_sum{} {}", name, labels, histogram.sum)));
+                lines.push((name, format!("{}_count{} {}", name, labels, histogram.count)));
+            }
+        }
+        for (channel, num_dropped) in self.lag_counter.dropped().into_iter().sorted() {
+            let labels = Self::labels(&vec![("channel", channel)], None);
+            lines.push((DROPPED_MESSAGES, format!("{}{} {}", DROPPED_MESSAGES, labels, num_dropped)));
+        }
+        lines.push((UPTIME, format!("{} {}", UPTIME, self.started_at.elapsed().as_secs_f64())));
+
+        let mut rendered = String::new();
+        for (name, kind, help) in DESCRIPTIONS {
+            let _ = writeln!(rendered, "# HELP {} {}", name, help);
+            let _ = writeln!(rendered, "# TYPE {} {}", name, kind);
+            for (_, line) in lines.iter().filter(|(line_name, _)| *line_name == name) {
+                let _ = writeln!(rendered, "{}", line);
+            }
+        }
+        rendered
+    }
+
+    /// Renders labels as `{name="value",...}`, with a histogram bucket's `le` label last, or nothing for no labels.
+    fn labels(labels: &Labels, le: Option<&str>) -> String {
+        let rendered = labels
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .chain(le.map(|le| ("le", le)))
+            .map(|(name, value)| format!("{}=\"{}\"", name, Self::escape(value)))
+            .join(",");
+        match rendered.is_empty() {
+            true => rendered,
+            false => format!("{{{}}}", rendered),
+        }
+    }
+
+    fn escape(value: &str) -> String {
+        value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::Metrics;
+    use crate::utilities::lag_counter::LagCounter;
+    use notify::{event::RemoveKind, EventKind};
+    use std::{path::Path, time::Duration};
+
+    #[test]
+    fn renders_the_prometheus_text_format() {
+        let lag_counter = LagCounter::new();
+        let metrics = Metrics::new(lag_counter.clone());
+        let watch_path = Path::new("/watched/\"quoted\"");
+        metrics.event_received(&EventKind::Remove(RemoveKind::Folder), watch_path);
+        metrics.event_received(&EventKind::Remove(RemoveKind::File), watch_path);
+        metrics.script_ran(metrics.script_started(), "backup", "success", Duration::from_millis(300));
+        metrics.set_queue_depth("spawn", 3);
+        lag_counter.record("events", 7);
+
+        let rendered = metrics.render();
+        for expected in [
+            "# TYPE rusty_hooks_events_received_total counter",
+            r#"rusty_hooks_events_received_total{kind="remove",watch_path="/watched/\"quoted\""} 2"#,
+            r#"rusty_hooks_script_runs_total{script="backup",outcome="success"} 1"#,
+            r#"rusty_hooks_script_duration_seconds_bucket{script="backup",le="0.25"} 0"#,
+            r#"rusty_hooks_script_duration_seconds_bucket{script="backup",le="0.5"} 1"#,
+            r#"rusty_hooks_script_duration_seconds_bucket{script="backup",le="+Inf"} 1"#,
+            r#"rusty_hooks_script_duration_seconds_count{script="backup"} 1"#,
+            r#"rusty_hooks_queue_depth{channel="spawn"} 3"#,
+            r#"rusty_hooks_dropped_messages_total{channel="events"} 7"#,
+            "# TYPE rusty_hooks_uptime_seconds gauge",
+        ] {
+            assert!(rendered.lines().any(|line| line == expected), "missing {}\n{}", expected, rendered);
+        }
+    }
+
+    #[test]
+    fn scripts_stop_counting_as_running_however_their_run_ends() {
+        let metrics = Metrics::new(LagCounter::new());
+        let ran = metrics.script_started();
+        let aborted = metrics.script_started();
+        assert_eq!(metrics.scripts_running(), 2);
+        metrics.script_ran(ran, "backup", "success", Duration::from_millis(300));
+        assert_eq!(metrics.scripts_running(), 1);
+        drop(aborted);
+        assert_eq!(metrics.scripts_running(), 0);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::utilities::lag_counter::LagCounter;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Counters, gauges and histograms describing what rusty-hooks has been up to, rendered in the Prometheus text format
+/// for the optional `/metrics` endpoint. Cheap to clone, every clone shares the same values.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    pub(super) samples: Arc<Mutex<Samples>>,
+    /// Dropped message counts are already kept here, so they're read from it rather than counted twice.
+    pub(super) lag_counter: LagCounter,
+    pub(super) started_at: Instant,
+}
+
+/// A script counted as running, until this is dropped. Scripts aborted or killed mid-run never get to record how they
+/// did, so they're counted out whichever way their run ends.
+#[must_use]
+#[derive(Debug)]
+pub struct ScriptRunning(pub(super) Metrics);
+
+/// Label names and values a sample is recorded under, in the order they're rendered in.
+pub type Labels = Vec<(&'static str, String)>;
+
+/// Every sample recorded so far, by metric name and labels.
+#[derive(Debug, Default)]
+pub struct Samples {
+    pub counters: BTreeMap<(&'static str, Labels), u64>,
+    pub gauges: BTreeMap<(&'static str, Labels), i64>,
+    pub histograms: BTreeMap<(&'static str, Labels), Histogram>,
+    /// How many messages were waiting on each channel, how many its probe had counted, and when, as of the last one
+    /// received from it.
+    pub received: BTreeMap<&'static str, (usize, usize, Instant)>,
+    /// Probes of the channels whose consumers asked for them, see [`Metrics::track_queue`].
+    pub probes: BTreeMap<&'static str, QueueProbe>,
+    pub last_notify_error: Option<Instant>,
+}
+
+/// Counts the messages sent on a channel since the probe was made, through a receiver of its own that's never received from.
+pub struct QueueProbe(pub Box<dyn Fn() -> usize + Send>);
+
+impl fmt::Debug for QueueProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "QueueProbe({})", (self.0)())
+    }
+}
+
+/// Observations counted into buckets of [`super::record::DURATION_BUCKETS`]. Each bucket counts every observation up to
+/// its bound, as Prometheus expects.
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
@@ -1,57 +1,220 @@
 use super::structs::Runner;
+use crate::control::{RunId, Runs};
 use crate::errors::script_errors::script_error::ScriptError;
 use crate::errors::shared_errors::thread_errors::{ThreadError, UnexpectedAnyhowError};
 use crate::errors::watcher_errors::{spawn_error::SpawnError, subscriber_error::SubscriptionError};
-use crate::scripts::structs::Script;
-use crate::utilities::traits::Utilities;
+use crate::metrics::Metrics;
+use crate::watcher::structs::Rename;
+use crate::runner::types::SpawnMessage;
+use crate::scripts::structs::{Script, ScriptRegistry};
+use crate::shutdown::Shutdown;
+use crate::systemd::NOTIFY_ENV_VARS;
+use crate::telemetry::spans;
+use crate::utilities::{
+    lag_counter::LagCounter, path_key::PathKey, thread_types::ChannelCapacities, traits::Utilities,
+};
 use async_process::{Command, Output};
-use futures::future::try_join_all;
-use log::{debug, error, info};
-use std::{fs, path::PathBuf, time::Duration};
-use tokio::{sync::broadcast::Sender, task::JoinHandle};
+use futures::future::join_all;
+use log::{debug, error, info, warn};
+use opentelemetry::{
+    trace::{Status, TraceContextExt},
+    Context, KeyValue,
+};
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::broadcast::{
+        error::{RecvError, TryRecvError},
+        Receiver, Sender,
+    },
+    task::JoinHandle,
+};
 
 impl Runner {
-    pub fn new() -> Result<Self, ThreadError> {
-        let spawn_channel = <Self as Utilities>::new_channel::<(PathBuf, Vec<Script>)>();
-        let unsubscribe_broadcast_channel = <Self as Utilities>::new_channel::<PathBuf>();
+    pub fn new(
+        capacities: ChannelCapacities,
+        lag_counter: LagCounter,
+        metrics: Metrics,
+        shutdown: Shutdown,
+        runs: Runs,
+    ) -> Result<Self, ThreadError> {
+        let spawn_channel =
+            <Self as Utilities>::new_channel::<SpawnMessage>(capacities.messages);
+        let unsubscribe_broadcast_channel =
+            <Self as Utilities>::new_channel::<PathBuf>(capacities.messages);
         let script_runtime = <Self as Utilities>::new_runtime(4, &"script-runner".to_string())?;
         Ok(Runner {
             runtime: script_runtime,
             spawn_channel,
             unsubscribe_broadcast_channel,
+            lag_counter,
+            metrics,
+            shutdown,
+            runs,
         })
     }
 
-    pub async fn init(&self) -> Result<(), SpawnError> {
+    /// Runs scripts on the paths sent over from the PathSubscriber until the spawn channel fails or shutdown is requested,
+    /// tracking whether it's still at it in the metrics. Runs resumed from the last shutdown go first.
+    pub async fn init(&self, resumed: Vec<SpawnMessage>) -> Result<(), SpawnError> {
+        self.metrics.task_started("runner");
+        let ran = self.run_scripts(resumed.into()).await;
+        self.metrics.task_stopped("runner");
+        ran
+    }
+
+    /// Once shutdown is requested, scripts already running get until the grace period runs out, and are killed after.
+    /// Paths flushed by the PathSubscriber keep being taken for as long as there's time, and whatever isn't run in time
+    /// is set aside.
+    async fn run_scripts(&self, mut resumed: VecDeque<SpawnMessage>) -> Result<(), SpawnError> {
         let mut spawn_listener = self.spawn_channel.0.clone().subscribe();
+        self.metrics.track_queue("spawn", &spawn_listener);
         // listening for paths to run scripts on, sent over from the PathSubscriber
         loop {
-            let (path, scripts) = spawn_listener
-                .recv()
-                .await
-                .map_err(ThreadError::RecvError)?;
+            // resumed runs were never subscribed to, so there's nothing to unsubscribe from once they're done
+            let (received, subscribed) = match (resumed.pop_front(), self.shutdown.is_requested()) {
+                (Some(message), false) => (Ok(message), false),
+                (Some(message), true) => {
+                    self.shutdown.set_aside(message);
+                    continue;
+                }
+                (None, false) => tokio::select! {
+                    received = spawn_listener.recv() => (received, true),
+                    _ = self.shutdown.requested() => continue,
+                },
+                (None, true) => match self.next_to_flush(&mut spawn_listener).await {
+                    Some(received) => (received, true),
+                    None => break,
+                },
+            };
+            let (path, mut scripts, rename, cx) = match received {
+                Ok(message) => {
+                    self.metrics.set_queue_depth("spawn", spawn_listener.len());
+                    message
+                }
+                Err(RecvError::Lagged(num_dropped)) => {
+                    // the dropped paths stay subscribed until their next event, nothing else to recover here
+                    self.lag_counter.record("spawn", num_dropped);
+                    continue;
+                }
+                Err(e) => return Err(ThreadError::RecvError(e).into()),
+            };
             let path_string = path
                 .to_str()
                 .unwrap_or("unable to pull string out of path buf");
             debug!("new path to spawn scripts for: {}", path_string);
+            spans::dequeued(&cx, "spawn_queue", vec![]);
+            let message = (path.clone(), scripts.clone(), rename.clone(), cx.clone());
+            let Some((run_id, manual)) = self.runs.start(&cx, &path) else {
+                info!("skipping scripts on {}, the run was cancelled while queued", path_string);
+                self.unsubscribe_if(subscribed, path)?;
+                continue;
+            };
+            // scripts triggered by hand run whether they're paused or not, on paths that were never subscribed to
+            let subscribed = subscribed && !manual;
+            if !manual {
+                scripts.retain(|script| !self.runs.is_paused(script));
+            }
+            if scripts.is_empty() {
+                info!("skipping scripts on {}, they're all paused", path_string);
+                self.runs.finished(run_id);
+                self.unsubscribe_if(subscribed, path)?;
+                continue;
+            }
             let unsubscribe_clone = self.unsubscribe_broadcast_channel.0.clone();
+            let metrics = self.metrics.clone();
 
-            let scripts_task: JoinHandle<Result<(), SpawnError>> = self.runtime.spawn(async move {
-                let script_processes: Vec<_> = scripts
-                    .iter()
-                    .map(|script| Self::run(&script.file_path, &path, &script.run_delay))
-                    .collect();
-                let awaited_scripts = try_join_all(script_processes)
-                    .await
-                    .map_err(|e| SpawnError::ScriptError(e.to_string()))?;
-                Self::log_script_output(awaited_scripts);
-                Self::rec_unsubscribe(unsubscribe_clone, path, 5)?;
+            let mut scripts_task: JoinHandle<Result<(), SpawnError>> = self.runtime.spawn(async move {
+                // higher priorities finish before lower ones start, equal ones run side by side
+                for batch in ScriptRegistry::priority_batches(&scripts) {
+                    let script_processes: Vec<_> = batch
+                        .iter()
+                        .map(|script| Self::run(script, &path, &rename, &cx, &metrics))
+                        .collect();
+                    // a script that can't be run doesn't stop the others, its failure is logged and counted like theirs
+                    let awaited_scripts = join_all(script_processes).await;
+                    Self::log_script_output(batch, awaited_scripts, &path, run_id);
+                }
+                if subscribed {
+                    Self::rec_unsubscribe(unsubscribe_clone, path, 5)?;
+                }
                 Ok(())
             });
-            scripts_task.await.map_err(ThreadError::JoinError)??
+            self.runs.running(run_id, scripts_task.abort_handle());
+            let ran = tokio::select! {
+                ran = &mut scripts_task => ran,
+                _ = self.shutdown.grace_expired() => {
+                    // dropping the scripts kills them, which only happens once the aborted task is polled again
+                    scripts_task.abort();
+                    let _ = (&mut scripts_task).await;
+                    warn!("shutdown grace period ran out with scripts still running on {}, killed them", message.0.display());
+                    self.shutdown.scripts_killed();
+                    self.runs.finished(run_id);
+                    self.shutdown.set_aside(message);
+                    break;
+                }
+            };
+            self.runs.finished(run_id);
+            match ran {
+                // aborted by cancelling the run, which killed its scripts on the way out
+                Err(e) if e.is_cancelled() => {
+                    info!("run {} on {} was cancelled while its scripts ran", run_id, message.0.display());
+                    self.unsubscribe_if(subscribed, message.0)?;
+                }
+                Ok(Ok(())) => {}
+                // one run going wrong, e.g. one triggered by hand, is no reason to stop running the rest
+                Ok(Err(e)) => error!("run {} on {} failed: {}", run_id, message.0.display(), e),
+                Err(e) => error!("run {} on {} failed: {}", run_id, message.0.display(), ThreadError::JoinError(e)),
+            }
+        }
+        // whatever's still waiting wasn't started before shutting down
+        resumed.into_iter().for_each(|message| self.shutdown.set_aside(message));
+        loop {
+            match spawn_listener.try_recv() {
+                Ok(message) => self.shutdown.set_aside(message),
+                Err(TryRecvError::Lagged(num_dropped)) => {
+                    self.lag_counter.record("spawn", num_dropped);
+                }
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// While shutting down, the next path to run scripts on. There's only one if pending paths are being flushed, and the
+    /// grace period hasn't run out, and something is either waiting on the channel or still to be flushed into it.
+    async fn next_to_flush(
+        &self,
+        spawn_listener: &mut Receiver<SpawnMessage>,
+    ) -> Option<Result<SpawnMessage, RecvError>> {
+        if !self.shutdown.flush {
+            return None;
+        }
+        loop {
+            // checked in this order since a path is queued before it stops counting as debouncing
+            if self.shutdown.num_debouncing() == 0 && spawn_listener.is_empty() {
+                return None;
+            }
+            tokio::select! {
+                received = spawn_listener.recv() => return Some(received),
+                _ = self.shutdown.grace_expired() => return None,
+                _ = tokio::time::sleep(Duration::from_millis(50)) => {},
+            }
         }
     }
 
+    /// Unsubscribes from a path whose scripts won't be run, if it was subscribed to in the first place.
+    fn unsubscribe_if(&self, subscribed: bool, path: PathBuf) -> Result<(), SpawnError> {
+        if subscribed {
+            Self::rec_unsubscribe(self.unsubscribe_broadcast_channel.0.clone(), path, 5)?;
+        }
+        Ok(())
+    }
+
     /// Recursively attempts to unsubscribe as many times as indicated by num_retries
     fn rec_unsubscribe(
         unsub_channel: Sender<PathBuf>,
@@ -74,12 +237,58 @@ impl Runner {
         }
     }
 
+    /// Runs a script on a path once its run delay is up, recording the run as a span in the trace of the event it's for,
+    /// and in the metrics.
     async fn run(
+        script: &Script,
+        target_path: &PathBuf,
+        rename: &Option<Rename>,
+        cx: &Context,
+        metrics: &Metrics,
+    ) -> Result<Output, ScriptError> {
+        let cx = spans::child(
+            cx,
+            "run_script",
+            vec![
+                KeyValue::new("script.name", script.name.clone()),
+                KeyValue::new("script.target_path", target_path.display().to_string()),
+            ],
+        );
+        tokio::time::sleep(Duration::from_secs(script.run_delay.into())).await;
+        let started_at = Instant::now();
+        let running = metrics.script_started();
+        let output = Self::spawn_script(&script.file_path, target_path, rename).await;
+        let duration = started_at.elapsed();
+        let span = cx.span();
+        span.set_attribute(KeyValue::new("script.duration_ms", duration.as_millis() as i64));
+        let outcome = match &output {
+            Ok(output) => {
+                if let Some(code) = output.status.code() {
+                    span.set_attribute(KeyValue::new("script.exit_code", code as i64));
+                }
+                match output.status.success() {
+                    true => "success",
+                    false => {
+                        span.set_status(Status::error("script exited unsuccessfully"));
+                        "failure"
+                    }
+                }
+            }
+            Err(e) => {
+                span.set_status(Status::error(e.to_string()));
+                "error"
+            }
+        };
+        span.end();
+        metrics.script_ran(running, &script.name, outcome, duration);
+        output
+    }
+
+    async fn spawn_script(
         script_path: &PathBuf,
         target_path: &PathBuf,
-        run_delay: &u8,
+        rename: &Option<Rename>,
     ) -> Result<Output, ScriptError> {
-        tokio::time::sleep(Duration::from_secs(run_delay.clone().into())).await;
         let script_path_string = script_path.to_str().ok_or(SpawnError::ArgError(
             "failed to parse script path".to_string(),
         ))?;
@@ -89,38 +298,112 @@ impl Runner {
         debug!("script path is at: {}", script_path_string);
         debug!("directory path is at: {}", target_path_string);
         match fs::exists(target_path) {
-            Ok(_) => {
-                debug!("target path exists, attempting to run");
-            }
+            Ok(true) => debug!("target path exists, attempting to run"),
+            // removal triggered scripts are handed the path that was removed
+            Ok(false) => debug!("target path no longer exists, attempting to run"),
             Err(e) => return Err(ScriptError::IoError(e.into())),
         }
-        let canonicalized_target_path = target_path.canonicalize()?;
+        // resolved without touching the file system, since the target may be gone
+        let absolute_target_path = PathKey::new(target_path).into_path_buf();
 
-        Ok(Command::new(script_path)
-            .arg(canonicalized_target_path.as_os_str())
-            .output()
-            .await?)
+        let mut command = Command::new(script_path);
+        command.arg(absolute_target_path.as_os_str());
+        // scripts still running once the shutdown grace period is up are dropped, and shouldn't outlive rusty hooks
+        command.kill_on_drop(true);
+        if let Some(rename) = rename {
+            // the old and new paths of whatever was renamed, see [`Rename::env_vars`]
+            command.envs(rename.env_vars());
+        }
+        // scripts aren't the service systemd is expecting to hear from
+        for var in NOTIFY_ENV_VARS {
+            command.env_remove(var);
+        }
+        Ok(command.output().await?)
     }
 
-    fn log_script_output(awaited_scripts: Vec<Output>) {
-        for script in awaited_scripts {
-            match script.status.success() {
+    /// Logs how each script of a batch did, and what it printed, with its name, watch path and exit code, the path it
+    /// ran on and the run's id as key-values, for log sinks such as journald to pick out. Scripts that couldn't be run at
+    /// all are logged with why instead.
+    fn log_script_output(
+        scripts: Vec<Script>,
+        awaited_scripts: Vec<Result<Output, ScriptError>>,
+        path: &Path,
+        run_id: RunId,
+    ) {
+        let path = path.display().to_string();
+        let (path, run_id) = (path.as_str(), run_id.0);
+        for (script, output) in scripts.iter().zip(awaited_scripts) {
+            let watch_path = script.watch_path.display().to_string();
+            let (script_name, watch_path) = (script.name.as_str(), watch_path.as_str());
+            let output = match output {
+                Ok(output) => output,
+                Err(e) => {
+                    error!(script_name, watch_path, path, run_id; "unable to run script: {}", e);
+                    continue;
+                }
+            };
+            let exit_code = output.status.code();
+            match output.status.success() {
                 true => {
-                    let stdout_str =
-                        String::from_utf8(script.stdout.clone()).unwrap_or("".to_string());
-                    stdout_str
-                        .split("\n")
-                        .filter(|l| l != &"")
-                        .for_each(|line| debug!(target: "script_output", "{:?}", line));
-                    info!("script execution successful");
+                    let stdout_str = String::from_utf8(output.stdout).unwrap_or("".to_string());
+                    stdout_str.split("\n").filter(|l| l != &"").for_each(|line| {
+                        debug!(target: "script_output", script_name, watch_path, path, run_id, exit_code; "{:?}", line)
+                    });
+                    info!(script_name, watch_path, path, run_id, exit_code; "script execution successful");
                 }
                 false => {
-                    let stderr_str =
-                        String::from_utf8(script.stderr.clone()).unwrap_or("".to_string());
-                    error!("error with a script");
-                    error!(target: "script_output", "{}", stderr_str);
+                    let stderr_str = String::from_utf8(output.stderr).unwrap_or("".to_string());
+                    error!(script_name, watch_path, path, run_id, exit_code; "error with a script");
+                    error!(target: "script_output", script_name, watch_path, path, run_id, exit_code; "{}", stderr_str);
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempdir::TempDir;
+
+    /// A script that creates a file beside it named after itself, once run.
+    fn touch_script(dir: &Path, name: &str) -> Script {
+        let file_path = dir.join(format!("{}.sh", name));
+        fs::write(&file_path, format!("#!/bin/sh\ntouch {}\n", dir.join(name).display())).unwrap();
+        fs::set_permissions(&file_path, fs::Permissions::from_mode(0o755)).unwrap();
+        Script { file_path, ..Script::for_test(name, dir) }
+    }
+
+    #[test]
+    fn keeps_running_scripts_once_one_cant_be_run() {
+        let dir = TempDir::new("runner").unwrap();
+        let missing = Script { file_path: dir.path().join("missing.sh"), ..Script::for_test("missing", dir.path()) };
+        let resumed = vec![
+            (dir.path().to_path_buf(), vec![missing, touch_script(dir.path(), "beside")], None, Context::new()),
+            (dir.path().to_path_buf(), vec![touch_script(dir.path(), "after")], None, Context::new()),
+        ];
+        let metrics = Metrics::new(LagCounter::new());
+        let shutdown = Shutdown::new(Duration::from_secs(5), false);
+        let capacities = ChannelCapacities { events: 16, messages: 16 };
+        let runner = Runner::new(capacities, LagCounter::new(), metrics.clone(), shutdown.clone(), Runs::new()).unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (ran, _) = runtime.block_on(async {
+            tokio::join!(runner.init(resumed), async {
+                // a runner that stopped at the first failure never gets to it
+                for _ in 0..1000 {
+                    if dir.path().join("after").exists() {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                shutdown.request();
+            })
+        });
+        ran.unwrap();
+        assert!(dir.path().join("beside").exists() && dir.path().join("after").exists());
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"rusty_hooks_script_runs_total{script="missing",outcome="error"} 1"#), "{}", rendered);
+    }
+}
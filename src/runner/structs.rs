@@ -1,5 +1,8 @@
-use crate::scripts::structs::Script;
-use crate::utilities::traits::Utilities;
+use super::types::SpawnMessage;
+use crate::control::Runs;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+use crate::utilities::{lag_counter::LagCounter, traits::Utilities};
 #[cfg(test)]
 use mocktopus::macros::*;
 use std::path::PathBuf;
@@ -9,11 +12,14 @@ use tokio::sync::broadcast::{Receiver, Sender};
 #[derive(Debug)]
 pub struct Runner {
     pub runtime: tokio::runtime::Runtime,
-    pub spawn_channel: (
-        Sender<(PathBuf, Vec<Script>)>,
-        Receiver<(PathBuf, Vec<Script>)>,
-    ),
+    pub spawn_channel: (Sender<SpawnMessage>, Receiver<SpawnMessage>),
     pub unsubscribe_broadcast_channel: (Sender<PathBuf>, Receiver<PathBuf>),
+    pub lag_counter: LagCounter,
+    pub metrics: Metrics,
+    /// Tells the runner to finish up, see [`Runner::init`].
+    pub shutdown: Shutdown,
+    /// Runs being run, so they can be cancelled, and what's paused.
+    pub runs: Runs,
 }
 
 #[cfg_attr(test, mockable)]
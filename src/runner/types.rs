@@ -1,6 +1,8 @@
 use crate::scripts::structs::Script;
+use crate::watcher::structs::Rename;
+use opentelemetry::Context;
 use std::path::PathBuf;
 
-pub type SpawnMessage = (PathBuf, Vec<Script>);
+pub type SpawnMessage = (PathBuf, Vec<Script>, Option<Rename>, Context); // rename is set when the path was subscribed to because something was renamed or moved, and the context carries the trace of the event that did it
 // pub type SpawnChannel =
 
@@ -1,2 +1,8 @@
+pub mod diff;
+pub mod expand;
 pub mod load;
+pub mod registry;
+pub mod schema;
+pub mod sources;
 pub mod structs;
+pub mod validate;
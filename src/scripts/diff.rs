@@ -0,0 +1,149 @@
+use super::structs::{ScriptsByWatchPath, ScriptsDiff};
+use crate::utilities::path_key::PathKey;
+use itertools::Itertools;
+use std::fmt;
+
+impl ScriptsDiff {
+    pub fn between(current: &ScriptsByWatchPath, incoming: &ScriptsByWatchPath) -> Self {
+        let added = incoming
+            .keys()
+            .filter(|watch_path| !current.contains_key(*watch_path))
+            .cloned()
+            .sorted()
+            .collect_vec();
+        let removed = current
+            .keys()
+            .filter(|watch_path| !incoming.contains_key(*watch_path))
+            .cloned()
+            .sorted()
+            .collect_vec();
+        let (updated, unchanged): (Vec<_>, Vec<_>) = current
+            .iter()
+            .filter_map(|(watch_path, scripts)| {
+                // watch_paths lists every watch path in the config, so it says nothing about this one in particular
+                incoming.get(watch_path).map(|incoming_scripts| {
+                    let same = scripts.registry == incoming_scripts.registry
+                        && scripts.watch_options == incoming_scripts.watch_options;
+                    (watch_path, same)
+                })
+            })
+            .partition(|(_, same)| !same);
+        ScriptsDiff {
+            added,
+            removed,
+            updated: updated
+                .into_iter()
+                .map(|(watch_path, _)| watch_path.clone())
+                .sorted()
+                .collect_vec(),
+            unchanged: unchanged.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+}
+
+impl fmt::Display for ScriptsDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |paths: &Vec<PathKey>| {
+            paths.iter().map(|path| path.display().to_string()).join(", ")
+        };
+        write!(
+            f,
+            "{} watch paths added [{}], {} removed [{}], {} updated [{}], {} unchanged",
+            self.added.len(),
+            list(&self.added),
+            self.removed.len(),
+            list(&self.removed),
+            self.updated.len(),
+            list(&self.updated),
+            self.unchanged
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripts::structs::{Scripts, ScriptsByWatchPath, ScriptsDiff, WatchOptions};
+    use crate::utilities::path_key::PathKey;
+    use std::{
+        collections::HashMap,
+        fs,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+    use tempdir::TempDir;
+
+    fn key(path: &str) -> PathKey {
+        PathKey::new(Path::new(path))
+    }
+
+    /// Scripts as loading has them, with every watch path of the config in `watch_paths`.
+    fn scripts_for(watch_paths: &[&str], max_depth: Option<usize>) -> Scripts {
+        let watch_paths = watch_paths.iter().map(Path::new).collect::<Vec<_>>();
+        Scripts::for_test(&watch_paths, WatchOptions { max_depth, ..WatchOptions::default() })
+    }
+
+    /// Writes a config with a script on each of the watch paths, which are directories made in the temp dir.
+    fn write_config(dir: &Path, watch_paths: &[&str]) -> PathBuf {
+        let script = dir.join("noop.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut config = "scripts:\n".to_string();
+        for watch_path in watch_paths {
+            fs::create_dir_all(dir.join(watch_path)).unwrap();
+            config.push_str(&format!(
+                "  - {{name: {watch_path}, description: d, file_name: noop.sh, watch_path: \"{}\", enabled: true, run_delay: 0, event_triggers: [Remove], dependencies: []}}\n",
+                dir.join(watch_path).display()
+            ));
+        }
+        let config_path = dir.join("scripts.yml");
+        fs::write(&config_path, config).unwrap();
+        config_path
+    }
+
+    #[test]
+    fn sorts_watch_paths_into_added_removed_and_updated() {
+        let before = ["/kept", "/changed", "/dropped"];
+        let after = ["/kept", "/changed", "/new"];
+        let current: ScriptsByWatchPath = HashMap::from([
+            (key("/kept"), scripts_for(&before, None)),
+            (key("/changed"), scripts_for(&before, None)),
+            (key("/dropped"), scripts_for(&before, None)),
+        ]);
+        let incoming: ScriptsByWatchPath = HashMap::from([
+            (key("/kept"), scripts_for(&after, None)),
+            (key("/changed"), scripts_for(&after, Some(1))),
+            (key("/new"), scripts_for(&after, None)),
+        ]);
+
+        let diff = ScriptsDiff::between(&current, &incoming);
+
+        assert_eq!(diff.added, vec![key("/new")]);
+        assert_eq!(diff.removed, vec![key("/dropped")]);
+        assert_eq!(diff.updated, vec![key("/changed")]);
+        assert_eq!(diff.unchanged, 1);
+    }
+
+    #[test]
+    fn identical_configs_produce_an_empty_diff() {
+        let current: ScriptsByWatchPath = HashMap::from([(key("/kept"), scripts_for(&["/kept"], None))]);
+
+        assert!(ScriptsDiff::between(&current, &current.clone()).is_empty());
+    }
+
+    #[test]
+    fn adding_a_watch_path_leaves_the_others_unchanged() {
+        let dir = TempDir::new("diff").unwrap();
+        let current = Scripts::load_all(&write_config(dir.path(), &["first", "second"])).unwrap();
+        let incoming = Scripts::load_all(&write_config(dir.path(), &["first", "second", "third"])).unwrap();
+
+        let diff = ScriptsDiff::between(&current, &incoming);
+
+        assert_eq!(diff.added, vec![PathKey::new(&dir.path().join("third"))]);
+        assert!(diff.removed.is_empty() && diff.updated.is_empty());
+        assert_eq!(diff.unchanged, 2);
+    }
+}
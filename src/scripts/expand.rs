@@ -0,0 +1,104 @@
+use super::structs::ScriptBlock;
+use crate::errors::script_errors::script_error::ScriptConfigError;
+use directories::BaseDirs;
+use itertools::Itertools;
+use log::{debug, warn};
+use std::env::VarError;
+use std::path::PathBuf;
+
+impl ScriptBlock {
+    /// The watch paths this block's `watch_path` stands for. `~` and `${VAR}`/`$VAR` are expanded first, then glob patterns
+    /// are matched against the file system, e.g. `/mnt/disk*/downloads`. A pattern can match nothing (yet), while a literal
+    /// path always stands for itself, existing or not.
+    pub fn expand_watch_path(&self) -> Result<Vec<PathBuf>, ScriptConfigError> {
+        self.expand_watch_path_with(|var| std::env::var(var))
+    }
+
+    /// [`ScriptBlock::expand_watch_path`], looking variables up with the provided function rather than in the process environment.
+    pub fn expand_watch_path_with(
+        &self,
+        env: impl Fn(&str) -> Result<String, VarError>,
+    ) -> Result<Vec<PathBuf>, ScriptConfigError> {
+        let home_dir = || BaseDirs::new().and_then(|dirs| dirs.home_dir().to_str().map(str::to_string));
+        let expanded = shellexpand::full_with_context(&self.watch_path, home_dir, |var| env(var).map(Some))
+            .map_err(|e| ScriptConfigError::WatchPathError(self.watch_path.clone(), e.to_string()))?;
+        if !Self::is_glob_pattern(&expanded) {
+            return Ok(vec![PathBuf::from(expanded.as_ref())]);
+        }
+        let matches = glob::glob(&expanded)
+            .map_err(|e| ScriptConfigError::WatchPathError(self.watch_path.clone(), e.to_string()))?;
+        let watch_paths = matches
+            .filter_map(|matched| match matched {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    // unreadable directories along the way just don't match
+                    debug!("skipping part of watch path pattern {}: {}", self.watch_path, e);
+                    None
+                }
+            })
+            .filter(|path| path.is_dir() || path.is_file())
+            .collect_vec();
+        if watch_paths.is_empty() {
+            warn!("watch path pattern {} doesn't match anything (yet)", self.watch_path);
+        }
+        Ok(watch_paths)
+    }
+
+    /// Whether this block's `watch_path` is a glob pattern, whose matches may change while rusty-hooks is running.
+    pub fn has_watch_path_pattern(&self) -> bool {
+        shellexpand::full(&self.watch_path).is_ok_and(|expanded| Self::is_glob_pattern(&expanded))
+    }
+
+    fn is_glob_pattern(path: &str) -> bool {
+        path.contains(['*', '?', '['])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripts::structs::ScriptBlock;
+    use std::env::VarError;
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn block(watch_path: String) -> ScriptBlock {
+        ScriptBlock {
+            name: "test".to_string(),
+            description: "test".to_string(),
+            file_name: "test.sh".to_string(),
+            watch_path,
+            enabled: true,
+            run_delay: 0,
+            event_triggers: vec!["Create".to_string()],
+            dependencies: vec![],
+            recursive: None,
+            max_depth: None,
+            follow_symlinks: None,
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn expands_env_vars_then_globs() {
+        let root = TempDir::new("mnt").unwrap();
+        for disk in ["disk1", "disk2", "other"] {
+            fs::create_dir_all(root.path().join(disk).join("downloads")).unwrap();
+        }
+        let env = |var: &str| match var {
+            "MNT" => Ok(root.path().display().to_string()),
+            _ => Err(VarError::NotPresent),
+        };
+        let pattern = block("${MNT}/disk*/downloads".to_string());
+
+        let mut expanded = pattern.expand_watch_path_with(env).unwrap();
+        expanded.sort();
+        assert_eq!(
+            expanded,
+            vec![
+                root.path().join("disk1").join("downloads"),
+                root.path().join("disk2").join("downloads"),
+            ]
+        );
+        assert!(block("${UNSET}/x".to_string()).expand_watch_path_with(env).is_err());
+    }
+}
@@ -1,31 +1,39 @@
-use super::structs::{Script, ScriptYAML, Scripts, ScriptsByEventTrigger};
+use super::structs::{
+    ConfigFormat, EventTrigger, ExpandedBlock, Script, ScriptConfig, ScriptRegistry, Scripts,
+    ScriptsByWatchPath, WatchOptions,
+};
 use crate::errors::script_errors::script_error::{ScriptConfigError, ScriptError};
 use crate::scripts::structs::ScriptBlock;
-use crate::utilities::traits::Utilities;
+use crate::utilities::{path_key::PathKey, traits::Utilities};
 use anyhow::anyhow;
 use itertools::FoldWhile::{Continue, Done};
 use itertools::Itertools;
-use log::{debug, error};
+use log::{debug, error, warn};
 use notify::{event::AccessKind, EventKind};
 use std::path::{Path, PathBuf};
-use std::{collections::HashMap, fs};
 
 impl Scripts {
     pub fn get_by_event(&self, event_kind: &EventKind) -> Vec<Script> {
-        match self.scripts_by_event_triggers.get(&event_kind) {
-            Some(scripts) => scripts.clone(),
-            None => return vec![],
-        }
+        // scripts are cached under the kind their trigger was parsed into, which for removals is the catch-all
+        let trigger_kind = match event_kind {
+            EventKind::Remove(_) => EventKind::Remove(notify::event::RemoveKind::Any),
+            kind => *kind,
+        };
+        self.get_by_trigger(&EventTrigger::Kind(trigger_kind))
+    }
+
+    pub fn get_by_trigger(&self, trigger: &EventTrigger) -> Vec<Script> {
+        self.registry.get(trigger)
     }
 
     pub fn validate_scripts(
         watch_path: &PathBuf,
-        unvalidated_scripts: Vec<ScriptBlock>,
+        expanded_blocks: &[ExpandedBlock],
         script_directory: &String,
     ) -> Result<Vec<Script>, ScriptConfigError> {
-        let script_validations: Vec<Result<(bool, PathBuf), std::io::Error>> = unvalidated_scripts
+        let script_validations: Vec<Result<(bool, PathBuf), std::io::Error>> = expanded_blocks
             .iter()
-            .map(|script| {
+            .map(|(script, expanded)| {
                 let script_path = Self::build_path(&vec![&script_directory, &script.file_name]);
                 let io_error_kind = std::io::ErrorKind::InvalidFilename;
                 let script_path_io_error = std::io::Error::new(
@@ -41,9 +49,7 @@ impl Scripts {
                         .to_string()
                     ),
                 );
-                let internal_watch_path = &script.watch_path;
-                let watch_paths_match =
-                    watch_path.clone() == std::path::Path::new(internal_watch_path).to_path_buf();
+                let watch_paths_match = Self::expanded_to(expanded, watch_path);
 
                 match (script_path, watch_paths_match) {
                     (Some(path), true) => Ok((true, path)),
@@ -82,42 +88,92 @@ impl Scripts {
             let io_error = std::io::Error::new(io_error_kind, "script validation error");
             Err(ScriptConfigError::IoError(io_error))
         } else {
-            Ok(unvalidated_scripts.iter().map_into().collect_vec())
+            Ok(expanded_blocks
+                .iter()
+                .map(|(block, expanded)| {
+                    let mut script: Script = block.into();
+                    // a pattern's script belongs to each path it expanded to, so it's looked up under that path
+                    if Self::expanded_to(expanded, watch_path) {
+                        script.watch_path = watch_path.clone();
+                    }
+                    script
+                })
+                .collect_vec())
         }
     }
 
-    pub fn all_watch_paths(config_path: &Path) -> Result<Vec<PathBuf>, ScriptError> {
-        let configs_file = fs::read_to_string(config_path).map_err(ScriptError::IoError)?;
+    /// Whether any enabled script's watch path is a glob pattern, whose matches have to be re-expanded now and then.
+    pub fn has_watch_path_patterns(config_path: &Path) -> Result<bool, ScriptError> {
+        let config = Self::read_config(config_path)?;
+        Ok(config
+            .scripts
+            .iter()
+            .any(|script| script.enabled && script.has_watch_path_pattern()))
+    }
+
+    /// Whether a block's expanded watch paths include the provided one, and so whether its script belongs to it.
+    fn expanded_to(expanded: &[PathBuf], watch_path: &Path) -> bool {
+        let watch_path = PathKey::new(watch_path);
+        expanded.iter().any(|path| PathKey::new(path) == watch_path)
+    }
+
+    /// Expands every block's watch path once, to be matched against each watch path from then on rather than globbed
+    /// again for each of them. An enabled block whose watch path doesn't expand fails the whole config, while a disabled
+    /// one is left expanded to nothing.
+    pub fn expand_blocks(blocks: Vec<ScriptBlock>) -> Result<Vec<ExpandedBlock>, ScriptConfigError> {
+        blocks
+            .into_iter()
+            .map(|block| match block.expand_watch_path() {
+                Ok(expanded) => Ok((block, expanded)),
+                Err(e) if block.enabled => Err(e),
+                Err(_) => Ok((block, vec![])),
+            })
+            .collect()
+    }
 
-        let scripts_yaml_configurations: ScriptYAML =
-            serde_yaml::from_str::<ScriptYAML>(&configs_file)
-                .map_err(ScriptConfigError::YAMLError)?;
+    pub fn all_watch_paths(config_path: &Path) -> Result<Vec<PathBuf>, ScriptError> {
+        let config = Self::read_config(config_path)?;
+        Self::check_watch_paths(&Self::expand_blocks(config.scripts)?)
+    }
 
+    /// The watch paths of every enabled block, as long as each of them is a directory, a file or doesn't exist (yet).
+    fn check_watch_paths(expanded_blocks: &[ExpandedBlock]) -> Result<Vec<PathBuf>, ScriptError> {
         let mut bad_path: Option<PathBuf> = None;
 
-        let watch_paths: Vec<PathBuf> = scripts_yaml_configurations
-            .scripts
+        let expanded_watch_paths: Vec<PathBuf> = expanded_blocks
             .iter()
-            .fold_while(vec![], |mut acc: Vec<PathBuf>, script: &ScriptBlock| {
-                let path = Path::new(&script.watch_path);
-                let can_read = path.read_dir();
+            .filter(|(block, _)| block.enabled)
+            .flat_map(|(_, expanded)| expanded.iter().cloned())
+            .collect();
 
-                match (script.enabled, path.is_dir(), can_read) {
-                    (true, true, Ok(_)) => {
+        let watch_paths: Vec<PathBuf> = expanded_watch_paths
+            .iter()
+            .fold_while(vec![], |mut acc: Vec<PathBuf>, path: &PathBuf| {
+                match (path.exists(), path.is_dir()) {
+                    (true, true) => {
+                        if let Err(e) = path.read_dir() {
+                            // e.g. permissions not yet granted, the watcher attaches once it's readable
+                            warn!("error reading path entries of {:?}: {}", path.to_str(), e);
+                        }
                         acc.push(path.to_path_buf());
                         Continue(acc)
                     }
-                    (true, true, Err(e)) => {
-                        debug!("error reading path entries: {}", e);
-                        bad_path = Some(path.to_path_buf());
-                        Done(acc)
+                    (true, false) if path.is_file() => {
+                        // watched through its parent directory, see Watcher::attach
+                        acc.push(path.to_path_buf());
+                        Continue(acc)
                     }
-                    (true, false, _) => {
-                        debug!("provided path is not a directory: {:?}", path.to_str());
+                    (true, false) => {
+                        debug!("provided path is neither a directory nor a file: {:?}", path.to_str());
                         bad_path = Some(path.to_path_buf());
                         Done(acc)
                     }
-                    (false, _, _) => Continue(acc),
+                    (false, _) => {
+                        // e.g. an unmounted drive, the watcher attaches once it appears
+                        warn!("watch path doesn't exist (yet): {:?}", path.to_str());
+                        acc.push(path.to_path_buf());
+                        Continue(acc)
+                    }
                 }
             })
             .into_inner();
@@ -137,12 +193,10 @@ impl Scripts {
         }
     }
 
-    pub fn by_watch_path(
-        watch_path: &PathBuf,
-        scripts_config_path: &Path,
-    ) -> Result<Self, ScriptError> {
-        let config_path_buf = scripts_config_path.to_path_buf();
-        let script_config_path_str = scripts_config_path.to_str().unwrap_or("");
+    /// Loads and validates the scripts for every enabled watch path in the config file. Several scripts can share a
+    /// watch path, so each path appears once.
+    pub fn load_all(config_path: &Path) -> Result<ScriptsByWatchPath, ScriptError> {
+        let config_path_buf = config_path.to_path_buf();
 
         let script_directory_path =
             Scripts::get_parent_dir_of_file(&config_path_buf).ok_or(ScriptError::ConfigError(
@@ -159,15 +213,26 @@ impl Scripts {
             ))?
             .to_string();
 
-        let script_configs_str =
-            fs::read_to_string(script_config_path_str).map_err(ScriptError::IoError)?;
-        let unvalidated_scripts: ScriptYAML =
-            serde_yaml::from_str(&script_configs_str).map_err(ScriptConfigError::YAMLError)?;
-        let validated_scripts = Self::validate_scripts(
-            watch_path,
-            unvalidated_scripts.scripts,
-            &script_directory_path_string,
-        )?;
+        let expanded_blocks = Self::expand_blocks(Self::read_config(config_path)?.scripts)?;
+        let watch_paths = Self::check_watch_paths(&expanded_blocks)?;
+        watch_paths
+            .into_iter()
+            // `./dir` and `dir` are one watch path
+            .unique_by(|watch_path| PathKey::new(watch_path))
+            .map(|watch_path| {
+                let scripts = Self::by_watch_path(&watch_path, &expanded_blocks, &script_directory_path_string)?;
+                Ok((PathKey::new(&watch_path), scripts))
+            })
+            .collect()
+    }
+
+    /// The scripts of the blocks that expanded to the watch path. See [`Scripts::expand_blocks`].
+    pub fn by_watch_path(
+        watch_path: &PathBuf,
+        expanded_blocks: &[ExpandedBlock],
+        script_directory: &String,
+    ) -> Result<Self, ScriptError> {
+        let validated_scripts = Self::validate_scripts(watch_path, expanded_blocks, script_directory)?;
 
         let watch_paths: Vec<PathBuf> = validated_scripts
             .iter()
@@ -177,7 +242,7 @@ impl Scripts {
         let filtered_by_watch_path: Vec<Script> = validated_scripts
             .iter()
             .filter(|script| {
-                watch_path.clone() == std::path::Path::new(&script.watch_path).to_path_buf()
+                PathKey::new(watch_path) == PathKey::new(Path::new(&script.watch_path))
             })
             .map(|s| s.to_owned())
             .collect();
@@ -193,76 +258,157 @@ impl Scripts {
             script_file_names
         );
 
-        let scripts_by_event_triggers = Self::cache_scripts_by_events(&filtered_by_watch_path);
+        let registry = Self::cache_scripts_by_events(&filtered_by_watch_path);
+        debug!("{} scripts registered for watch path {:?}", registry.len(), watch_path);
+        // the watch itself has to reach as deep as the deepest script, which is unlimited if any script is
+        let max_depth = filtered_by_watch_path
+            .iter()
+            .map(|script| script.max_depth)
+            .try_fold(0, |deepest, max_depth| max_depth.map(|max_depth| deepest.max(max_depth)));
+        // likewise it follows symlinks if any script does, and scripts that don't are left out of events reached through them
+        let follow_symlinks = filtered_by_watch_path
+            .iter()
+            .any(|script| script.follow_symlinks);
 
         Ok(Scripts {
-            scripts_by_event_triggers,
+            registry,
             watch_paths,
+            watch_options: WatchOptions {
+                max_depth,
+                follow_symlinks,
+            },
         })
     }
 
-    fn cache_scripts_by_events(files: &Vec<Script>) -> HashMap<EventKind, Vec<Script>> {
-        let acc_int: ScriptsByEventTrigger = HashMap::new();
-        let files_iter = files.clone().into_iter();
-        // iterate over each file and reduce to hashmap of event type and associated scripts to run
-        files_iter.fold(acc_int, |scripts_by_event_type_acc, current_script| {
-            let current_event_triggers = &current_script.event_triggers;
-            // need to reduce over the array of events a given script schema should be tied to and update the upper-level accumulator accordingly
-            current_event_triggers.into_iter().fold(
-                scripts_by_event_type_acc,
-                |mut scripts_by_event_type_acc, event| {
-                    let event_string = event.clone();
-                    let event_kind = match event_string.as_str() {
-                        "Access" => EventKind::Access(AccessKind::Any),
-                        "Create" => EventKind::Create(notify::event::CreateKind::Any),
-                        "Modify" => EventKind::Modify(notify::event::ModifyKind::Name(
-                            notify::event::RenameMode::To,
-                        )),
-                        "Remove" => EventKind::Remove(notify::event::RemoveKind::Any),
-                        "Other" => EventKind::Other,
-                        _ => return scripts_by_event_type_acc,
-                    };
-                    let event_schemas = Self::update_schema_vec(
-                        &event_kind,
-                        current_script.clone(),
-                        &mut scripts_by_event_type_acc,
-                    );
-                    scripts_by_event_type_acc.insert(event_kind.clone(), event_schemas);
-                    scripts_by_event_type_acc
-                },
-            )
-        })
+    /// Registers every script under each trigger it runs on. Triggers that don't parse are skipped, see
+    /// `rusty-hooks validate` for finding them.
+    fn cache_scripts_by_events(scripts: &[Script]) -> ScriptRegistry {
+        let mut registry = ScriptRegistry::new();
+        for script in scripts {
+            for trigger in script.event_triggers.iter().filter_map(|trigger| EventTrigger::parse(trigger)) {
+                registry.register(trigger, script.clone());
+            }
+        }
+        registry
     }
+}
 
-    fn update_schema_vec(
-        event_type: &EventKind,
-        script: Script,
-        scripts: &mut HashMap<EventKind, Vec<Script>>,
-    ) -> Vec<Script> {
-        debug!(
-            "deciding whether to insert script {:?}",
-            serde_yaml::to_string(&script.file_name)
-                .unwrap_or("unable to parse script file name".to_string())
-        );
-        match scripts.get_mut(event_type) {
-            Some(acc_event_type_scripts) => {
-                // array of scripts attached to event_type
-                let script_exists = acc_event_type_scripts
-                    .into_iter()
-                    .any(|script| script.file_name.eq_ignore_ascii_case(&script.file_name));
-                match script_exists {
-                    true => acc_event_type_scripts.to_vec(),
-                    false => {
-                        acc_event_type_scripts.push(script.into());
-                        acc_event_type_scripts.to_vec()
-                    }
+impl EventTrigger {
+    /// Parses a trigger as written in the config, one of [`super::structs::EVENT_TRIGGERS`].
+    pub fn parse(trigger: &str) -> Option<Self> {
+        match trigger {
+            "Access" => Some(EventTrigger::Kind(EventKind::Access(AccessKind::Any))),
+            "Create" => Some(EventTrigger::Kind(EventKind::Create(
+                notify::event::CreateKind::Any,
+            ))),
+            "Modify" => Some(EventTrigger::Kind(EventKind::Modify(
+                notify::event::ModifyKind::Name(notify::event::RenameMode::To),
+            ))),
+            "Remove" => Some(EventTrigger::Kind(EventKind::Remove(
+                notify::event::RemoveKind::Any,
+            ))),
+            "Other" => Some(EventTrigger::Kind(EventKind::Other)),
+            "MovedIn" => Some(EventTrigger::MovedIn),
+            "MovedOut" => Some(EventTrigger::MovedOut),
+            _ => None,
+        }
+    }
+}
+
+impl ConfigFormat {
+    /// Extensions of the files the config can be read from, see [`ConfigFormat::from_path`].
+    pub const EXTENSIONS: [&'static str; 4] = ["yml", "yaml", "toml", "json"];
+
+    /// The format a config file is written in, going by its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "yml" | "yaml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Parses a config file's contents. Errors point at the file, line and column they're about, when the parser knows them.
+    pub fn parse(&self, file: &Path, contents: &str) -> Result<ScriptConfig, ScriptConfigError> {
+        let file_name = file.display().to_string();
+        match self {
+            ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| {
+                match e.location() {
+                    Some(location) => Self::parse_error(file_name, location.line(), location.column(), e.to_string()),
+                    None => ScriptConfigError::ConfigFileError(file_name, e.to_string()),
                 }
-            }
-            None => {
-                let event_type_and_schema_to_insert = vec![script.clone()];
-                scripts.insert(event_type.clone(), vec![script.clone()]);
-                event_type_and_schema_to_insert
-            }
+            }),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| match e.span() {
+                Some(span) => {
+                    let (line, column) = Self::line_and_column(contents, span.start);
+                    Self::parse_error(file_name, line, column, e.message().to_string())
+                }
+                None => ScriptConfigError::ConfigFileError(file_name, e.message().to_string()),
+            }),
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| {
+                Self::parse_error(file_name, e.line(), e.column(), e.to_string())
+            }),
+        }
+    }
+
+    /// serde_yaml and serde_json end their messages with the location, which the error already leads with.
+    fn parse_error(file: String, line: usize, column: usize, message: String) -> ScriptConfigError {
+        let location_suffix = format!(" at line {} column {}", line, column);
+        let message = message
+            .strip_suffix(&location_suffix)
+            .map(str::to_string)
+            .unwrap_or(message);
+        ScriptConfigError::ParseError(file, line, column, message)
+    }
+
+    /// 1-based line and column of a byte offset into the contents.
+    fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+        let before = &contents[..offset.min(contents.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        (line, column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripts::structs::{EventTrigger, Scripts};
+    use crate::utilities::path_key::PathKey;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempdir::TempDir;
+
+    #[test]
+    fn loads_every_script_on_a_shared_watch_path() {
+        let dir = TempDir::new("shared").unwrap();
+        let watched = dir.path().join("watched");
+        fs::create_dir(&watched).unwrap();
+        let scripts = [("notify", 0), ("backup", 10), ("index", 0), ("cleanup", -5)];
+        let mut config = "scripts:\n".to_string();
+        for (name, priority) in scripts {
+            let script = dir.path().join(format!("{}.sh", name));
+            fs::write(&script, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+            config.push_str(&format!(
+                "  - {{name: {name}, description: d, file_name: {name}.sh, watch_path: \"{}\", enabled: true, run_delay: 0, event_triggers: [Modify, Remove], dependencies: [], priority: {priority}}}\n",
+                watched.display()
+            ));
+        }
+        let config_path = dir.path().join("scripts.yml");
+        fs::write(&config_path, config).unwrap();
+
+        let loaded = Scripts::load_all(&config_path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        let scripts = &loaded[&PathKey::new(&watched)];
+        assert_eq!(scripts.registry.len(), 4);
+        for trigger in ["Modify", "Remove"] {
+            let names = scripts
+                .get_by_trigger(&EventTrigger::parse(trigger).unwrap())
+                .into_iter()
+                .map(|script| script.name)
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec!["backup", "index", "notify", "cleanup"]);
         }
     }
 }
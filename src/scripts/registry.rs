@@ -0,0 +1,101 @@
+use super::structs::{EventTrigger, Script, ScriptRegistry};
+use itertools::Itertools;
+use std::cmp::Reverse;
+
+impl ScriptRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a script to run on a trigger, replacing any script of the same name already registered under it.
+    pub fn register(&mut self, trigger: EventTrigger, script: Script) {
+        let scripts = self.by_trigger.entry(trigger).or_default();
+        Self::merge_into(scripts, vec![script]);
+    }
+
+    /// The scripts that run on a trigger, in the order they run in.
+    pub fn get(&self, trigger: &EventTrigger) -> Vec<Script> {
+        self.by_trigger.get(trigger).cloned().unwrap_or_default()
+    }
+
+    /// The script of that name, whatever triggers it's registered under.
+    pub fn find(&self, name: &str) -> Option<Script> {
+        self.by_trigger.values().flatten().find(|script| script.name == name).cloned()
+    }
+
+    /// Every script registered, once each whatever triggers it's under, in the order they run in.
+    pub fn all(&self) -> Vec<Script> {
+        let mut scripts = vec![];
+        Self::merge_into(&mut scripts, self.by_trigger.values().flatten().cloned().collect());
+        scripts
+    }
+
+    /// Number of distinct scripts registered, whatever triggers they're under.
+    pub fn len(&self) -> usize {
+        self.by_trigger
+            .values()
+            .flatten()
+            .map(|script| &script.name)
+            .unique()
+            .count()
+    }
+
+    /// Merges scripts into a list of scripts to run, e.g. those of a path already subscribed to, keeping one script per name
+    /// and the list in the order it runs in: by priority, highest first, then by name. Incoming scripts replace ones of the
+    /// same name, since they're from the more recent config.
+    pub fn merge_into(scripts: &mut Vec<Script>, incoming: Vec<Script>) {
+        for script in incoming {
+            match scripts.iter_mut().find(|existing| existing.name == script.name) {
+                Some(existing) => *existing = script,
+                None => scripts.push(script),
+            }
+        }
+        scripts.sort_by(|a, b| {
+            (Reverse(a.priority), &a.name).cmp(&(Reverse(b.priority), &b.name))
+        });
+    }
+
+    /// Splits scripts, in the order [`ScriptRegistry::merge_into`] keeps them in, into batches of equal priority.
+    /// Batches run one after the other, the scripts within a batch side by side.
+    pub fn priority_batches(scripts: &[Script]) -> Vec<Vec<Script>> {
+        scripts
+            .iter()
+            .group_by(|script| script.priority)
+            .into_iter()
+            .map(|(_, batch)| batch.cloned().collect())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripts::structs::{EventTrigger, Script, ScriptRegistry};
+    use notify::{event::RemoveKind, EventKind};
+    use std::path::Path;
+
+    fn script(name: &str, priority: i32) -> Script {
+        Script { priority, ..Script::for_test(name, Path::new("/watched")) }
+    }
+
+    #[test]
+    fn keeps_every_script_on_a_trigger_in_priority_order() {
+        let remove = EventTrigger::Kind(EventKind::Remove(RemoveKind::Any));
+        let mut registry = ScriptRegistry::new();
+        registry.register(remove, script("c", 0));
+        registry.register(remove, script("a", 0));
+        registry.register(remove, script("b", 10));
+        registry.register(remove, script("a", -1));
+
+        let names = |scripts: &[Script]| scripts.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+        let registered = registry.get(&remove);
+        assert_eq!(names(&registered), vec!["b", "c", "a"]);
+        assert_eq!(registry.len(), 3);
+        assert_eq!(
+            ScriptRegistry::priority_batches(&registered)
+                .iter()
+                .map(|batch| names(batch))
+                .collect::<Vec<_>>(),
+            vec![vec!["b"], vec!["c"], vec!["a"]]
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use super::structs::{ScriptConfig, EVENT_TRIGGERS};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, SingleOrVec},
+    schema_for, JsonSchema,
+};
+
+impl ScriptConfig {
+    /// The JSON schema of a config file, as checked in at scripts.schema.json and printed by `rusty-hooks schema`.
+    pub fn schema_json() -> String {
+        // serializing a schema can't fail, it's nothing but maps, strings and numbers
+        serde_json::to_string_pretty(&schema_for!(ScriptConfig)).unwrap_or_default()
+    }
+}
+
+/// Triggers are kept as plain strings in the config types, unknown ones being skipped when the scripts are cached, but the
+/// schema only allows the known ones.
+pub fn event_triggers_schema(gen: &mut SchemaGenerator) -> Schema {
+    let trigger = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(EVENT_TRIGGERS.iter().map(|trigger| (*trigger).into()).collect()),
+        ..Default::default()
+    };
+    let mut schema: SchemaObject = <Vec<String>>::json_schema(gen).into_object();
+    schema.array().items = Some(SingleOrVec::Single(Box::new(trigger.into())));
+    schema.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripts::structs::ScriptConfig;
+
+    #[test]
+    fn checked_in_schema_matches_the_config_types() {
+        let checked_in = include_str!("../../scripts.schema.json");
+        assert_eq!(
+            checked_in.trim_end(),
+            ScriptConfig::schema_json(),
+            "scripts.schema.json is out of date, regenerate it with `rusty-hooks schema > scripts.schema.json`"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use super::structs::{ConfigFormat, ScriptConfig, Scripts};
+use crate::errors::script_errors::script_error::{ScriptConfigError, ScriptError};
+use crate::utilities::path_key::PathKey;
+use itertools::Itertools;
+use log::{debug, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory of config fragments next to the main config file. Every fragment in it is merged into the config, in
+/// lexical order of their file names.
+pub const FRAGMENTS_DIR: &str = "scripts.d";
+
+impl Scripts {
+    /// Reads the whole config: the main config file, then whatever it includes, then the fragments in [`FRAGMENTS_DIR`],
+    /// merged into one in that order. Script names have to be unique across all of them.
+    pub fn read_config(config_path: &Path) -> Result<ScriptConfig, ScriptError> {
+        let mut merged = ScriptConfig {
+            scripts: vec![],
+            include: vec![],
+            logging: None,
+            telemetry: None,
+        };
+        let mut defined_in: HashMap<String, PathBuf> = HashMap::new();
+        for (file, config) in Self::read_config_files(config_path)? {
+            if file == config_path {
+                merged.logging = config.logging;
+            }
+            for script in config.scripts {
+                if let Some(first) = defined_in.get(&script.name) {
+                    return Err(ScriptConfigError::DuplicateScriptName(
+                        script.name,
+                        first.display().to_string(),
+                        file.display().to_string(),
+                    )
+                    .into());
+                }
+                defined_in.insert(script.name.clone(), file.clone());
+                merged.scripts.push(script);
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Every file the config is made up of, in the order they're merged. See [`Scripts::read_config`].
+    pub fn config_files(config_path: &Path) -> Result<Vec<PathBuf>, ScriptError> {
+        Ok(Self::read_config_files(config_path)?
+            .into_iter()
+            .map(|(file, _)| file)
+            .collect())
+    }
+
+    /// The directory fragments are picked up from, whether or not it exists.
+    pub fn fragments_dir(config_path: &Path) -> PathBuf {
+        config_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(FRAGMENTS_DIR)
+    }
+
+    /// Every file the config is made up of, parsed, in the order they're merged. Nothing is checked across files.
+    pub fn read_config_files(config_path: &Path) -> Result<Vec<(PathBuf, ScriptConfig)>, ScriptError> {
+        let mut visited: HashSet<PathKey> = HashSet::new();
+        let mut collected = vec![];
+        Self::collect_with_includes(config_path, &mut visited, &mut collected)?;
+        for fragment in Self::fragments(config_path)? {
+            Self::collect_with_includes(&fragment, &mut visited, &mut collected)?;
+        }
+        Ok(collected)
+    }
+
+    /// Reads a config file and, depth first, every file it includes. Files already read are skipped, which also keeps
+    /// include cycles from going round forever.
+    fn collect_with_includes(
+        file: &Path,
+        visited: &mut HashSet<PathKey>,
+        collected: &mut Vec<(PathBuf, ScriptConfig)>,
+    ) -> Result<(), ScriptError> {
+        if !visited.insert(PathKey::new(file)) {
+            warn!("config file {} is included more than once, only reading it the first time", file.display());
+            return Ok(());
+        }
+        let config = Self::read_config_file(file)?;
+        let includes = Self::resolve_includes(file, &config.include)?;
+        collected.push((file.to_path_buf(), config));
+        for included in includes {
+            Self::collect_with_includes(&included, visited, collected)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves the `include:` entries of a config file, relative to the file's directory. An entry can be a glob pattern,
+    /// whose matches are included in lexical order, while a literal path has to exist.
+    fn resolve_includes(file: &Path, include: &[String]) -> Result<Vec<PathBuf>, ScriptError> {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let mut resolved = vec![];
+        for entry in include {
+            let path = dir.join(entry);
+            let path_string = path.to_string_lossy();
+            if !path_string.contains(['*', '?', '[']) {
+                if !path.is_file() {
+                    return Err(ScriptConfigError::ConfigFileError(
+                        file.display().to_string(),
+                        format!("`{}` isn't a file", path.display()),
+                    )
+                    .into());
+                }
+                resolved.push(path);
+                continue;
+            }
+            let matches = glob::glob(&path_string).map_err(|e| {
+                ScriptConfigError::ConfigFileError(file.display().to_string(), e.to_string())
+            })?;
+            let matched = matches
+                .filter_map(|matched| matched.ok())
+                .filter(|path| path.is_file())
+                .sorted()
+                .collect_vec();
+            if matched.is_empty() {
+                debug!("include {} of {} doesn't match any files", entry, file.display());
+            }
+            resolved.extend(matched);
+        }
+        Ok(resolved)
+    }
+
+    /// The fragments in [`FRAGMENTS_DIR`], sorted by file name. There being no such directory is fine.
+    fn fragments(config_path: &Path) -> Result<Vec<PathBuf>, ScriptError> {
+        let fragments_dir = Self::fragments_dir(config_path);
+        if !fragments_dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let fragments = fragments_dir
+            .read_dir()
+            .map_err(ScriptError::IoError)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && Self::is_fragment(path))
+            .sorted()
+            .collect_vec();
+        Ok(fragments)
+    }
+
+    /// Whether a file in [`FRAGMENTS_DIR`] is read as a fragment, i.e. whether it's in one of the [`ConfigFormat`]s.
+    pub fn is_fragment(path: &Path) -> bool {
+        ConfigFormat::from_path(path).is_some()
+    }
+
+    /// Reads and parses a single config file in the format its extension names, without following its includes or
+    /// validating any of it.
+    pub fn read_config_file(file: &Path) -> Result<ScriptConfig, ScriptError> {
+        let format = ConfigFormat::from_path(file).ok_or_else(|| {
+            ScriptConfigError::ConfigFileError(
+                file.display().to_string(),
+                format!("unsupported format, expected one of .{}", ConfigFormat::EXTENSIONS.join(", .")),
+            )
+        })?;
+        let contents = fs::read_to_string(file).map_err(|e| {
+            ScriptConfigError::ConfigFileError(file.display().to_string(), e.to_string())
+        })?;
+        Ok(format.parse(file, &contents)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::script_errors::script_error::{ScriptConfigError, ScriptError};
+    use crate::scripts::structs::Scripts;
+    use std::fs;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    fn write_config(path: &Path, script_names: &[&str], include: &[&str]) {
+        let scripts = script_names
+            .iter()
+            .map(|name| {
+                format!(
+                    "  - name: {}\n    description: test\n    file_name: test.sh\n    watch_path: /tmp\n    enabled: true\n    run_delay: 0\n    event_triggers: [\"Create\"]\n    dependencies: []\n",
+                    name
+                )
+            })
+            .collect::<String>();
+        let include = include.iter().map(|path| format!("  - {}\n", path)).collect::<String>();
+        fs::write(path, format!("include:\n{}scripts:\n{}", include, scripts)).unwrap();
+    }
+
+    #[test]
+    fn merges_includes_then_fragments_in_lexical_order() {
+        let dir = TempDir::new("config").unwrap();
+        let config_path = dir.path().join("scripts.yml");
+        fs::create_dir(dir.path().join("scripts.d")).unwrap();
+        write_config(&config_path, &["main"], &["extra/*.yml"]);
+        fs::create_dir(dir.path().join("extra")).unwrap();
+        write_config(&dir.path().join("extra").join("more.yml"), &["included"], &[]);
+        write_config(&dir.path().join("scripts.d").join("20-b.yml"), &["b"], &[]);
+        write_config(&dir.path().join("scripts.d").join("10-a.yml"), &["a"], &[]);
+        fs::write(dir.path().join("scripts.d").join("notes.txt"), "not a fragment").unwrap();
+
+        let names = Scripts::read_config(&config_path)
+            .unwrap()
+            .scripts
+            .into_iter()
+            .map(|script| script.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["main", "included", "a", "b"]);
+
+        write_config(&dir.path().join("scripts.d").join("30-c.yml"), &["main"], &[]);
+        assert!(matches!(
+            Scripts::read_config(&config_path),
+            Err(ScriptError::ConfigError(ScriptConfigError::DuplicateScriptName(..)))
+        ));
+    }
+
+    #[test]
+    fn reads_every_format_alike_and_locates_parse_errors() {
+        let dir = TempDir::new("formats").unwrap();
+        let yaml = dir.path().join("scripts.yaml");
+        let toml = dir.path().join("scripts.toml");
+        let json = dir.path().join("scripts.json");
+        write_config(&yaml, &["same"], &[]);
+        fs::write(
+            &toml,
+            "[[scripts]]\nname = \"same\"\ndescription = \"test\"\nfile_name = \"test.sh\"\nwatch_path = \"/tmp\"\nenabled = true\nrun_delay = 0\nevent_triggers = [\"Create\"]\ndependencies = []\n",
+        )
+        .unwrap();
+        fs::write(
+            &json,
+            r#"{"scripts": [{"name": "same", "description": "test", "file_name": "test.sh", "watch_path": "/tmp", "enabled": true, "run_delay": 0, "event_triggers": ["Create"], "dependencies": []}]}"#,
+        )
+        .unwrap();
+
+        let parsed = [&yaml, &toml, &json]
+            .map(|path| format!("{:?}", Scripts::read_config(path).unwrap().scripts));
+        assert_eq!(parsed[0], parsed[1]);
+        assert_eq!(parsed[0], parsed[2]);
+
+        fs::write(&toml, "[[scripts]]\nname = \"broken\"\nenabled = maybe\n").unwrap();
+        assert!(matches!(
+            Scripts::read_config(&toml),
+            Err(ScriptError::ConfigError(ScriptConfigError::ParseError(_, 3, 11, _)))
+        ));
+        fs::write(&json, "{\n  \"scripts\": [\n    {\"name\": }\n  ]\n}").unwrap();
+        assert!(matches!(
+            Scripts::read_config(&json),
+            Err(ScriptError::ConfigError(ScriptConfigError::ParseError(_, 3, 14, _)))
+        ));
+    }
+}
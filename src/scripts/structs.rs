@@ -1,91 +1,257 @@
-use crate::utilities::traits::Utilities;
+use crate::logging::LoggingConfig;
+use crate::telemetry::TelemetryConfig;
+use crate::utilities::{path_key::PathKey, traits::Utilities};
+use super::schema::event_triggers_schema;
 use notify::EventKind;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Scripts {
-    pub scripts_by_event_triggers: ScriptsByEventTrigger,
+    pub registry: ScriptRegistry,
     pub watch_paths: Vec<PathBuf>,
+    /// How the watch path is watched, combined from all of its scripts.
+    pub watch_options: WatchOptions,
 }
 
-pub type ScriptsByEventTrigger = HashMap<EventKind, Vec<Script>>; // string identifies the event type, Vec<ScriptSchemas> are all scripts that should run on a given event
+/// How a watch path is watched. Scripts sharing a watch path share one watch, so it covers whatever any of them asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchOptions {
+    /// How deep below the watch path to watch, `None` meaning all the way down. The deepest any of its scripts asks for.
+    pub max_depth: Option<usize>,
+    /// Whether symlinked directories below the watch path are watched through. Followed if any of its scripts follows them.
+    pub follow_symlinks: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            max_depth: None,
+            follow_symlinks: true,
+        }
+    }
+}
+
+impl WatchOptions {
+    /// How deep rusty-hooks watches the directories below the watch path itself, one by one, rather than leaving it to
+    /// notify. notify's recursive watches always follow symlinks, so watch paths that don't are walked by hand throughout.
+    pub fn walked_depth(&self) -> Option<usize> {
+        match (self.max_depth, self.follow_symlinks) {
+            (None, false) => Some(usize::MAX),
+            (max_depth, _) => max_depth,
+        }
+    }
+}
+
+/// The scripts of a watch path, by the triggers they run on. Scripts are told apart by their unique name, so registering
+/// a script again under the same trigger replaces it, and each trigger's scripts are kept in the order they run in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptRegistry {
+    pub(super) by_trigger: HashMap<EventTrigger, Vec<Script>>,
+}
+
+/// Every trigger a script block's `event_triggers` can name, see [`EventTrigger::parse`].
+pub const EVENT_TRIGGERS: [&str; 7] = [
+    "Access", "Create", "Modify", "Remove", "Other", "MovedIn", "MovedOut",
+];
+
+/// What a script can be configured to run on, as named in a script block's `event_triggers`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventTrigger {
+    /// One of notify's own event kinds, e.g. `Modify` or `Remove`.
+    Kind(EventKind),
+    /// Something was moved into the watch path, from outside it or from another watch path.
+    MovedIn,
+    /// Something was moved out of the watch path, to outside it or to another watch path.
+    MovedOut,
+}
+
+pub type ExpandedBlock = (ScriptBlock, Vec<PathBuf>); // a script block along with the watch paths its watch_path expanded to, see ScriptBlock::expand_watch_path
+pub type ScriptsByWatchPath = HashMap<PathKey, Scripts>; // every watch path handled by the shared watcher, keyed as it is compared, mapped to the scripts configured for it
 
 impl Utilities for Scripts {}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ScriptJSON {
-    pub enabled: bool,
-    pub event_triggers: Vec<String>,
-    pub file_name: String,
-    pub watch_path: String,
-    pub run_delay: u8,
+/// Watch paths whose configuration differs between two loads of the config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptsDiff {
+    /// Watch paths only present in the new configuration.
+    pub added: Vec<PathKey>,
+    /// Watch paths only present in the old configuration.
+    pub removed: Vec<PathKey>,
+    /// Watch paths present in both, but with a different set of scripts.
+    pub updated: Vec<PathKey>,
+    /// Number of watch paths left exactly as they were.
+    pub unchanged: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// A script, and when to run it.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct ScriptBlock {
+    /// Script name, distinct from the script's file name. Has to be unique across every config file.
     pub name: String,
+    /// What the script does.
     pub description: String,
+    /// Name of the script file, which has to be in the same folder as the main config file.
     pub file_name: String,
+    /// Path to the directory or file to watch for changes. May use `~`, `$VAR`/`${VAR}` and glob patterns such as
+    /// `/mnt/disk*/downloads`, in which case every path it matches is watched.
     pub watch_path: String,
+    /// Whether or not to run this script.
     pub enabled: bool,
+    /// Seconds to wait after a change is detected before running the script.
     pub run_delay: u8,
+    /// Events that trigger the script to run.
+    #[schemars(schema_with = "event_triggers_schema")]
     pub event_triggers: Vec<String>,
+    /// Scripts that must complete successfully before running this script.
     pub dependencies: Vec<Option<String>>,
+    /// Set to false to only watch the watch path's direct children. Defaults to true.
+    #[serde(default)]
+    pub recursive: Option<bool>,
+    /// How many levels below the watch path to watch, 1 being its direct children. Unlimited by default.
+    #[serde(default)]
+    pub max_depth: Option<NonZeroUsize>,
+    /// Set to false to ignore events reached through symlinked directories below the watch path. Defaults to true.
+    /// The watch path itself is always resolved, even when it's a symlink.
+    #[serde(default)]
+    pub follow_symlinks: Option<bool>,
+    /// When several scripts run on the same event, the ones with a higher priority run, and finish, before the ones with a
+    /// lower priority start. Scripts of equal priority run side by side. Defaults to 0.
+    #[serde(default)]
+    pub priority: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ScriptYAML {
+impl ScriptBlock {
+    /// The depth this block's script watches to, `None` meaning unlimited. `recursive: false` wins over any `max_depth`.
+    pub fn depth_limit(&self) -> Option<usize> {
+        match self.recursive {
+            Some(false) => Some(1),
+            _ => self.max_depth.map(NonZeroUsize::get),
+        }
+    }
+}
+
+/// Defines how rusty-hooks should run your scripts. The same fields apply whether the config is written in YAML, TOML or
+/// JSON.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+#[schemars(title = "Scripts Specifications")]
+pub struct ScriptConfig {
+    /// The scripts to run.
+    #[serde(default)]
     pub scripts: Vec<ScriptBlock>,
+    /// Further config files to merge in, relative to this one. Glob patterns are allowed.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// How rusty-hooks logs. Only read from the main config file, when rusty-hooks starts.
+    #[serde(default)]
+    pub logging: Option<LoggingConfig>,
+    /// Where traces of processed events go. Only read from the main config file, when rusty-hooks starts.
+    #[serde(default)]
+    pub telemetry: Option<TelemetryConfig>,
+}
+
+/// Formats the config files can be written in, told apart by their extension. They all describe the same [`ScriptConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Script {
+    /// Unique across the whole config, see [`ScriptBlock::name`].
+    pub name: String,
     pub event_triggers: Vec<String>,
     pub file_path: PathBuf,
     pub file_name: String,
     pub failed: Option<bool>,
     pub run_delay: u8,
     pub watch_path: PathBuf,
+    /// Events deeper than this below the watch path don't trigger the script. `None` means unlimited.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Whether events reached through symlinked directories below the watch path trigger the script.
+    #[serde(default = "follows_symlinks_by_default")]
+    pub follow_symlinks: bool,
+    /// Scripts with a higher priority run, and finish, before scripts with a lower one start.
+    #[serde(default)]
+    pub priority: i32,
 }
 
-impl Utilities for Script {}
+fn follows_symlinks_by_default() -> bool {
+    WatchOptions::default().follow_symlinks
+}
 
-impl From<ScriptJSON> for Script {
-    fn from(json: ScriptJSON) -> Self {
-        let path_string = format!("./scripts/{}", json.file_name.clone());
-        let as_path = Path::new(&path_string).to_path_buf();
+impl Script {
+    /// Whether events this many levels below the watch path should trigger the script.
+    pub fn reaches(&self, depth: usize) -> bool {
+        self.max_depth.map_or(true, |max_depth| depth <= max_depth)
+    }
 
-        let watch_path = Path::new(&json.watch_path).to_path_buf();
+    /// Whether events reached through a symlinked directory below the watch path, if that's how this one was, should trigger the script.
+    pub fn follows(&self, through_symlink: bool) -> bool {
+        self.follow_symlinks || !through_symlink
+    }
+}
 
+impl Utilities for Script {}
+
+#[cfg(test)]
+impl Script {
+    /// A script at `./scripts/<name>.sh` on a watch path, run on removals, for tests to adjust with struct update syntax.
+    pub fn for_test(name: &str, watch_path: &Path) -> Self {
         Script {
-            event_triggers: json.event_triggers,
-            file_path: as_path,
-            file_name: json.file_name,
+            name: name.to_string(),
+            event_triggers: vec!["Remove".to_string()],
+            file_path: PathBuf::from(format!("./scripts/{}.sh", name)),
+            file_name: format!("{}.sh", name),
             failed: None,
-            run_delay: json.run_delay,
-            watch_path,
+            run_delay: 0,
+            watch_path: watch_path.to_path_buf(),
+            max_depth: None,
+            follow_symlinks: follows_symlinks_by_default(),
+            priority: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Scripts {
+    /// A watch path with no scripts registered yet, among the config's `watch_paths`, watched as `watch_options` say.
+    pub fn for_test(watch_paths: &[&Path], watch_options: WatchOptions) -> Self {
+        Scripts {
+            registry: ScriptRegistry::new(),
+            watch_paths: watch_paths.iter().map(|path| path.to_path_buf()).collect(),
+            watch_options,
         }
     }
 }
 
 impl From<ScriptBlock> for Script {
     fn from(block: ScriptBlock) -> Self {
+        let max_depth = block.depth_limit();
         let path_string = format!("./scripts/{}", block.file_name.clone());
         let as_path = Path::new(&path_string).to_path_buf();
 
         let watch_path = Path::new(&block.watch_path).to_path_buf();
 
         Script {
+            name: block.name,
             event_triggers: block.event_triggers,
             file_path: as_path,
             file_name: block.file_name,
             failed: None,
             run_delay: block.run_delay,
             watch_path,
+            max_depth,
+            follow_symlinks: block.follow_symlinks.unwrap_or(follows_symlinks_by_default()),
+            priority: block.priority.unwrap_or_default(),
         }
     }
 }
@@ -98,29 +264,44 @@ impl From<&ScriptBlock> for Script {
         let watch_path = Path::new(&yaml.watch_path).to_path_buf();
 
         Script {
+            name: yaml.name.clone(),
             event_triggers: yaml.event_triggers.clone(),
             file_path: as_path,
             file_name: yaml.file_name.clone(),
             failed: None,
             run_delay: yaml.run_delay,
             watch_path,
+            max_depth: yaml.depth_limit(),
+            follow_symlinks: yaml.follow_symlinks.unwrap_or(follows_symlinks_by_default()),
+            priority: yaml.priority.unwrap_or_default(),
         }
     }
 }
 
-impl From<&ScriptJSON> for Script {
-    fn from(json: &ScriptJSON) -> Self {
-        let path_string = format!("./scripts/{}", json.file_name.clone());
-        let as_path = Path::new(&path_string).to_path_buf();
+/// How bad a problem found by validating the config is.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The config won't load, or a script can never run.
+    Error,
+    /// The config loads, but probably doesn't do what was meant, e.g. a watch path that doesn't exist yet.
+    Warning,
+}
 
-        let watch_path = Path::new(&json.watch_path).to_path_buf();
-        Script {
-            event_triggers: json.event_triggers.clone(),
-            file_path: as_path,
-            file_name: json.file_name.clone(),
-            failed: None,
-            run_delay: json.run_delay,
-            watch_path,
-        }
-    }
+/// A problem found by validating the config, see [`ValidationReport`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    /// The config file the problem is in, if it's down to one.
+    pub file: Option<PathBuf>,
+    /// The script the problem is with, if it's down to one.
+    pub script: Option<String>,
+    pub message: String,
+}
+
+/// Every problem found by validating a config, without starting to watch anything.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub config_path: PathBuf,
+    pub problems: Vec<Problem>,
 }
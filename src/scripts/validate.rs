@@ -0,0 +1,305 @@
+use super::structs::{EventTrigger, Problem, ScriptBlock, ScriptConfig, Scripts, Severity, ValidationReport};
+use crate::watcher::structs::Watcher;
+use is_executable::IsExecutable;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+impl ValidationReport {
+    /// Loads the config the way startup does and collects every problem with it, rather than stopping at the first one.
+    pub fn for_config(config_path: &Path) -> Self {
+        let mut report = ValidationReport {
+            config_path: config_path.to_path_buf(),
+            problems: vec![],
+        };
+        let files = match Scripts::read_config_files(config_path) {
+            Ok(files) => files,
+            Err(e) => {
+                // a file that can't be parsed leaves nothing to check the rest of the config against
+                report.push(Severity::Error, None, None, e.to_string());
+                return report;
+            }
+        };
+        let script_dir = config_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        let blocks = files
+            .iter()
+            .flat_map(|(file, config)| config.scripts.iter().map(move |block| (file, block)))
+            .collect_vec();
+
+        report.check_names(&blocks);
+        for (file, block) in &blocks {
+            report.check_script_file(file, block, &script_dir);
+            report.check_triggers(file, block);
+            if block.enabled {
+                report.check_watch_path(file, block);
+            }
+        }
+        report.check_dependencies(&blocks);
+        report.check_logging(&files);
+        report
+    }
+
+    /// Whether the config would load, warnings aside.
+    pub fn is_valid(&self) -> bool {
+        !self
+            .problems
+            .iter()
+            .any(|problem| problem.severity == Severity::Error)
+    }
+
+    fn push(&mut self, severity: Severity, file: Option<&PathBuf>, block: Option<&ScriptBlock>, message: String) {
+        self.problems.push(Problem {
+            severity,
+            file: file.cloned(),
+            script: block.map(|block| block.name.clone()),
+            message,
+        });
+    }
+
+    fn check_names(&mut self, blocks: &[(&PathBuf, &ScriptBlock)]) {
+        let mut defined_in: HashMap<&str, &PathBuf> = HashMap::new();
+        for (file, block) in blocks {
+            match defined_in.get(block.name.as_str()) {
+                Some(first) => {
+                    let message = format!("script name is already used in {}", first.display());
+                    self.push(Severity::Error, Some(file), Some(block), message);
+                }
+                None => {
+                    defined_in.insert(&block.name, file);
+                }
+            }
+        }
+    }
+
+    /// Only the main config file's `logging` section is read, and the log4rs config it names has to exist.
+    fn check_logging(&mut self, files: &[(PathBuf, ScriptConfig)]) {
+        for (file, config) in files {
+            let Some(logging) = &config.logging else { continue };
+            if file != &self.config_path {
+                let message = "logging section is ignored, only the main config file's is read".to_string();
+                self.push(Severity::Warning, Some(file), None, message);
+                continue;
+            }
+            if let Some(log_config) = &logging.config {
+                let log_config = file.parent().unwrap_or(Path::new(".")).join(log_config);
+                if !log_config.is_file() {
+                    let message = format!("log config {} doesn't exist", log_config.display());
+                    self.push(Severity::Error, Some(file), None, message);
+                }
+            }
+        }
+    }
+
+    fn check_script_file(&mut self, file: &PathBuf, block: &ScriptBlock, script_dir: &Path) {
+        let script_path = script_dir.join(&block.file_name);
+        let message = match (script_path.is_file(), script_path.is_executable()) {
+            (false, _) => format!("script file {} doesn't exist", script_path.display()),
+            (true, false) => format!("script file {} isn't executable", script_path.display()),
+            (true, true) => return,
+        };
+        self.push(Severity::Error, Some(file), Some(block), message);
+    }
+
+    /// Unknown triggers are skipped when loading, and triggers no event ever gets past the watcher for never run their
+    /// scripts, see [`Watcher::reaches_scripts`]. A script with nothing but such triggers can never run at all.
+    fn check_triggers(&mut self, file: &PathBuf, block: &ScriptBlock) {
+        let mut reachable = 0;
+        for trigger in &block.event_triggers {
+            match EventTrigger::parse(trigger) {
+                None => {
+                    let message = format!("unknown event trigger `{}`", trigger);
+                    self.push(Severity::Error, Some(file), Some(block), message);
+                }
+                Some(parsed) if !Watcher::reaches_scripts(&parsed) => {
+                    let message = format!("event trigger `{}` never runs scripts, those events are ignored", trigger);
+                    self.push(Severity::Warning, Some(file), Some(block), message);
+                }
+                Some(_) => reachable += 1,
+            }
+        }
+        if block.enabled && reachable == 0 {
+            let message = "script can never run, none of its event triggers reach it".to_string();
+            self.push(Severity::Error, Some(file), Some(block), message);
+        }
+    }
+
+    /// Watch paths that don't exist yet only warrant a warning, since they're attached once they appear.
+    fn check_watch_path(&mut self, file: &PathBuf, block: &ScriptBlock) {
+        let watch_paths = match block.expand_watch_path() {
+            Ok(watch_paths) => watch_paths,
+            Err(e) => {
+                self.push(Severity::Error, Some(file), Some(block), e.to_string());
+                return;
+            }
+        };
+        if watch_paths.is_empty() {
+            let message = format!("watch path pattern {} doesn't match anything (yet)", block.watch_path);
+            self.push(Severity::Warning, Some(file), Some(block), message);
+        }
+        for watch_path in watch_paths {
+            let (severity, message) = match (watch_path.exists(), watch_path.is_dir() || watch_path.is_file()) {
+                (true, true) => continue,
+                (true, false) => (
+                    Severity::Error,
+                    format!("watch path {} is neither a directory nor a file", watch_path.display()),
+                ),
+                (false, _) => (
+                    Severity::Warning,
+                    format!("watch path {} doesn't exist (yet)", watch_path.display()),
+                ),
+            };
+            self.push(severity, Some(file), Some(block), message);
+        }
+    }
+
+    fn check_dependencies(&mut self, blocks: &[(&PathBuf, &ScriptBlock)]) {
+        let by_name: HashMap<&str, (&PathBuf, &ScriptBlock)> = blocks
+            .iter()
+            .map(|(file, block)| (block.name.as_str(), (*file, *block)))
+            .collect();
+        for (file, block) in blocks {
+            for dependency in block.dependencies.iter().flatten() {
+                if !by_name.contains_key(dependency.as_str()) {
+                    let message = format!("depends on unknown script `{}`", dependency);
+                    self.push(Severity::Error, Some(file), Some(block), message);
+                }
+            }
+        }
+        let mut reported: HashSet<Vec<&str>> = HashSet::new();
+        for (file, block) in blocks {
+            let Some(cycle) = Self::dependency_cycle(&block.name, &by_name) else {
+                continue;
+            };
+            // every script on a cycle finds it, so it's reported once, by the script it's first found from
+            let mut members = cycle.clone();
+            members.sort();
+            if reported.insert(members) {
+                let message = format!("dependency cycle: {}", cycle.iter().chain([&cycle[0]]).join(" -> "));
+                self.push(Severity::Error, Some(file), Some(block), message);
+            }
+        }
+    }
+
+    /// The cycle of dependencies leading from a script back to itself, if there is one.
+    fn dependency_cycle<'a>(
+        name: &'a str,
+        by_name: &HashMap<&'a str, (&PathBuf, &'a ScriptBlock)>,
+    ) -> Option<Vec<&'a str>> {
+        fn walk<'a>(
+            current: &'a str,
+            start: &'a str,
+            by_name: &HashMap<&'a str, (&PathBuf, &'a ScriptBlock)>,
+            path: &mut Vec<&'a str>,
+            visited: &mut HashSet<&'a str>,
+        ) -> bool {
+            let Some((_, block)) = by_name.get(current) else {
+                return false;
+            };
+            for dependency in block.dependencies.iter().flatten() {
+                if dependency == start {
+                    return true;
+                }
+                if visited.insert(dependency.as_str()) {
+                    path.push(dependency.as_str());
+                    if walk(dependency.as_str(), start, by_name, path, visited) {
+                        return true;
+                    }
+                    path.pop();
+                }
+            }
+            false
+        }
+        let mut path = vec![name];
+        let mut visited = HashSet::from([name]);
+        walk(name, name, by_name, &mut path, &mut visited).then_some(path)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: ", severity)?;
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        if let Some(script) = &self.script {
+            write!(f, "script `{}`: ", script)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "{}", problem)?;
+        }
+        let count = |severity| self.problems.iter().filter(|problem| problem.severity == severity).count();
+        write!(
+            f,
+            "{}: {} errors, {} warnings",
+            self.config_path.display(),
+            count(Severity::Error),
+            count(Severity::Warning)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scripts::structs::{Severity, ValidationReport};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempdir::TempDir;
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let dir = TempDir::new("validate").unwrap();
+        let script = dir.path().join("run.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.path().join("not_executable.sh"), "#!/bin/sh\n").unwrap();
+        let watch_path = dir.path().display();
+        let config = format!(
+            r#"scripts:
+  - {{name: fine, description: d, file_name: run.sh, watch_path: "{watch_path}", enabled: true, run_delay: 0, event_triggers: [Modify], dependencies: []}}
+  - {{name: a, description: d, file_name: not_executable.sh, watch_path: "{watch_path}", enabled: true, run_delay: 0, event_triggers: [Modify, Nonsense], dependencies: [b]}}
+  - {{name: b, description: d, file_name: missing.sh, watch_path: "{watch_path}/gone", enabled: true, run_delay: 0, event_triggers: [Create], dependencies: [a]}}
+  - {{name: fine, description: d, file_name: run.sh, watch_path: "{watch_path}", enabled: true, run_delay: 0, event_triggers: [Remove], dependencies: [nobody]}}
+"#
+        );
+        let config_path = dir.path().join("scripts.yml");
+        fs::write(&config_path, config).unwrap();
+
+        let report = ValidationReport::for_config(&config_path);
+        let messages = report
+            .problems
+            .iter()
+            .map(|problem| (problem.severity, problem.script.clone().unwrap_or_default(), problem.message.clone()))
+            .collect::<Vec<_>>();
+        let expected = [
+            (Severity::Error, "fine", "script name is already used in"),
+            (Severity::Error, "a", "isn't executable"),
+            (Severity::Error, "a", "unknown event trigger `Nonsense`"),
+            (Severity::Error, "b", "doesn't exist"),
+            (Severity::Warning, "b", "event trigger `Create` never runs scripts"),
+            (Severity::Error, "b", "script can never run"),
+            (Severity::Warning, "b", "gone doesn't exist (yet)"),
+            (Severity::Error, "fine", "depends on unknown script `nobody`"),
+            (Severity::Error, "a", "dependency cycle: a -> b -> a"),
+        ];
+        assert_eq!(messages.len(), expected.len(), "{:#?}", messages);
+        for ((severity, script, message), (expected_severity, expected_script, expected_message)) in
+            messages.iter().zip(expected)
+        {
+            assert_eq!(*severity, expected_severity);
+            assert_eq!(script, expected_script);
+            assert!(message.contains(expected_message), "{} doesn't contain {}", message, expected_message);
+        }
+        assert!(!report.is_valid());
+    }
+}
@@ -0,0 +1,5 @@
+mod init;
+mod signals;
+mod structs;
+mod unfinished;
+pub use structs::{Shutdown, ShutdownSignals, UnfinishedRuns, EXIT_SCRIPTS_KILLED, EXIT_TASK_FAILED};
@@ -0,0 +1,110 @@
+use super::structs::{DebounceGuard, Shutdown};
+use crate::runner::types::SpawnMessage;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::watch,
+    time::{sleep, sleep_until},
+};
+
+impl Shutdown {
+    pub fn new(grace_period: Duration, flush: bool) -> Self {
+        let (requested, _) = watch::channel(None);
+        Shutdown {
+            requested: Arc::new(requested),
+            grace_period,
+            flush,
+            debouncing: Arc::new(AtomicUsize::new(0)),
+            set_aside: Arc::new(Mutex::new(vec![])),
+            scripts_killed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Asks every task to wrap up. Asking again doesn't restart the grace period.
+    pub fn request(&self) {
+        self.requested.send_if_modified(|requested| match requested {
+            Some(_) => false,
+            None => {
+                *requested = Some(Instant::now());
+                true
+            }
+        });
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.borrow().is_some()
+    }
+
+    /// Resolves once shutdown is requested, straight away if it already has been.
+    pub async fn requested(&self) -> Instant {
+        let mut receiver = self.requested.subscribe();
+        // the sender lives as long as self does, so this can't fail
+        let requested = receiver.wait_for(|requested| requested.is_some()).await;
+        requested.ok().and_then(|requested| *requested).unwrap_or_else(Instant::now)
+    }
+
+    /// Resolves once the grace period after shutdown was requested has run out.
+    pub async fn grace_expired(&self) {
+        let requested_at = self.requested().await;
+        sleep_until((requested_at + self.grace_period).into()).await
+    }
+
+    pub fn start_debounce(&self) -> DebounceGuard {
+        self.debouncing.fetch_add(1, Ordering::SeqCst);
+        DebounceGuard {
+            debouncing: self.debouncing.clone(),
+        }
+    }
+
+    pub fn num_debouncing(&self) -> usize {
+        self.debouncing.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once every path that was waiting out its debounce has been queued or set aside, or the grace period runs
+    /// out. Their wait tasks do so on their own time after shutdown is requested, so they're waited on before whatever
+    /// was set aside is taken.
+    pub async fn debounced(&self) {
+        let drained = async {
+            while self.num_debouncing() > 0 {
+                sleep(Duration::from_millis(50)).await;
+            }
+        };
+        tokio::select! {
+            _ = drained => {},
+            _ = self.grace_expired() => {},
+        }
+    }
+
+    pub fn set_aside(&self, message: SpawnMessage) {
+        match self.set_aside.lock() {
+            Ok(mut set_aside) => set_aside.push(message),
+            Err(poisoned) => poisoned.into_inner().push(message),
+        }
+    }
+
+    pub fn take_set_aside(&self) -> Vec<SpawnMessage> {
+        match self.set_aside.lock() {
+            Ok(mut set_aside) => std::mem::take(&mut *set_aside),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        }
+    }
+
+    pub fn scripts_killed(&self) {
+        self.scripts_killed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn were_scripts_killed(&self) -> bool {
+        self.scripts_killed.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for DebounceGuard {
+    fn drop(&mut self) {
+        self.debouncing.fetch_sub(1, Ordering::SeqCst);
+    }
+}
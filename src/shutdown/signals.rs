@@ -0,0 +1,19 @@
+use super::structs::ShutdownSignals;
+use tokio::signal::unix::{signal, SignalKind};
+
+impl ShutdownSignals {
+    pub fn listen() -> std::io::Result<Self> {
+        Ok(ShutdownSignals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Waits for the next SIGTERM or SIGINT, returning its name.
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.terminate.recv() => "SIGTERM",
+            _ = self.interrupt.recv() => "SIGINT",
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::runner::types::SpawnMessage;
+use crate::scripts::structs::Script;
+use crate::watcher::structs::Rename;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{signal::unix::Signal, sync::watch};
+
+/// Exit code when a task stopped on its own, rather than because shutdown was asked for.
+pub const EXIT_TASK_FAILED: i32 = 1;
+/// Exit code when scripts were still running once the grace period ran out, and were killed.
+pub const EXIT_SCRIPTS_KILLED: i32 = 3;
+
+/// Shared between every task that has something to wrap up when rusty hooks is asked to stop. Once requested, no more
+/// events are taken in, paths still waiting out their debounce are either flushed to the runner or set aside, and
+/// scripts already running get until the grace period runs out to finish.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    /// When shutdown was requested, `None` until it is.
+    pub requested: Arc<watch::Sender<Option<Instant>>>,
+    /// How long running scripts get to finish once shutdown is requested.
+    pub grace_period: Duration,
+    /// Whether paths still waiting out their debounce have their scripts run on shutdown, instead of being set aside.
+    pub flush: bool,
+    /// Number of subscribed paths still waiting out their debounce.
+    pub debouncing: Arc<AtomicUsize>,
+    /// Script runs that didn't happen before shutting down, to be saved as [`UnfinishedRuns`].
+    pub set_aside: Arc<Mutex<Vec<SpawnMessage>>>,
+    pub scripts_killed: Arc<AtomicBool>,
+}
+
+/// Counts a path as waiting out its debounce for as long as it's held.
+#[derive(Debug)]
+pub struct DebounceGuard {
+    pub debouncing: Arc<AtomicUsize>,
+}
+
+/// SIGTERM and SIGINT, either of which asks rusty hooks to shut down.
+#[derive(Debug)]
+pub struct ShutdownSignals {
+    pub terminate: Signal,
+    pub interrupt: Signal,
+}
+
+/// Script runs set aside at shutdown, saved so the next start can pick them back up.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UnfinishedRuns {
+    pub runs: Vec<UnfinishedRun>,
+}
+
+/// A path and the scripts that were still to run on it. The scripts are as they were configured then, and are looked
+/// up again in whatever the config says when they're resumed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UnfinishedRun {
+    pub path: PathBuf,
+    pub scripts: Vec<Script>,
+    pub rename: Option<Rename>,
+}
@@ -0,0 +1,111 @@
+use super::structs::{UnfinishedRun, UnfinishedRuns};
+use crate::runner::types::SpawnMessage;
+use crate::scripts::structs::{Script, ScriptRegistry, ScriptsByWatchPath};
+use crate::telemetry::spans;
+use crate::utilities::{path_key::PathKey, traits::Utilities};
+use directories::BaseDirs;
+use log::{info, warn};
+use opentelemetry::{trace::TraceContextExt, KeyValue};
+use std::{fs, io::ErrorKind, path::{Path, PathBuf}};
+
+impl Utilities for UnfinishedRuns {}
+
+impl UnfinishedRuns {
+    /// Where unfinished runs are kept when --unfinished-file isn't provided. Named instances each keep their own.
+    pub fn default_path(instance: Option<&str>) -> Option<PathBuf> {
+        let file_name = match instance {
+            Some(instance) => format!("unfinished-{}.json", instance),
+            None => "unfinished.json".to_string(),
+        };
+        BaseDirs::new().map(|dirs| dirs.data_local_dir().join("rusty-hooks").join(file_name))
+    }
+
+    /// Collects runs set aside at shutdown, merging those for the same path the way their subscriptions would have been.
+    pub fn from_set_aside(set_aside: Vec<SpawnMessage>) -> Self {
+        let mut runs: Vec<UnfinishedRun> = vec![];
+        for (path, scripts, rename, _) in set_aside {
+            let path_hash = Self::path_hasher(&path);
+            match runs.iter_mut().find(|run| Self::path_hasher(&run.path) == path_hash) {
+                Some(run) => {
+                    ScriptRegistry::merge_into(&mut run.scripts, scripts);
+                    if rename.is_some() {
+                        run.rename = rename;
+                    }
+                }
+                None => runs.push(UnfinishedRun { path, scripts, rename }),
+            }
+        }
+        UnfinishedRuns { runs }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Writes the runs out for the next start to pick up, replacing whatever was there.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Reads the runs left by the last shutdown, removing the file so they're only ever picked up once, and turns them back
+    /// into messages for the runner. Scripts are looked up again in the current config, and dropped if they're no longer in
+    /// it, so resumed runs don't run scripts that have since been removed or changed.
+    pub fn resume(path: &Path, scripts_by_watch_path: &ScriptsByWatchPath) -> std::io::Result<Vec<SpawnMessage>> {
+        let unfinished: UnfinishedRuns = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        fs::remove_file(path)?;
+        let resumed = unfinished
+            .runs
+            .into_iter()
+            .filter_map(|run| {
+                let mut scripts = vec![];
+                let current = run.scripts.iter().filter_map(|script| Self::current_script(script, scripts_by_watch_path));
+                ScriptRegistry::merge_into(&mut scripts, current.collect());
+                if scripts.is_empty() {
+                    warn!("none of the scripts left to run on {} are configured anymore", run.path.display());
+                    return None;
+                }
+                let cx = spans::root("resume", vec![KeyValue::new("path", run.path.display().to_string())]);
+                cx.span().end();
+                Some((run.path, scripts, run.rename, spans::queued(&cx)))
+            })
+            .collect::<Vec<_>>();
+        info!("resuming {} script runs left unfinished by the last shutdown", resumed.len());
+        Ok(resumed)
+    }
+
+    fn current_script(script: &Script, scripts_by_watch_path: &ScriptsByWatchPath) -> Option<Script> {
+        scripts_by_watch_path
+            .get(&PathKey::new(&script.watch_path))
+            .and_then(|scripts| scripts.registry.find(&script.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::Context;
+
+    fn script(name: &str) -> Script {
+        Script::for_test(name, Path::new("/watched"))
+    }
+
+    #[test]
+    fn merges_runs_set_aside_for_the_same_path() {
+        let set_aside = vec![
+            (PathBuf::from("/watched/a"), vec![script("first")], None, Context::new()),
+            (PathBuf::from("/watched/b"), vec![script("first")], None, Context::new()),
+            (PathBuf::from("/watched/./a"), vec![script("second")], None, Context::new()),
+        ];
+        let unfinished = UnfinishedRuns::from_set_aside(set_aside);
+        assert_eq!(unfinished.runs.len(), 2);
+        let names = unfinished.runs[0].scripts.iter().map(|script| script.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["first", "second"]);
+    }
+}
@@ -0,0 +1,5 @@
+mod notify;
+mod structs;
+mod unit;
+pub use structs::{SystemdNotifier, UnitOptions, NOTIFY_ENV_VARS};
+pub use unit::STOP_TIMEOUT_MARGIN_SEC;
@@ -0,0 +1,99 @@
+use super::structs::SystemdNotifier;
+use crate::health_reporter::Health;
+use log::{debug, warn};
+use std::{env, os::unix::net::UnixDatagram, sync::Arc, time::Duration};
+use tokio::time::sleep;
+
+impl SystemdNotifier {
+    pub fn from_env() -> Self {
+        let socket = match env::var("NOTIFY_SOCKET") {
+            Ok(address) => match Self::connect(&address) {
+                Ok(socket) => Some(Arc::new(socket)),
+                Err(e) => {
+                    warn!("unable to connect to systemd's notify socket at {}: {}", address, e);
+                    None
+                }
+            },
+            Err(_) => None,
+        };
+        // the watchdog is only meant for us if it names our pid, or no pid at all
+        let watchdog_pid = env::var("WATCHDOG_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+        let watchdog_usec = match watchdog_pid {
+            Some(pid) if pid != std::process::id() => None,
+            _ => env::var("WATCHDOG_USEC").ok().and_then(|usec| usec.parse::<u64>().ok()),
+        };
+        SystemdNotifier {
+            socket,
+            watchdog_usec: watchdog_usec.filter(|usec| *usec > 0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Sends newline separated `KEY=value` assignments to systemd. Failing to is only logged, systemd will notice on its own.
+    pub fn notify(&self, state: &str) {
+        if let Some(socket) = &self.socket {
+            if let Err(e) = socket.send(state.as_bytes()) {
+                debug!("unable to notify systemd of {:?}: {}", state, e);
+            }
+        }
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1\nSTATUS=stopping");
+    }
+
+    /// Half the watchdog timeout, as sd_watchdog_enabled(3) recommends, or every 10 seconds if there's no watchdog and
+    /// only the status is being kept up to date.
+    pub fn ping_interval(&self) -> Duration {
+        match self.watchdog_usec {
+            Some(usec) => Duration::from_micros(usec / 2),
+            None => Duration::from_secs(10),
+        }
+    }
+
+    /// Waits for every task to start before sending `READY=1`, then keeps the status up to date and pets the watchdog for
+    /// as long as the health checks find rusty hooks alive. Once they don't, systemd is left to time it out and restart it.
+    pub async fn supervise(self, health: Health) {
+        health.until_started().await;
+        self.notify(&format!("READY=1\nSTATUS={}", health.summary()));
+        loop {
+            sleep(self.ping_interval()).await;
+            let report = health.check();
+            let mut state = format!("STATUS={}", health.summary());
+            match (report.live, self.watchdog_usec) {
+                (true, Some(_)) => state.push_str("\nWATCHDOG=1"),
+                (true, None) => {}
+                (false, _) => {
+                    let failing = report.checks.iter().filter(|check| !check.ok).map(|check| check.name.as_str());
+                    warn!("not petting systemd's watchdog, failing: {}", failing.collect::<Vec<_>>().join(", "));
+                }
+            }
+            self.notify(&state);
+        }
+    }
+
+    /// Addresses starting with `@` are in the abstract namespace, which only exists on linux.
+    fn connect(address: &str) -> std::io::Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        match address.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.connect_addr(&address)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "abstract sockets are linux only",
+                ))
+            }
+            None => socket.connect(address)?,
+        }
+        Ok(socket)
+    }
+}
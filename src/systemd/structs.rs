@@ -0,0 +1,36 @@
+use std::{os::unix::net::UnixDatagram, path::PathBuf, sync::Arc};
+
+/// Environment variables systemd hands the service to talk back to it with. They're kept from the scripts it runs.
+pub const NOTIFY_ENV_VARS: [&str; 3] = ["NOTIFY_SOCKET", "WATCHDOG_USEC", "WATCHDOG_PID"];
+
+/// Tells systemd how rusty hooks is doing, following sd_notify(3), over the socket in `NOTIFY_SOCKET`. Does nothing when
+/// rusty hooks wasn't started by systemd as a `Type=notify` service.
+#[derive(Debug, Clone, Default)]
+pub struct SystemdNotifier {
+    pub socket: Option<Arc<UnixDatagram>>,
+    /// How often systemd expects a `WATCHDOG=1`, `None` if it isn't watching.
+    pub watchdog_usec: Option<u64>,
+}
+
+/// What `rusty-hooks systemd-unit` writes the unit file for.
+#[derive(Debug, Clone)]
+pub struct UnitOptions {
+    pub executable: PathBuf,
+    pub config: PathBuf,
+    pub working_directory: PathBuf,
+    pub http_address: Option<String>,
+    pub health_socket: Option<PathBuf>,
+    pub control_socket: Option<PathBuf>,
+    pub pid_file: Option<PathBuf>,
+    pub instance: Option<String>,
+    pub log_config: Option<PathBuf>,
+    pub log_level: Option<String>,
+    pub log_sink: Vec<String>,
+    pub syslog_socket: Option<PathBuf>,
+    pub syslog_facility: Option<String>,
+    pub shutdown_grace_period: u64,
+    pub flush_on_shutdown: bool,
+    /// Written for `systemctl --user`, rather than as a system service run as `user`.
+    pub user_unit: bool,
+    pub user: Option<String>,
+}
@@ -0,0 +1,175 @@
+use super::structs::UnitOptions;
+use crate::errors::command_line_errors::enums::CommandLineError;
+use crate::utilities::cli_args::CommandLineArgs;
+use std::{env, fmt::Write, path::Path};
+
+/// Seconds systemd waits for a `WATCHDOG=1` before restarting the service.
+pub const WATCHDOG_SEC: u64 = 60;
+/// Seconds systemd waits for rusty hooks to stop on top of its shutdown grace period, before killing it.
+pub const STOP_TIMEOUT_MARGIN_SEC: u64 = 15;
+
+impl UnitOptions {
+    /// Points the unit at this executable and the config it would load, resolved to absolute paths since the unit won't
+    /// be started from here.
+    pub fn new(args: &CommandLineArgs, user_unit: bool) -> Result<Self, CommandLineError> {
+        Ok(UnitOptions {
+            executable: env::current_exe()?,
+            config: args.get_config_path()?,
+            working_directory: env::current_dir()?,
+            http_address: args.http_address.map(|address| address.to_string()),
+            health_socket: args.health_socket.as_ref().map(|path| path.canonicalize().unwrap_or(path.clone())),
+            control_socket: args.control_socket.as_ref().map(|path| path.canonicalize().unwrap_or(path.clone())),
+            pid_file: args.pid_file.as_ref().map(|path| path.canonicalize().unwrap_or(path.clone())),
+            instance: args.instance.clone(),
+            log_config: args.log_config.as_ref().map(|path| path.canonicalize().unwrap_or(path.clone())),
+            log_level: args.log_level.map(|level| level.to_string().to_lowercase()),
+            log_sink: args.log_sink.iter().map(|sink| sink.to_string()).collect(),
+            syslog_socket: args.syslog_socket.clone(),
+            syslog_facility: args.syslog_facility.clone(),
+            shutdown_grace_period: args.shutdown_grace_period,
+            flush_on_shutdown: args.flush_on_shutdown,
+            user_unit,
+            user: env::var("USER").ok().filter(|user| !user.is_empty() && user != "root"),
+        })
+    }
+
+    /// A `Type=notify` unit with the watchdog on, sandboxed as far as it can be without knowing what the scripts touch:
+    /// the system's own files are read-only, but home directories and the rest stay writable for them.
+    pub fn render(&self) -> String {
+        let mut exec_start = format!(
+            "{} --config {}",
+            quote(&self.executable),
+            quote(&self.config)
+        );
+        if let Some(address) = &self.http_address {
+            let _ = write!(exec_start, " --http-address {}", address);
+        }
+        if let Some(socket) = &self.health_socket {
+            let _ = write!(exec_start, " --health-socket {}", quote(socket));
+        }
+        if let Some(socket) = &self.control_socket {
+            let _ = write!(exec_start, " --control-socket {}", quote(socket));
+        }
+        if let Some(pid_file) = &self.pid_file {
+            let _ = write!(exec_start, " --pid-file {}", quote(pid_file));
+        }
+        if let Some(instance) = &self.instance {
+            let _ = write!(exec_start, " --instance {}", instance);
+        }
+        if let Some(log_config) = &self.log_config {
+            let _ = write!(exec_start, " --log-config {}", quote(log_config));
+        }
+        if let Some(level) = &self.log_level {
+            let _ = write!(exec_start, " --log-level {}", level);
+        }
+        if !self.log_sink.is_empty() {
+            let _ = write!(exec_start, " --log-sink {}", self.log_sink.join(","));
+        }
+        if let Some(socket) = &self.syslog_socket {
+            let _ = write!(exec_start, " --syslog-socket {}", quote(socket));
+        }
+        if let Some(facility) = &self.syslog_facility {
+            let _ = write!(exec_start, " --syslog-facility {}", facility);
+        }
+        let _ = write!(exec_start, " --shutdown-grace-period {}", self.shutdown_grace_period);
+        if self.flush_on_shutdown {
+            let _ = write!(exec_start, " --flush-on-shutdown");
+        }
+        let mut unit = String::new();
+        let _ = writeln!(unit, "[Unit]");
+        let _ = writeln!(unit, "Description={}", env!("CARGO_PKG_DESCRIPTION"));
+        let _ = writeln!(unit, "Documentation={}", env!("CARGO_PKG_HOMEPAGE"));
+        let _ = writeln!(unit, "After=local-fs.target");
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Service]");
+        let _ = writeln!(unit, "Type=notify");
+        let _ = writeln!(unit, "NotifyAccess=main");
+        let _ = writeln!(unit, "ExecStart={}", exec_start);
+        // the scripts are looked up relative to it
+        let _ = writeln!(unit, "WorkingDirectory={}", quote(&self.working_directory));
+        if let (false, Some(user)) = (self.user_unit, &self.user) {
+            let _ = writeln!(unit, "User={}", user);
+        }
+        let _ = writeln!(unit, "WatchdogSec={}", WATCHDOG_SEC);
+        // room for the scripts' grace period, and for setting aside whatever didn't run
+        let _ = writeln!(unit, "TimeoutStopSec={}", self.shutdown_grace_period + STOP_TIMEOUT_MARGIN_SEC);
+        let _ = writeln!(unit, "Restart=on-failure");
+        let _ = writeln!(unit, "RestartSec=5");
+        for hardening in [
+            "NoNewPrivileges=yes",
+            "ProtectSystem=full",
+            "ProtectKernelTunables=yes",
+            "ProtectKernelModules=yes",
+            "ProtectKernelLogs=yes",
+            "ProtectControlGroups=yes",
+            "ProtectClock=yes",
+            "ProtectHostname=yes",
+            "RestrictSUIDSGID=yes",
+            "RestrictRealtime=yes",
+            "RestrictNamespaces=yes",
+            "RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6",
+            "LockPersonality=yes",
+            "SystemCallArchitectures=native",
+        ] {
+            let _ = writeln!(unit, "{}", hardening);
+        }
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Install]");
+        let _ = writeln!(
+            unit,
+            "WantedBy={}",
+            match self.user_unit {
+                true => "default.target",
+                false => "multi-user.target",
+            }
+        );
+        unit
+    }
+}
+
+/// Quotes a path for a unit file if it has to be, i.e. if it has whitespace, quotes or backslashes in it.
+fn quote(path: &Path) -> String {
+    let path = path.display().to_string();
+    match path.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        true => format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"")),
+        false => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn renders_a_notify_unit_for_the_config() {
+        let options = UnitOptions {
+            executable: PathBuf::from("/usr/local/bin/rusty-hooks"),
+            config: PathBuf::from("/home/me/my scripts/scripts.yml"),
+            working_directory: PathBuf::from("/home/me"),
+            http_address: Some("127.0.0.1:9464".to_string()),
+            health_socket: None,
+            control_socket: None,
+            pid_file: None,
+            instance: None,
+            log_config: None,
+            log_level: None,
+            log_sink: vec![],
+            syslog_socket: None,
+            syslog_facility: None,
+            shutdown_grace_period: 30,
+            flush_on_shutdown: false,
+            user_unit: false,
+            user: Some("me".to_string()),
+        };
+        let unit = options.render();
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains(&format!("WatchdogSec={}\n", WATCHDOG_SEC)));
+        assert!(unit.contains(
+            "ExecStart=/usr/local/bin/rusty-hooks --config \"/home/me/my scripts/scripts.yml\" --http-address 127.0.0.1:9464 --shutdown-grace-period 30\n"
+        ));
+        assert!(unit.contains("TimeoutStopSec=45\n"));
+        assert!(unit.contains("User=me\n"));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+    }
+}
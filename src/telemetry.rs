@@ -0,0 +1,4 @@
+mod init;
+pub mod spans;
+mod structs;
+pub use structs::{Telemetry, TelemetryConfig, TraceExporter};
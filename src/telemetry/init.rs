@@ -0,0 +1,69 @@
+use super::structs::{Telemetry, TelemetryConfig, TraceExporter};
+use crate::errors::telemetry_errors::telemetry_error::TelemetryError;
+use crate::scripts::structs::Scripts;
+use crate::utilities::cli_args::CommandLineArgs;
+use log::info;
+use opentelemetry::{
+    global,
+    sdk::{trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use std::path::Path;
+
+impl Telemetry {
+    /// Installs the exporter picked by --traces, or else the config file's `telemetry.traces`, sending to --otlp-endpoint,
+    /// or else `telemetry.otlp_endpoint`. See [`Telemetry::install`].
+    pub fn init(args: &CommandLineArgs, config_path: Option<&Path>) -> Result<(), TelemetryError> {
+        let section = config_path.map(Self::section).unwrap_or_default();
+        let exporter = args.traces.or(section.traces).unwrap_or(TraceExporter::None);
+        let otlp_endpoint = args.otlp_endpoint.as_deref().or(section.otlp_endpoint.as_deref());
+        Self::install(exporter, otlp_endpoint)
+    }
+
+    /// Installs the exporter as the global tracer provider. Until this is called, or with [`TraceExporter::None`], spans are
+    /// no-ops. The OTLP exporter is batched onto the current tokio runtime, and when no endpoint is provided falls back to
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT`, then to `http://localhost:4317`.
+    pub fn install(exporter: TraceExporter, otlp_endpoint: Option<&str>) -> Result<(), TelemetryError> {
+        let config = trace::config().with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            env!("CARGO_PKG_NAME"),
+        )]));
+        match exporter {
+            TraceExporter::None => return Ok(()),
+            TraceExporter::Otlp => {
+                let mut otlp_exporter = opentelemetry_otlp::new_exporter().tonic();
+                if let Some(endpoint) = otlp_endpoint {
+                    otlp_exporter = otlp_exporter.with_endpoint(endpoint);
+                }
+                opentelemetry_otlp::new_pipeline()
+                    .tracing()
+                    .with_exporter(otlp_exporter)
+                    .with_trace_config(config)
+                    .install_batch(opentelemetry::runtime::Tokio)?;
+            }
+            TraceExporter::Stdout => {
+                let provider = trace::TracerProvider::builder()
+                    .with_simple_exporter(opentelemetry_stdout::SpanExporter::default())
+                    .with_config(config)
+                    .build();
+                global::set_tracer_provider(provider);
+            }
+        }
+        info!("exporting traces to {:?}", exporter);
+        Ok(())
+    }
+
+    /// Flushes spans that haven't been exported yet. Called on the way out, since batched spans are lost otherwise.
+    pub fn shutdown() {
+        global::shutdown_tracer_provider();
+    }
+
+    /// The main config file's `telemetry` section. Included files and fragments don't get a say.
+    fn section(config_path: &Path) -> TelemetryConfig {
+        Scripts::read_config_file(config_path)
+            .ok()
+            .and_then(|config| config.telemetry)
+            .unwrap_or_default()
+    }
+}
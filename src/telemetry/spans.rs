@@ -0,0 +1,119 @@
+use opentelemetry::{
+    global::{self, BoxedTracer},
+    trace::{Span, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use std::time::SystemTime;
+
+/// Name of the tracer every span is started from.
+pub const TRACER_NAME: &str = "rusty-hooks";
+
+/// When a message was put on a channel. Carried along in the message's trace context, so the time it spent waiting to be
+/// picked up can be recorded once it is. See [`queued`] and [`dequeued`].
+#[derive(Debug, Clone, Copy)]
+struct QueuedAt(SystemTime);
+
+pub fn tracer() -> BoxedTracer {
+    global::tracer(TRACER_NAME)
+}
+
+/// Starts a new trace, returning the context its spans are started under. See [`child`] for ending it.
+pub fn root(name: &'static str, attributes: Vec<KeyValue>) -> Context {
+    child(&Context::new(), name, attributes)
+}
+
+/// Starts a span under the one in the provided context, returning the context with the new span in it. The span has to
+/// be ended with `.span().end()`: contexts are carried along in channel messages, and broadcast channels hold on to those
+/// long after they're received, so waiting for the last clone to drop could take forever.
+pub fn child(cx: &Context, name: &'static str, attributes: Vec<KeyValue>) -> Context {
+    child_of(&tracer(), cx, name, attributes)
+}
+
+/// [`child`], started from the provided tracer rather than the global one.
+fn child_of<T>(tracer: &T, cx: &Context, name: &'static str, attributes: Vec<KeyValue>) -> Context
+where
+    T: Tracer,
+    T::Span: Send + Sync + 'static,
+{
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start_with_context(tracer, cx);
+    cx.with_span(span)
+}
+
+/// Stamps a context with the time its message is put on a channel.
+pub fn queued(cx: &Context) -> Context {
+    cx.with_value(QueuedAt(SystemTime::now()))
+}
+
+/// Records the time a message spent on a channel, from when its context was [`queued`] until now, as a span of its own.
+pub fn dequeued(cx: &Context, name: &'static str, attributes: Vec<KeyValue>) {
+    dequeued_from(&tracer(), cx, name, attributes)
+}
+
+/// [`dequeued`], started from the provided tracer rather than the global one.
+fn dequeued_from<T: Tracer>(tracer: &T, cx: &Context, name: &'static str, attributes: Vec<KeyValue>) {
+    let Some(QueuedAt(queued_at)) = cx.get::<QueuedAt>() else {
+        return;
+    };
+    tracer
+        .span_builder(name)
+        .with_start_time(*queued_at)
+        .with_attributes(attributes)
+        .start_with_context(tracer, cx)
+        .end();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::telemetry::spans;
+    use futures::future::{self, BoxFuture};
+    use opentelemetry::{
+        sdk::{
+            export::trace::{ExportResult, SpanData, SpanExporter},
+            trace::TracerProvider,
+        },
+        trace::{TraceContextExt, TracerProvider as _},
+        Context,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, Default)]
+    struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for Collected {
+        fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+            self.0.lock().unwrap().extend(batch);
+            Box::pin(future::ready(Ok(())))
+        }
+    }
+
+    #[test]
+    fn queued_messages_stay_in_the_trace_they_were_sent_from() {
+        let collected = Collected::default();
+        // a provider of its own, leaving the global one to the rest of the process
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(collected.clone())
+            .build();
+        let tracer = provider.tracer(spans::TRACER_NAME);
+        let root = spans::child_of(&tracer, &Context::new(), "evaluate_event", vec![]);
+        let queued = spans::queued(&root);
+        spans::dequeued_from(&tracer, &queued, "spawn_queue", vec![]);
+        spans::dequeued_from(&tracer, &root, "never_queued", vec![]);
+        spans::child_of(&tracer, &queued, "run_script", vec![]).span().end();
+        root.span().end();
+        // flushes whatever the exporter hasn't been handed yet
+        provider.force_flush();
+
+        let collected = collected.0.lock().unwrap();
+        let find = |name| collected.iter().find(|span| span.name == name);
+        let root = find("evaluate_event").unwrap();
+        for name in ["spawn_queue", "run_script"] {
+            let span = find(name).unwrap();
+            assert_eq!(span.parent_span_id, root.span_context.span_id());
+            assert_eq!(span.span_context.trace_id(), root.span_context.trace_id());
+        }
+        assert!(find("never_queued").is_none());
+    }
+}
@@ -0,0 +1,29 @@
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where traces of processed events are sent, if anywhere.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceExporter {
+    /// don't record traces
+    None,
+    /// export to an OTLP collector over gRPC
+    Otlp,
+    /// print finished spans to stdout, for trying things out without a collector
+    Stdout,
+}
+
+/// Where traces of processed events go. Only the main config file's is read, once, when rusty-hooks starts.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct TelemetryConfig {
+    /// Where to send traces, unless --traces is provided. Defaults to none.
+    pub traces: Option<TraceExporter>,
+    /// OTLP collector to send traces to with `traces: otlp`, unless --otlp-endpoint is provided. Defaults to
+    /// OTEL_EXPORTER_OTLP_ENDPOINT, then http://localhost:4317.
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Sets up, and tears down, the global tracer provider every span is started from. See [`super::spans`].
+#[derive(Debug)]
+pub struct Telemetry;
@@ -1,4 +1,6 @@
 pub mod cli_args;
+pub mod lag_counter;
+pub mod path_key;
 pub mod set_process_lockfile;
 pub mod timer;
 pub mod traits;
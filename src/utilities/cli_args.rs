@@ -1,38 +1,302 @@
-use super::traits::Utilities;
+use super::{path_key::PathKey, thread_types::ChannelCapacities, traits::Utilities};
+use crate::control::Target;
 use crate::errors::command_line_errors::enums::CommandLineError;
-use clap::Parser;
+use crate::health_reporter::HealthEndpoint;
+use crate::logging::LogSink;
+use crate::shutdown::UnfinishedRuns;
+use crate::systemd::STOP_TIMEOUT_MARGIN_SEC;
+use crate::scripts::structs::ConfigFormat;
+use crate::telemetry::TraceExporter;
+use crate::utilities::set_process_lockfile::Lockfile;
+use clap::{Args, Parser, Subcommand};
 use itertools::Itertools;
 use log::{debug, LevelFilter};
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Name, less its extension, of the configuration file looked for in the script folder when --config isn't provided.
+pub const DEFAULT_CONFIG_FILE_STEM: &str = "scripts";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct CommandLineArgs {
-    /// level of logging
-    #[arg(short, long, default_value = "error")]
-    pub log_level: LevelFilter,
-    /// path to configuration file - required
-    #[arg(short, long)]
-    pub script_folder: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// level of the main log. defaults to the config file's logging.level, then info
+    #[arg(short, long, env = "RUSTY_HOOKS_LOG_LEVEL")]
+    pub log_level: Option<LevelFilter>,
+    /// log4rs config file to log with instead of the built-in config, which logs to stdout and to files in
+    /// rusty-hooks/logs in the local data directory. takes precedence over the config file's logging.config
+    #[arg(long, env = "RUSTY_HOOKS_LOG_CONFIG")]
+    pub log_config: Option<PathBuf>,
+    /// where else the built-in config logs to, e.g. --log-sink journald,syslog. takes precedence over the config file's
+    /// logging.sinks
+    #[arg(long, value_enum, value_delimiter = ',', env = "RUSTY_HOOKS_LOG_SINK")]
+    pub log_sink: Vec<LogSink>,
+    /// unix socket the syslog sink sends to. defaults to the first of /dev/log, /var/run/syslog and /var/run/log there is
+    #[arg(long, env = "RUSTY_HOOKS_SYSLOG_SOCKET")]
+    pub syslog_socket: Option<PathBuf>,
+    /// syslog facility to log as with the syslog sink, e.g. user or local0. defaults to daemon
+    #[arg(long, env = "RUSTY_HOOKS_SYSLOG_FACILITY")]
+    pub syslog_facility: Option<String>,
+    /// folder holding the scripts and their configuration file, scripts.yml/.yaml/.toml/.json - required unless --config is provided
+    #[arg(short, long, required_unless_present = "config")]
+    pub script_folder: Option<PathBuf>,
+    /// path to the configuration file, the scripts being in the same folder. Takes precedence over --script-folder
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+    /// number of file system events buffered before the oldest are dropped. dropped events trigger a rescan of the watch paths
+    #[arg(long, default_value_t = 1024)]
+    pub event_channel_capacity: usize,
+    /// number of subscriptions, script runs and unsubscriptions buffered between tasks
+    #[arg(long, default_value_t = 256)]
+    pub channel_capacity: usize,
+    /// seconds between checks for watch paths that have appeared or disappeared, e.g. drives being mounted
+    #[arg(long, default_value_t = 10)]
+    pub watch_path_poll_interval: u64,
+    /// where to send traces of processed events, each following an event through its debounce to the scripts it ran. defaults to the config file's telemetry.traces, then none
+    #[arg(long, value_enum, env = "RUSTY_HOOKS_TRACES")]
+    pub traces: Option<TraceExporter>,
+    /// OTLP collector to send traces to with --traces otlp. defaults to the config file's telemetry.otlp_endpoint, then OTEL_EXPORTER_OTLP_ENDPOINT, then http://localhost:4317
+    #[arg(long, env = "RUSTY_HOOKS_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// address to serve Prometheus metrics at /metrics and health checks at /healthz and /readyz, e.g. 127.0.0.1:9464. not served unless provided
+    #[arg(long, env = "RUSTY_HOOKS_HTTP_ADDRESS", global = true)]
+    pub http_address: Option<SocketAddr>,
+    /// unix socket to serve the same metrics and health checks on as --http-address
+    #[arg(long, env = "RUSTY_HOOKS_HEALTH_SOCKET", global = true)]
+    pub health_socket: Option<PathBuf>,
+    /// seconds a channel can go without being received from, while messages wait on it, before rusty hooks counts as dead
+    #[arg(long, default_value_t = 300)]
+    pub queue_stall_timeout: u64,
+    /// seconds running scripts get to finish on SIGTERM or SIGINT before they're killed
+    #[arg(long, default_value_t = 30, env = "RUSTY_HOOKS_SHUTDOWN_GRACE_PERIOD")]
+    pub shutdown_grace_period: u64,
+    /// on shutdown, run the scripts of paths still waiting out their debounce instead of setting them aside for next time
+    #[arg(long, env = "RUSTY_HOOKS_FLUSH_ON_SHUTDOWN")]
+    pub flush_on_shutdown: bool,
+    /// where script runs that didn't happen before shutting down are saved, to be picked up on the next start. defaults to
+    /// rusty-hooks/unfinished.json in the local data directory, e.g. ~/.local/share
+    #[arg(long, env = "RUSTY_HOOKS_UNFINISHED_FILE")]
+    pub unfinished_file: Option<PathBuf>,
+    /// unix socket taking the list, pause, resume, trigger, flush and cancel commands. defaults to the pid file's path,
+    /// ending in .sock instead
+    #[arg(long, env = "RUSTY_HOOKS_CONTROL_SOCKET", global = true)]
+    pub control_socket: Option<PathBuf>,
+    /// pid file locked for as long as rusty hooks runs, so that only one instance runs at a time. defaults to
+    /// rusty-hooks/rusty-hooks.pid in $XDG_RUNTIME_DIR, or else the local data directory
+    #[arg(long, env = "RUSTY_HOOKS_PID_FILE", global = true)]
+    pub pid_file: Option<PathBuf>,
+    /// name of this instance, so that instances watching for different configs can run side by side. each gets its own
+    /// default pid file, control socket and unfinished file, e.g. rusty-hooks-<name>.pid
+    #[arg(long, env = "RUSTY_HOOKS_INSTANCE", global = true, value_parser = parse_instance_name)]
+    pub instance: Option<String>,
+    /// run in the background, exiting once rusty hooks is up. it stays in the current directory, which relative paths
+    /// given here are found relative to
+    #[arg(long)]
+    pub daemon: bool,
+    /// file the daemon's stdout and stderr are appended to with --daemon or `restart`, instead of being discarded
+    #[arg(long, env = "RUSTY_HOOKS_DAEMON_OUTPUT", global = true)]
+    pub daemon_output: Option<PathBuf>,
+}
+
+/// Things rusty-hooks can do instead of watching.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// print the JSON schema of the configuration file
+    Schema,
+    /// check the configuration for problems without watching anything. exits non-zero if it wouldn't load
+    Validate {
+        /// print the problems found as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// ask a running instance whether it's ready, over --health-socket or else --http-address. exits 0 if it is, 1 if it
+    /// isn't and 2 if it can't be reached
+    Status,
+    /// print a systemd unit file that runs rusty-hooks as it's being run now, with the same --config, --http-address and
+    /// --health-socket. e.g. `rusty-hooks -c scripts.yml systemd-unit > /etc/systemd/system/rusty-hooks.service`
+    SystemdUnit {
+        /// write it for `systemctl --user`, instead of as a system service run as the current user
+        #[arg(long)]
+        user: bool,
+    },
+    /// list a running instance's watch paths and scripts, and the runs it's debouncing, has queued or is running, over
+    /// its control socket
+    List {
+        /// print the listing as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// stop a script, or every script of a watch path, from running until it's resumed. paths are still debounced
+    /// meanwhile, and runs whose timers run out while paused leave the paused scripts out
+    Pause(TargetArgs),
+    /// let a paused script, or watch path, run again
+    Resume(TargetArgs),
+    /// run a script on a path now, through the same runner as scripts whose timers ran out. runs even if it's paused
+    Trigger {
+        /// name of the script, as configured
+        script: String,
+        /// path to hand the script, within one of the watch paths it's configured for
+        path: PathBuf,
+    },
+    /// run out every pending timer now, so their scripts run without waiting for things to settle
+    Flush,
+    /// cancel a run, whether it's debouncing, queued or running. running scripts are killed. see `list` for run ids
+    Cancel {
+        run_id: u64,
+    },
+    /// stop the instance holding the lock on the pid file with SIGTERM, waiting for it to exit. exits 0 once it has, or if
+    /// it wasn't running, and 1 if it's still running when the timeout runs out
+    Stop {
+        /// seconds to wait for it to exit. defaults to the shutdown grace period plus 15
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// stop the running instance, as `stop` does, then start it again in the background with the options given here
+    Restart {
+        /// seconds to wait for it to exit. defaults to the shutdown grace period plus 15
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+}
+
+/// Which script or watch path `pause` and `resume` apply to.
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+pub struct TargetArgs {
+    /// name of the script
+    #[arg(long)]
+    pub script: Option<String>,
+    /// watch path, as listed by `list`
+    #[arg(long)]
+    pub watch_path: Option<PathBuf>,
+}
+
+impl TargetArgs {
+    pub fn target(&self) -> Target {
+        match (&self.script, &self.watch_path) {
+            (Some(name), _) => Target::Script(name.clone()),
+            (None, Some(path)) => Target::WatchPath(PathKey::new(path).into_path_buf()),
+            // clap makes sure one of the two is provided
+            (None, None) => unreachable!(),
+        }
+    }
 }
 
 impl Utilities for CommandLineArgs {}
 
+/// Instance names end up in file names, so they're kept to letters, digits, `-` and `_`.
+fn parse_instance_name(name: &str) -> Result<String, String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    match !name.is_empty() && name.chars().all(allowed) {
+        true => Ok(name.to_string()),
+        false => Err("only letters, digits, - and _ are allowed".to_string()),
+    }
+}
+
 impl CommandLineArgs {
+    pub fn channel_capacities(&self) -> ChannelCapacities {
+        ChannelCapacities {
+            events: self.event_channel_capacity,
+            messages: self.channel_capacity,
+        }
+    }
+
+    pub fn watch_path_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.watch_path_poll_interval.max(1))
+    }
+
+    pub fn queue_stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.queue_stall_timeout)
+    }
+
+    pub fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_period)
+    }
+
+    pub fn stop_timeout(&self, timeout: Option<u64>) -> Duration {
+        Duration::from_secs(timeout.unwrap_or(self.shutdown_grace_period + STOP_TIMEOUT_MARGIN_SEC))
+    }
+
+    pub fn unfinished_file(&self) -> Option<PathBuf> {
+        self.unfinished_file
+            .clone()
+            .or_else(|| UnfinishedRuns::default_path(self.instance.as_deref()))
+    }
+
+    pub fn pid_file(&self) -> Option<PathBuf> {
+        self.pid_file
+            .clone()
+            .or_else(|| Lockfile::default_path(self.instance.as_deref()))
+    }
+
+    /// Where the control socket is, or goes: next to the pid file, unless it's been put somewhere else.
+    pub fn control_socket(&self) -> Option<PathBuf> {
+        self.control_socket
+            .clone()
+            .or_else(|| self.pid_file().map(|pid_file| pid_file.with_extension("sock")))
+    }
+
+    /// Where `rusty-hooks status` should ask, preferring the unix socket as it can't be some other process's port.
+    pub fn health_endpoint(&self) -> Option<HealthEndpoint> {
+        match (&self.health_socket, self.http_address) {
+            (Some(path), _) => Some(HealthEndpoint::Socket(path.clone())),
+            (None, Some(address)) => Some(HealthEndpoint::Http(address)),
+            (None, None) => None,
+        }
+    }
+
+    /// Finds the main configuration file. An explicit --config wins, otherwise it's the script folder's scripts.yml (or
+    /// .yaml, .toml, .json), or the folder's only config file of any of those formats. Folders where that's ambiguous are
+    /// rejected, rather than picking whichever file the file system happens to list first.
     pub fn get_config_path(&self) -> Result<PathBuf, CommandLineError> {
+        if let Some(config) = &self.config {
+            debug!("config path: {}", config.display());
+            let config = config.canonicalize()?;
+            return match (config.is_file(), ConfigFormat::from_path(&config)) {
+                (true, Some(_)) => Ok(config),
+                (false, _) => Err(CommandLineError::ScriptConfigError(format!(
+                    "{} isn't a file",
+                    config.display()
+                ))),
+                (true, None) => Err(CommandLineError::ScriptConfigError(format!(
+                    "{} isn't in a supported format, expected one of .{}",
+                    config.display(),
+                    ConfigFormat::EXTENSIONS.join(", .")
+                ))),
+            };
+        }
         let possible_config_error = CommandLineError::ScriptConfigError(
-            "unable to verify script configuration file".to_string(),
+            "neither --config nor --script-folder was provided".to_string(),
         );
-        let config_path = self.script_folder.clone();
-        let config_path_str = config_path.to_str().unwrap_or("");
-        debug!("config path: {}", config_path_str);
-        let config_dir = config_path.canonicalize()?.read_dir()?;
-        let config_dir_files = config_dir.collect_vec();
-        if !Self::dir_contains_file_type(&config_dir_files, &"yml".to_string()) {
-            return Err(possible_config_error);
+        let script_folder = self.script_folder.clone().ok_or(possible_config_error)?;
+        debug!("script folder: {}", script_folder.display());
+        let script_folder = script_folder.canonicalize()?;
+        let config_dir_files = script_folder.read_dir()?.collect_vec();
+        let all_configs = ConfigFormat::EXTENSIONS
+            .iter()
+            .flat_map(|extension| Self::all_of_file_type(&config_dir_files, &extension.to_string()))
+            .sorted()
+            .collect_vec();
+        let (default_configs, other_configs): (Vec<PathBuf>, Vec<PathBuf>) = all_configs
+            .into_iter()
+            .partition(|path| path.file_stem().is_some_and(|stem| stem == DEFAULT_CONFIG_FILE_STEM));
+        let candidates = match default_configs.is_empty() {
+            true => other_configs,
+            false => default_configs,
+        };
+        match candidates.as_slice() {
+            [config_file] => Ok(config_file.clone()),
+            [] => Err(CommandLineError::ScriptConfigError(format!(
+                "no {}.yml or other config file in {}",
+                DEFAULT_CONFIG_FILE_STEM,
+                script_folder.display()
+            ))),
+            _ => Err(CommandLineError::ScriptConfigError(format!(
+                "several possible config files in {}, pick one with --config: {}",
+                script_folder.display(),
+                candidates.iter().map(|path| path.display()).join(", ")
+            ))),
         }
-        let config_file = Self::get_first_of_file_type(&config_dir_files, &"yml".to_string())
-            .ok_or(possible_config_error)?;
-        Ok(config_file)
     }
 }
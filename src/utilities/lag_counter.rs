@@ -0,0 +1,46 @@
+use log::warn;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Tallies messages dropped because a broadcast receiver fell behind its channel's capacity, keyed by channel name.
+/// Cheap to clone, every clone shares the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct LagCounter {
+    dropped: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl LagCounter {
+    pub fn new() -> Self {
+        LagCounter::default()
+    }
+
+    /// Records `num_dropped` lost messages on `channel`, logging the running total for that channel.
+    pub fn record(&self, channel: &str, num_dropped: u64) -> u64 {
+        let total = match self.dropped.lock() {
+            Ok(mut dropped) => {
+                let total = dropped.entry(channel.to_string()).or_insert(0);
+                *total += num_dropped;
+                *total
+            }
+            Err(e) => {
+                warn!("unable to lock onto lag counts: {}", e);
+                num_dropped
+            }
+        };
+        warn!(
+            "{} channel lagged, dropped {} messages ({} dropped on it so far)",
+            channel, num_dropped, total
+        );
+        total
+    }
+
+    /// Snapshot of dropped message counts per channel.
+    pub fn dropped(&self) -> HashMap<String, u64> {
+        self.dropped
+            .lock()
+            .map(|dropped| dropped.clone())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,79 @@
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+
+/// The identity of a path wherever rusty-hooks compares or hashes paths: subscriptions and unsubscriptions, routing events
+/// to watch paths, and matching scripts to watch paths. Keys are absolute and normalised lexically, without touching the
+/// file system, so removed paths (and ones that never existed) compare just like existing ones. Symlinks are never resolved:
+/// a watch path is keyed as configured, which is also how notify reports the paths of events beneath it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PathKey(PathBuf);
+
+impl PathKey {
+    pub fn new(path: &Path) -> Self {
+        // only fails for empty paths, which have nothing to normalise anyway
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        PathKey(Self::normalize_lexically(&absolute))
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        self.0
+    }
+
+    /// Whether the other key is this one, or sits anywhere beneath it.
+    pub fn contains(&self, other: &PathKey) -> bool {
+        other.0.starts_with(&self.0)
+    }
+
+    /// How many levels beneath this key the other one sits, e.g. 1 for direct children. `None` when it isn't beneath it.
+    pub fn depth_of(&self, other: &PathKey) -> Option<usize> {
+        other
+            .0
+            .strip_prefix(&self.0)
+            .ok()
+            .map(|relative| relative.components().count())
+    }
+
+    /// Resolves `.` and `..` components without touching the file system. Symlinks are left as they are, so this can
+    /// disagree with [`Path::canonicalize`], but unlike it works for paths that have already been removed.
+    fn normalize_lexically(path: &Path) -> PathBuf {
+        path.components()
+            .fold(PathBuf::new(), |mut normalized, component| {
+                match component {
+                    Component::CurDir => {}
+                    Component::ParentDir => {
+                        // popping past the root is a no-op, matching how `/..` resolves
+                        if !normalized.pop() {
+                            normalized.push(component);
+                        }
+                    }
+                    _ => normalized.push(component),
+                };
+                normalized
+            })
+    }
+}
+
+impl From<&Path> for PathKey {
+    fn from(path: &Path) -> Self {
+        PathKey::new(path)
+    }
+}
+
+impl From<&PathBuf> for PathKey {
+    fn from(path: &PathBuf) -> Self {
+        PathKey::new(path)
+    }
+}
+
+/// Lets a key stand in for the normalised path wherever one is borrowed, e.g. a watch path keying [`crate::scripts::structs::ScriptsByWatchPath`].
+impl Deref for PathKey {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
@@ -1,137 +1,179 @@
-use crate::errors::watcher_errors::path_error::PathError;
+use crate::errors::lockfile_errors::lockfile_error::LockfileError;
 use directories::BaseDirs;
 use fs2::FileExt;
-use log::debug;
+use log::{debug, warn};
 use std::{
-    fs::File,
-    io::Write,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, Write},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
+    thread::sleep,
+    time::Duration,
 };
 
+/// How many times [`Lockfile::holder`] looks again at a pid file that's locked without naming a running process, i.e.
+/// one that's being taken over, and how long it waits in between.
+const HOLDER_RETRIES: u32 = 100;
+const HOLDER_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Holds the lock on the pid file for as long as rusty hooks runs, so only one instance of each name runs at a time. The
+/// lock is an flock on the file, which the OS lets go of however the process ends, so a pid file that can be locked was
+/// left behind by an instance that's no longer running. Whichever file is at the path and locked names its holder, except
+/// for the moment it takes an instance to lock a stale one and move its own into place.
 #[derive(Debug)]
 pub struct Lockfile {
-    pub locked: bool,
-    pub lock: Option<File>,
-    pub pid: u32,
+    pub lock: File,
     pub path: PathBuf,
 }
 
 impl Lockfile {
-    pub fn set(pid: Option<u32>, path: Option<&PathBuf>) -> Result<Self, std::io::Error> {
-        let default_path =
-            Lockfile::default_lockfile_path().expect("unable to configure path to write pid to");
-
-        let mut preliminary_self = Lockfile {
-            locked: false,
-            pid: pid.unwrap_or(std::process::id()).clone(),
-            path: path.unwrap_or(&default_path).clone(),
-            lock: None,
-        };
-
-        let file_exists = preliminary_self.file_path_valid();
-
-        debug!(
-            "default path to lockfile is: {}",
-            default_path.to_str().unwrap()
-        );
-
-        match file_exists {
-            true => {
-                // file exists and is unlocked
-                debug!("lockfile exists, updating pid");
-                preliminary_self.lock_and_update_existing()?;
-                return Ok(preliminary_self);
+    /// Locks the pid file, then moves a locked one naming this process in over it, creating it and its directory if need
+    /// be. Fails with [`LockfileError::AlreadyRunning`] if another instance holds the lock.
+    pub fn set(path: &Path) -> Result<Self, LockfileError> {
+        let io_error = |source| LockfileError::IoError { path: path.to_path_buf(), source };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(io_error)?;
+            let previous_pid = Self::read_pid(&mut file);
+            if FileExt::try_lock_exclusive(&file).is_err() {
+                return Err(LockfileError::AlreadyRunning { pid: previous_pid, path: path.to_path_buf() });
             }
-            false => {
-                // file doesn't exist, need to write a new one
-                debug!("lockfile doesn\'t exist, creating a new one");
-                preliminary_self.create_if_nonexistent()?;
-                preliminary_self.acquire_file_lock()?;
-                return Ok(preliminary_self);
+            // the instance that held it may have removed the file between it being opened and locked here, in which case
+            // the lock is on a file nobody else will ever see, and another instance could lock a new one
+            if !Self::still_at(&file, path) {
+                debug!("pid file was replaced while locking it, trying again");
+                continue;
             }
+            let previous_pid = Self::read_pid(&mut file);
+            if let Some(stale_pid) = previous_pid.filter(|pid| *pid != std::process::id()) {
+                match Self::is_alive(stale_pid) {
+                    true => warn!("pid file {} named pid {}, which is running but doesn't hold the lock, so it isn't rusty hooks", path.display(), stale_pid),
+                    false => warn!("pid file {} was left behind by pid {}, which is no longer running", path.display(), stale_pid),
+                }
+            }
+            // written beside it rather than over the stale pid, so it's never read half written or before it's written
+            let pid = std::process::id();
+            let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+            file_name.push(format!(".{}", pid));
+            let replacement_path = path.with_file_name(file_name);
+            let mut replacement = File::create(&replacement_path).map_err(io_error)?;
+            let replaced = FileExt::try_lock_exclusive(&replacement)
+                .and_then(|_| Self::write_pid(&mut replacement, pid))
+                .and_then(|_| fs::rename(&replacement_path, path));
+            if let Err(e) = replaced {
+                let _ = fs::remove_file(&replacement_path);
+                return Err(io_error(e));
+            }
+            debug!("holding the lock on {} as pid {}", path.display(), pid);
+            // the stale file's lock goes with it, and anyone waiting on it will find it's no longer at the path
+            return Ok(Lockfile { lock: replacement, path: path.to_path_buf() });
         }
     }
 
-    fn default_lockfile_path() -> Option<PathBuf> {
-        let home_dir = BaseDirs::new()
-            .and_then(|p| Some(p.home_dir().to_path_buf()))?
-            .canonicalize()
-            .ok()?;
-        let rusty_hooks_subdir = Path::join(&Path::new("rusty-hooks/rusty-hooks.pid"), home_dir);
-        Some(rusty_hooks_subdir)
+    /// Removes and unlocks the pid file, so the next instance starts with a clean slate. Removed first, so there's never an
+    /// unlocked pid file for another instance to pick up and then lose.
+    pub fn release(self) -> Result<(), std::io::Error> {
+        fs::remove_file(&self.path)?;
+        FileExt::unlock(&self.lock)
+    }
+
+    /// Where the pid file goes when --pid-file isn't provided: `$XDG_RUNTIME_DIR/rusty-hooks`, falling back to the local
+    /// data directory where there's no runtime directory, e.g. on mac or outside a login session. Named instances each get
+    /// their own, see [`Lockfile::file_name`].
+    pub fn default_path(instance: Option<&str>) -> Option<PathBuf> {
+        let dirs = BaseDirs::new()?;
+        let dir = dirs.runtime_dir().unwrap_or(dirs.data_local_dir());
+        Some(dir.join("rusty-hooks").join(Self::file_name(instance, "pid")))
+    }
+
+    /// `rusty-hooks.<extension>`, or `rusty-hooks-<instance>.<extension>` for a named instance.
+    fn file_name(instance: Option<&str>, extension: &str) -> String {
+        match instance {
+            Some(instance) => format!("rusty-hooks-{}.{}", instance, extension),
+            None => format!("rusty-hooks.{}", extension),
+        }
     }
 
-    fn lock_and_update_existing(&mut self) -> Result<(), std::io::Error> {
-        self.check_for_existing_lock()?;
-        let pid_as_str = self.pid.to_string();
-        if let Some(mut pid_file) = &self.lock.as_ref() {
-            pid_file.write_all(&pid_as_str.as_bytes())?;
-        } else {
-            self.acquire_file_lock()?;
-        };
-        Ok(())
+    /// The pid of the instance holding the lock on a pid file, e.g. for `rusty-hooks stop` to signal. `None` if there's no
+    /// pid file, or nothing holds its lock, so whatever pid is in it is stale. A locked one that doesn't name a running
+    /// process, or has been replaced since it was opened, is being taken over, and is looked at again once it has been.
+    pub fn holder(path: &Path) -> Option<u32> {
+        for _ in 0..HOLDER_RETRIES {
+            let mut file = File::open(path).ok()?;
+            // fs2's, since std's own only arrived in 1.89
+            if FileExt::try_lock_shared(&file).is_ok() {
+                let _ = FileExt::unlock(&file);
+                return None;
+            }
+            match Self::read_pid(&mut file) {
+                Some(pid) if Self::is_alive(pid) && Self::still_at(&file, path) => return Some(pid),
+                _ => sleep(HOLDER_RETRY_INTERVAL),
+            }
+        }
+        None
     }
 
-    fn check_for_existing_lock(&self) -> Result<(), std::io::Error> {
-        let io_error_kind = std::io::ErrorKind::InvalidFilename;
-        let filepath_error = std::io::Error::new(
-            io_error_kind,
-            PathError::InvalidPath("error with path provided for lockfile".into()),
-        );
-        self.file_path_valid().then_some(()).ok_or(filepath_error)?;
-        let file = File::open(&self.path)?.try_clone()?;
-        file.try_lock_exclusive()
+    /// Whether a process with the pid exists. One that exists but belongs to another user still counts.
+    pub fn is_alive(pid: u32) -> bool {
+        // signal 0 only checks whether the signal could be sent
+        let sent = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        sent == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
     }
 
-    fn file_path_valid(&self) -> bool {
-        self.path.is_file().clone()
+    fn read_pid(file: &mut File) -> Option<u32> {
+        let mut contents = String::new();
+        file.rewind().ok()?;
+        file.read_to_string(&mut contents).ok()?;
+        contents.trim().parse().ok()
     }
 
-    fn acquire_file_lock(&mut self) -> std::io::Result<()> {
-        let file = File::open(&self.path)?.try_clone()?;
-        file.try_lock_exclusive()?;
-        self.lock = Some(file);
-        self.locked = true;
-        Ok(())
+    fn write_pid(file: &mut File, pid: u32) -> std::io::Result<()> {
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(pid.to_string().as_bytes())?;
+        file.sync_all()
     }
 
-    fn create_if_nonexistent(&mut self) -> Result<(), std::io::Error> {
-        if self.file_path_valid() {
-            return Ok(());
+    fn still_at(file: &File, path: &Path) -> bool {
+        match (file.metadata(), fs::metadata(path)) {
+            (Ok(locked), Ok(at_path)) => locked.dev() == at_path.dev() && locked.ino() == at_path.ino(),
+            _ => false,
         }
+    }
+}
 
-        let provided_path_is_dir = &self.path.is_dir();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
 
-        let intended_dir = &self.path.parent();
+    #[test]
+    fn only_one_lock_at_a_time_and_stale_pid_files_are_taken_over() {
+        let dir = TempDir::new("lockfile").unwrap();
+        let path = dir.path().join("run").join("rusty-hooks.pid");
+        // no process gets a pid this high, see /proc/sys/kernel/pid_max
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "4194305").unwrap();
 
-        return match (provided_path_is_dir, intended_dir) {
-            (true, _) => {
-                // create file inside dir
-                let path_with_filename = &self.path.join("rusty-hooks.pid");
-                let mut file = File::create(path_with_filename)?;
-                let pid_as_str = self.pid.to_string();
-                file.write_all(pid_as_str.as_bytes())?;
-                self.path = path_with_filename.clone();
-                Ok(())
-            }
-            (false, Some(path)) => {
-                // use the path to create file
-                let path_with_filename = path.join("rusty-hooks.pid");
-                let mut file = File::create(path_with_filename.clone())?;
-                let pid_as_str = self.pid.to_string();
-                file.write_all(pid_as_str.as_bytes())?;
-                self.path = path_with_filename;
-                Ok(())
-            }
-            (false, None) => {
-                // no useable path
-                let io_error_kind = std::io::ErrorKind::InvalidFilename;
-                let custom_error2 = std::io::Error::new(
-                    io_error_kind,
-                    PathError::InvalidPath("error with path provided for lockfile".into()),
-                );
-                Err(custom_error2)
-            }
-        };
+        let lockfile = Lockfile::set(&path).unwrap();
+        assert_eq!(Lockfile::holder(&path), Some(std::process::id()));
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        match Lockfile::set(&path) {
+            Err(LockfileError::AlreadyRunning { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            other => panic!("expected the second lock to fail, got {:?}", other),
+        }
+        lockfile.release().unwrap();
+        assert!(!path.exists());
+        fs::write(&path, "4194305").unwrap();
+        assert_eq!(Lockfile::holder(&path), None);
+        Lockfile::set(&path).unwrap().release().unwrap();
     }
 }
@@ -1,6 +1,6 @@
 use crate::runner::types::SpawnMessage;
 use notify::Event;
-use std::{path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
 
 pub type BroadcastSender<T> = tokio::sync::broadcast::Sender<T>;
 pub type BroadcastReceiver<T> = tokio::sync::broadcast::Receiver<T>;
@@ -15,4 +15,16 @@ pub type SpawnSender = BroadcastSender<SpawnMessage>;
 pub type UnsubscribeSender = BroadcastSender<PathBuf>;
 pub type SubscribeChannel = Channel<SpawnMessage>;
 pub type UnsubscribeChannel = Channel<PathBuf>;
+pub type RescanRequest = (Option<PathBuf>, SystemTime); // watch path to rescan, or None for all of them, and when its messages started dropping
+pub type RescanChannel = Channel<RescanRequest>;
+
+/// Buffer sizes of the broadcast channels connecting the watcher, subscriber and runner tasks. A receiver that falls
+/// more than its channel's capacity behind loses the oldest messages.
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelCapacities {
+    /// Capacity of the channel carrying events out of notify.
+    pub events: usize,
+    /// Capacity of the subscription, spawn and unsubscribe channels.
+    pub messages: usize,
+}
 
@@ -1,7 +1,7 @@
 use crate::errors::shared_errors::thread_errors::ThreadError;
 use crate::{
     errors::watcher_errors::path_error::PathError,
-    utilities::{thread_types::Channel, timer::Timer},
+    utilities::{path_key::PathKey, thread_types::Channel, timer::Timer},
 };
 use log::{debug, error, info};
 use std::{
@@ -15,52 +15,16 @@ use tokio::runtime::Runtime;
 pub type DirEntries = Vec<Result<DirEntry, std::io::Error>>;
 
 pub trait Utilities {
-    fn hasher(path: &String) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        path.hash(&mut hasher);
-        hasher.finish()
-    }
-
-    fn dir_contains_file_type(dir: &DirEntries, extension: &String) -> bool {
-        let contains = &dir.iter().any(|entry| {
-            let Ok(valid_entry) = entry else { return false };
-            let entry_path = valid_entry.path();
-            let Some(file_extension) = entry_path.extension() else {
-                return false;
-            };
-            let Some(ext_str) = file_extension.to_str() else {
-                return false;
-            };
-            if ext_str == extension {
-                return true;
-            }
-            false
-        });
-        *contains
-    }
-
-    fn get_first_of_file_type(dir: &DirEntries, extension: &String) -> Option<PathBuf> {
-        let possible_matches: Vec<&Result<std::fs::DirEntry, std::io::Error>> = dir
+    /// Every file in the directory with the extension, sorted so the result doesn't depend on directory order.
+    fn all_of_file_type(dir: &DirEntries, extension: &String) -> Vec<PathBuf> {
+        let mut matches: Vec<PathBuf> = dir
             .iter()
-            .filter(|file| {
-                let Ok(valid_entry) = file else { return false };
-                let path_buf = valid_entry.path();
-                let entry_path = path_buf.as_path();
-                let Some(file_extension) = entry_path.extension() else {
-                    return false;
-                };
-                let Some(ext_str) = file_extension.to_str() else {
-                    return false;
-                };
-                if ext_str == extension {
-                    return true;
-                }
-                false
-            })
+            .filter_map(|entry| entry.as_ref().ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some(extension.as_str()))
             .collect();
-        let first_match = possible_matches.iter().next()?;
-        let is_match = first_match.as_ref().ok();
-        Some(is_match?.path())
+        matches.sort();
+        matches
     }
 
     fn format_unvalidated_path(segments: &Vec<&String>) -> String {
@@ -110,8 +74,8 @@ pub trait Utilities {
         };
     }
 
-    fn new_channel<T: std::clone::Clone>() -> Channel<T> {
-        tokio::sync::broadcast::channel::<T>(16)
+    fn new_channel<T: std::clone::Clone>(capacity: usize) -> Channel<T> {
+        tokio::sync::broadcast::channel::<T>(capacity)
     }
 
     fn new_runtime(num_threads: usize, thread_name: &String) -> Result<Runtime, ThreadError> {
@@ -127,30 +91,84 @@ pub trait Utilities {
         Timer::new(wait_duration)
     }
 
-    fn path_hasher(path: &PathBuf) -> Result<u64, PathError> {
+    /// Hashes a path by its [`PathKey`], so paths that no longer exist (or never did) hash just as well as those that do,
+    /// and `/watch/./a` and `/watch/b/../a` hash the same as `/watch/a`.
+    fn path_hasher(path: &PathBuf) -> u64 {
         let mut hasher = DefaultHasher::new();
-        let abs_path = path.canonicalize()?;
-        abs_path.hash(&mut hasher);
-        Ok(hasher.finish())
+        PathKey::new(path).hash(&mut hasher);
+        hasher.finish()
     }
 
+    /// Finds the event's home dir, i.e. the path one level below the root that the leaf sits in, purely lexically so
+    /// that removed and renamed-away paths resolve as well as existing ones.
     fn walk_up_to_event_home_dir<'a>(leaf: PathBuf, root: PathBuf) -> Result<PathBuf, PathError> {
-        // walk upwards from the leaf until we hit the parentmost directory of the event, i.e. the path that is one level below the root
-        let leaf_hash = Self::path_hasher(&leaf)?;
-        let parent_of_leaf = leaf.parent().ok_or(PathError::TraversalError)?;
-        let parent_of_leaf_hash = Self::path_hasher(&parent_of_leaf.to_path_buf())?;
-        let root_hash = Self::path_hasher(&root)?;
-        if parent_of_leaf_hash == root_hash {
-            return Ok(leaf);
-        } else if leaf_hash == root_hash {
-            return Err(PathError::TraversalError);
-        } else {
-            Self::walk_up_to_event_home_dir(parent_of_leaf.to_path_buf(), root)
-        }
+        let leaf_key = PathKey::new(&leaf);
+        let root_key = PathKey::new(&root);
+        let relative_to_root = leaf_key
+            .as_path()
+            .strip_prefix(root_key.as_path())
+            .map_err(|_| PathError::TraversalError)?;
+        // a leaf equal to the root has no home dir below it
+        let home_dir_name = relative_to_root
+            .components()
+            .next()
+            .ok_or(PathError::TraversalError)?;
+        Ok(root_key.as_path().join(home_dir_name))
+    }
+
+    /// How many levels below the root a path sits, e.g. 1 for the root's direct children. `None` when it isn't below the root.
+    fn depth_below(path: &Path, root: &Path) -> Option<usize> {
+        PathKey::new(root).depth_of(&PathKey::new(path))
     }
 
+    /// Whether the subdir is the path itself or sits anywhere beneath it, compared by [`PathKey`].
     fn path_contains_subdir(path: &PathBuf, subdir: &PathBuf) -> bool {
-        let mut ancestors = subdir.ancestors();
-        ancestors.any(|p| &p.to_path_buf() == path)
+        PathKey::new(path).contains(&PathKey::new(subdir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Utilities;
+    use std::path::PathBuf;
+
+    struct Paths;
+    impl Utilities for Paths {}
+
+    #[test]
+    fn finds_home_dir_of_a_path_that_doesnt_exist() {
+        let root = PathBuf::from("/rusty-hooks-tests/watch_path");
+        let removed = root.join("downloads/very/nested/file.mkv");
+        let home_dir = Paths::walk_up_to_event_home_dir(removed, root.clone()).unwrap();
+        assert_eq!(home_dir, root.join("downloads"))
+    }
+
+    #[test]
+    fn refuses_home_dir_of_the_root_itself() {
+        let root = PathBuf::from("/rusty-hooks-tests/watch_path");
+        assert!(Paths::walk_up_to_event_home_dir(root.clone(), root).is_err())
+    }
+
+    #[test]
+    fn measures_depth_below_the_root() {
+        let root = PathBuf::from("/rusty-hooks-tests/watch_path");
+        assert_eq!(Paths::depth_below(&root.join("downloads"), &root), Some(1));
+        assert_eq!(Paths::depth_below(&root.join("a/./b/../b/c"), &root), Some(3));
+        assert_eq!(Paths::depth_below(&PathBuf::from("/elsewhere"), &root), None)
+    }
+
+    #[test]
+    fn matches_relative_and_absolute_paths_alike() {
+        let relative = PathBuf::from("watch_path/downloads");
+        let absolute = std::env::current_dir().unwrap().join("watch_path");
+        assert!(Paths::path_contains_subdir(&absolute, &relative));
+        assert_eq!(Paths::path_hasher(&relative), Paths::path_hasher(&absolute.join("downloads")))
+    }
+
+    #[test]
+    fn hashes_equivalent_paths_the_same() {
+        let plain = PathBuf::from("/rusty-hooks-tests/watch_path/downloads");
+        let dotted = PathBuf::from("/rusty-hooks-tests/./watch_path/other/../downloads");
+        assert_eq!(Paths::path_hasher(&plain), Paths::path_hasher(&dotted))
     }
 }
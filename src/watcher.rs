@@ -1,6 +1,10 @@
+pub mod attach;
 pub mod init;
 pub mod structs;
 pub mod types;
 pub mod watch_events;
 pub mod path_subscriber;
+pub mod reload;
+pub mod rename;
+pub mod rescan;
 mod event_loop;
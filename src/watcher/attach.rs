@@ -0,0 +1,308 @@
+use super::structs::{WatchPathStatus, Watcher};
+use super::types::SharedWatchPathStatuses;
+use crate::errors::watcher_errors::event_error::EventError;
+use crate::scripts::structs::{ScriptsByWatchPath, WatchOptions};
+use crate::utilities::path_key::PathKey;
+use itertools::Itertools;
+use log::{debug, error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as NotifyWatcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+impl Watcher {
+    /// Watches the path if it's a readable directory, as deep as its options say, or if it's a file, through its
+    /// parent directory. Otherwise it's recorded as missing and left for [`Watcher::poll_watch_paths`] to attach once it
+    /// shows up, so one absent path doesn't hold up the rest.
+    pub fn attach(
+        notifier_handle: &mut RecommendedWatcher,
+        watch_path: &PathBuf,
+        options: WatchOptions,
+        statuses: &SharedWatchPathStatuses,
+    ) -> Result<WatchPathStatus, EventError> {
+        let status = match Self::watch_path_available(watch_path) {
+            true if watch_path.is_file() => {
+                Self::watch_file(notifier_handle, watch_path, statuses)?;
+                info!("now watching path: {}", watch_path.display());
+                WatchPathStatus::WatchingFile
+            }
+            true => {
+                match options.walked_depth() {
+                    None => notifier_handle.watch(watch_path.as_ref(), RecursiveMode::Recursive)?,
+                    Some(depth) => Self::watch_within_depth(
+                        notifier_handle,
+                        watch_path,
+                        depth,
+                        options.follow_symlinks,
+                    )?,
+                }
+                info!("now watching path: {}", watch_path.display());
+                WatchPathStatus::Watching
+            }
+            false => {
+                warn!(
+                    "watch path {} doesn't exist or can't be read, will attach to it once it can",
+                    watch_path.display()
+                );
+                WatchPathStatus::Missing
+            }
+        };
+        Self::set_status(statuses, watch_path, Some(status));
+        Ok(status)
+    }
+
+    /// Stops watching the path, and every directory watched below it one by one, and forgets about it entirely.
+    pub fn detach(
+        notifier_handle: &mut RecommendedWatcher,
+        watch_path: &PathBuf,
+        options: WatchOptions,
+        statuses: &SharedWatchPathStatuses,
+    ) {
+        let previous_status = Self::status_of(statuses, watch_path);
+        Self::set_status(statuses, watch_path, None);
+        if previous_status == Some(WatchPathStatus::WatchingFile) {
+            Self::unwatch_file(notifier_handle, watch_path, statuses);
+            info!("no longer watching path: {}", watch_path.display());
+        }
+        if previous_status == Some(WatchPathStatus::Watching) {
+            if let Some(depth) = options.walked_depth() {
+                let dirs = Self::dirs_within_depth(watch_path, depth - 1, options.follow_symlinks);
+                for dir in dirs {
+                    // directories removed since they were watched took their watches with them
+                    let _ = notifier_handle.unwatch(&dir);
+                }
+            }
+            match notifier_handle.unwatch(watch_path.as_ref()) {
+                Ok(_) => info!("no longer watching path: {}", watch_path.display()),
+                // the watch goes away on its own when the path is deleted or unmounted
+                Err(e) => warn!("unable to unwatch path {}: {}", watch_path.display(), e),
+            }
+        }
+    }
+
+    /// Watches a directory and the directories below it, each on its own, down to `depth` levels below it. Events
+    /// are reported for the entries of every directory watched, so only directories less than `depth` deep are watched.
+    /// Running out of inotify watches fails the whole thing, while directories vanishing along the way are skipped.
+    pub fn watch_within_depth(
+        notifier_handle: &mut RecommendedWatcher,
+        dir: &Path,
+        depth: usize,
+        follow_symlinks: bool,
+    ) -> Result<(), EventError> {
+        notifier_handle.watch(dir, RecursiveMode::NonRecursive)?;
+        for subdir in Self::dirs_within_depth(dir, depth.saturating_sub(1), follow_symlinks) {
+            match notifier_handle.watch(&subdir, RecursiveMode::NonRecursive) {
+                Ok(_) => {}
+                Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => return Err(e.into()),
+                Err(e) => debug!("skipping watch of {}: {}", subdir.display(), e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Watches the directory a file sits in rather than the file itself. Editors and config management tools commonly replace
+    /// files by renaming a new one over them, which would end a watch on the file. A directory that's already watched as part
+    /// of another watch path isn't watched again, since doing so would stop notify watching its new subdirectories.
+    fn watch_file(
+        notifier_handle: &mut RecommendedWatcher,
+        file: &Path,
+        statuses: &SharedWatchPathStatuses,
+    ) -> Result<(), EventError> {
+        let Some(parent) = file.parent() else {
+            return Ok(());
+        };
+        if !Self::watched_as_part_of_a_dir(parent, statuses) {
+            notifier_handle.watch(parent, RecursiveMode::NonRecursive)?;
+        }
+        Ok(())
+    }
+
+    /// Stops watching a file's parent directory, unless another watch path still relies on it. Expects the file to
+    /// already have been removed from the statuses.
+    fn unwatch_file(
+        notifier_handle: &mut RecommendedWatcher,
+        file: &Path,
+        statuses: &SharedWatchPathStatuses,
+    ) {
+        let Some(parent) = file.parent() else {
+            return;
+        };
+        let shared_with_another_file = Self::snapshot(statuses).iter().any(|(watch_path, status)| {
+            *status == WatchPathStatus::WatchingFile && watch_path.parent() == Some(parent)
+        });
+        if shared_with_another_file || Self::watched_as_part_of_a_dir(parent, statuses) {
+            return;
+        }
+        if let Err(e) = notifier_handle.unwatch(parent) {
+            // the watch goes away on its own when the directory is deleted or unmounted
+            warn!("unable to unwatch {}: {}", parent.display(), e);
+        }
+    }
+
+    /// Whether a directory sits inside a directory watch path that's being watched.
+    fn watched_as_part_of_a_dir(dir: &Path, statuses: &SharedWatchPathStatuses) -> bool {
+        Self::snapshot(statuses).iter().any(|(watch_path, status)| {
+            *status == WatchPathStatus::Watching && dir.starts_with(watch_path)
+        })
+    }
+
+    /// Lists the directories up to `depth` levels below a directory. Symlinked directories are only listed, and descended
+    /// into, when following symlinks. A symlink loop is only as deep as `depth` lets it go.
+    fn dirs_within_depth(dir: &Path, depth: usize, follow_symlinks: bool) -> Vec<PathBuf> {
+        if depth == 0 {
+            return vec![];
+        }
+        let Ok(entries) = dir.read_dir() else {
+            return vec![];
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| match follow_symlinks {
+                true => entry.path().is_dir(),
+                false => entry.file_type().is_ok_and(|file_type| file_type.is_dir()),
+            })
+            .flat_map(|entry| {
+                let subdir = entry.path();
+                let mut below = Self::dirs_within_depth(&subdir, depth - 1, follow_symlinks);
+                below.insert(0, subdir);
+                below
+            })
+            .collect()
+    }
+
+    /// Attaches missing watch paths that have appeared, e.g. a drive being mounted, and detaches watched
+    /// paths that have disappeared, keeping them around as missing until they come back.
+    pub fn poll_watch_paths(
+        notifier_handle: &mut RecommendedWatcher,
+        statuses: &SharedWatchPathStatuses,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+    ) {
+        let snapshot = Self::snapshot(statuses);
+        for (watch_path, status) in snapshot.into_iter().sorted_by_key(|(watch_path, _)| watch_path.clone()) {
+            match (status, Self::watch_path_available(&watch_path)) {
+                (WatchPathStatus::Missing, true) => {
+                    info!("watch path {} appeared, attaching", watch_path.display());
+                    let options = scripts_by_watch_path
+                        .get(&PathKey::new(&watch_path))
+                        .map(|scripts| scripts.watch_options)
+                        .unwrap_or_default();
+                    if let Err(e) = Self::attach(notifier_handle, &watch_path, options, statuses) {
+                        error!(
+                            "unable to attach to watch path {}, will retry: {}",
+                            watch_path.display(),
+                            e
+                        );
+                    }
+                }
+                (WatchPathStatus::Watching, false) => {
+                    warn!(
+                        "watch path {} disappeared, detaching until it comes back",
+                        watch_path.display()
+                    );
+                    let _ = notifier_handle.unwatch(watch_path.as_ref());
+                    Self::set_status(statuses, &watch_path, Some(WatchPathStatus::Missing));
+                }
+                // a watched file missing is normal, e.g. halfway through being replaced, so only its directory going away counts
+                (WatchPathStatus::WatchingFile, _) if !watch_path.parent().is_some_and(Path::is_dir) => {
+                    warn!(
+                        "directory of watch path {} disappeared, detaching until it comes back",
+                        watch_path.display()
+                    );
+                    Self::set_status(statuses, &watch_path, Some(WatchPathStatus::Missing));
+                    Self::unwatch_file(notifier_handle, &watch_path, statuses);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn watch_path_available(watch_path: &PathBuf) -> bool {
+        watch_path.is_file() || (watch_path.is_dir() && watch_path.read_dir().is_ok())
+    }
+
+    fn snapshot(statuses: &SharedWatchPathStatuses) -> HashMap<PathBuf, WatchPathStatus> {
+        match statuses.read() {
+            Ok(statuses) => statuses.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn status_of(statuses: &SharedWatchPathStatuses, watch_path: &PathBuf) -> Option<WatchPathStatus> {
+        match statuses.read() {
+            Ok(statuses) => statuses.get(watch_path).copied(),
+            Err(poisoned) => poisoned.into_inner().get(watch_path).copied(),
+        }
+    }
+
+    pub fn set_status(
+        statuses: &SharedWatchPathStatuses,
+        watch_path: &PathBuf,
+        status: Option<WatchPathStatus>,
+    ) {
+        let mut statuses = statuses
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match status {
+            Some(status) => statuses.insert(watch_path.clone(), status),
+            None => statuses.remove(watch_path),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs,
+        sync::{Arc, RwLock},
+    };
+    use tempdir::TempDir;
+
+    fn notifier() -> RecommendedWatcher {
+        notify::recommended_watcher(|_: notify::Result<notify::Event>| {}).unwrap()
+    }
+
+    #[test]
+    fn attaches_watch_paths_once_they_appear_and_detaches_them_once_gone() {
+        let dir = TempDir::new("poll").unwrap();
+        let watch_path = dir.path().join("drive");
+        let statuses: SharedWatchPathStatuses = Arc::new(RwLock::new(HashMap::new()));
+        let scripts_by_watch_path = ScriptsByWatchPath::new();
+        let mut notifier_handle = notifier();
+        let options = WatchOptions::default();
+
+        let status = Watcher::attach(&mut notifier_handle, &watch_path, options, &statuses).unwrap();
+        assert_eq!(status, WatchPathStatus::Missing);
+        Watcher::poll_watch_paths(&mut notifier_handle, &statuses, &scripts_by_watch_path);
+        assert_eq!(Watcher::status_of(&statuses, &watch_path), Some(WatchPathStatus::Missing));
+
+        fs::create_dir(&watch_path).unwrap();
+        Watcher::poll_watch_paths(&mut notifier_handle, &statuses, &scripts_by_watch_path);
+        assert_eq!(Watcher::status_of(&statuses, &watch_path), Some(WatchPathStatus::Watching));
+
+        fs::remove_dir(&watch_path).unwrap();
+        Watcher::poll_watch_paths(&mut notifier_handle, &statuses, &scripts_by_watch_path);
+        assert_eq!(Watcher::status_of(&statuses, &watch_path), Some(WatchPathStatus::Missing));
+    }
+
+    #[test]
+    fn keeps_watching_a_directory_until_the_last_file_watched_through_it_is_detached() {
+        let dir = TempDir::new("files").unwrap();
+        let files = ["a", "b"].map(|name| dir.path().join(name));
+        files.iter().for_each(|file| fs::write(file, "").unwrap());
+        let options = WatchOptions::default();
+        // unwatching a directory that's no longer watched fails, so it tells whether the watch was kept
+        let still_watched_after_detaching_a = |watched: &[PathBuf]| {
+            let statuses: SharedWatchPathStatuses = Arc::new(RwLock::new(HashMap::new()));
+            let mut notifier_handle = notifier();
+            for file in watched {
+                let status = Watcher::attach(&mut notifier_handle, file, options, &statuses).unwrap();
+                assert_eq!(status, WatchPathStatus::WatchingFile);
+            }
+            Watcher::detach(&mut notifier_handle, &files[0], options, &statuses);
+            notifier_handle.unwatch(dir.path()).is_ok()
+        };
+
+        assert!(still_watched_after_detaching_a(&files));
+        assert!(!still_watched_after_detaching_a(&files[..1]));
+    }
+}
@@ -3,7 +3,9 @@ use crate::{
         shared_errors::thread_errors::{ThreadError, UnexpectedAnyhowError},
         watcher_errors::subscriber_error::SubscriptionError,
     },
+    metrics::Metrics,
     utilities::{
+        lag_counter::LagCounter,
         thread_types::{BroadcastReceiver, EventMessage},
         traits::Utilities,
     },
@@ -12,17 +14,24 @@ use chrono::DateTime;
 use chrono::Duration;
 use chrono::Utc;
 use std::{path::PathBuf, sync::Arc};
-use tokio::{runtime::Handle, sync::Mutex, task::JoinHandle};
+use tokio::{
+    runtime::Handle,
+    sync::{broadcast::error::RecvError, Mutex},
+    task::JoinHandle,
+};
 
 use super::structs::PathSubscriber;
 
 impl PathSubscriber {
     /// Begins awaiting incoming path subscription, sent from [`Watcher::watch_events()`]. If an incoming event
-    /// is attached to a path already subscribed to, it updates the timer running on a separate thread.
+    /// is attached to a path already subscribed to, it updates the timer running on a separate thread. Should the
+    /// listener lag, the dropped events may have touched the path, so the timer is reset as if they had.
     pub fn event_loop(
         mut events_listener: BroadcastReceiver<EventMessage>,
         subscription_path: PathBuf,
         timer_controller: Arc<Mutex<(Duration, DateTime<Utc>)>>,
+        lag_counter: LagCounter,
+        metrics: Metrics,
     ) -> JoinHandle<Result<(), SubscriptionError>> {
         let handle = Handle::current();
 
@@ -32,7 +41,7 @@ impl PathSubscriber {
             let sub_path_str = subscription_path
                 .to_str()
                 .unwrap_or("unable to pull string out of path buf");
-            let sub_path_hash = Self::hasher(&sub_path_str.to_string());
+            let sub_path_hash = Self::path_hasher(&subscription_path);
             loop {
                 let event = match events_listener
                     .recv()
@@ -40,6 +49,15 @@ impl PathSubscriber {
                     .map_err(|e| ThreadError::RecvError(e.into()))
                 {
                     Ok(e) => e,
+                    Err(ThreadError::RecvError(RecvError::Lagged(num_dropped))) => {
+                        lag_counter.record("timer events", num_dropped);
+                        let mut controller_lock = timer_controller.try_lock()?;
+                        controller_lock.1 = chrono::prelude::Utc::now();
+                        metrics.timer_reset();
+                        continue;
+                    }
+                    // nothing more can touch the path, e.g. once shutting down, so the timer is left to run out as is
+                    Err(ThreadError::RecvError(RecvError::Closed)) => return Ok(()),
                     Err(e) => {
                         num_events_errors += 1;
                         last_error = Some(e.into());
@@ -72,6 +90,7 @@ impl PathSubscriber {
                     let now = chrono::prelude::Utc::now();
                     let mut controller_lock = timer_controller.try_lock()?;
                     controller_lock.1 = now;
+                    metrics.timer_reset();
                 } else {
                     // continue to let the timer run out while monitoring new events
                     continue;
@@ -88,10 +107,7 @@ impl PathSubscriber {
                 return true;
             };
             let cur_path_parent = cur_path.ancestors().any(|ancestor| {
-                let ancestor_path_str = ancestor
-                    .to_str()
-                    .unwrap_or("unable to pull string out of path buf");
-                let ancestor_hash = Self::hasher(&ancestor_path_str.to_string());
+                let ancestor_hash = Self::path_hasher(&ancestor.to_path_buf());
                 ancestor_hash == sub_path_hash
             });
             cur_path_parent
@@ -1,37 +1,73 @@
 use super::structs::{PathSubscriber, Watcher};
+use super::types::{DepthWatchChannel, DepthWatchRequest, EmittedAt, PathsCacheArc, ReloadChannel, SharedScripts};
+use crate::errors::shared_errors::thread_errors::ThreadError;
 use crate::errors::watcher_errors::watcher_error::WatcherError;
-use crate::scripts::structs::{Script, Scripts};
-use crate::utilities::{thread_types::EventChannel, traits::Utilities};
+use crate::metrics::Metrics;
+use crate::runner::types::SpawnMessage;
+use crate::control::Runs;
+use crate::shutdown::Shutdown;
+use crate::utilities::{
+    lag_counter::LagCounter,
+    thread_types::{ChannelCapacities, EventChannel},
+    traits::Utilities,
+};
 use crate::{
     errors::watcher_errors::{event_error::EventError, subscriber_error::SubscriptionError},
     utilities::thread_types::UnsubscribeSender,
 };
+use itertools::Itertools;
 use log::{debug, error, info};
-use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher as NotifyWatcher};
-use std::{path::PathBuf, sync::Arc};
+use notify::{Config, Event, RecommendedWatcher, Watcher as NotifyWatcher};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
 use tokio::{
+    signal::unix::{signal, SignalKind},
     sync::broadcast::{error::RecvError, Sender},
     task::JoinHandle,
+    time::{interval, MissedTickBehavior},
 };
 
 impl Watcher {
-    pub fn new() -> Result<Self, WatcherError> {
+    pub fn new(
+        capacities: ChannelCapacities,
+        lag_counter: LagCounter,
+        metrics: Metrics,
+        poll_interval: Duration,
+        shutdown: Shutdown,
+        runs: Runs,
+    ) -> Result<Self, WatcherError> {
         let watcher_runtime = <Self as Utilities>::new_runtime(4, &"watcher-runtime".to_string())?;
         Ok(Watcher {
             runtime: watcher_runtime,
+            capacities,
+            lag_counter,
+            metrics,
+            poll_interval,
+            statuses: Arc::new(RwLock::new(HashMap::new())),
+            shutdown,
+            runs,
         })
     }
 
     /// Creates a new [`notify::RecommendedWatcher`] and passes the RecommendedWatcher a callback to emit events over an MPSC channel
-    /// monitored in the route_subscriptions function.
-    fn notifier_task() -> notify::Result<(RecommendedWatcher, EventChannel)> {
+    /// monitored in the route_subscriptions function. The callback notes which watch paths it emits events for, see [`Watcher::record_emitted`].
+    fn notifier_task(
+        capacity: usize,
+        scripts_by_watch_path: SharedScripts,
+        emitted_at: EmittedAt,
+    ) -> notify::Result<(RecommendedWatcher, EventChannel)> {
         let (events_emitter, events_receiver) =
-            tokio::sync::broadcast::channel::<Result<Event, Arc<notify::Error>>>(16);
+            Self::new_channel::<Result<Event, Arc<notify::Error>>>(capacity);
         let events_channel_clone = events_emitter.clone();
 
         let notify_watcher = RecommendedWatcher::new(
             move |res| match res {
                 Ok(event) => {
+                    Self::record_emitted(&event, &scripts_by_watch_path, &emitted_at);
                     match events_emitter.send(Ok(event)) {
                         Ok(_) => {}
                         Err(e) => {
@@ -50,24 +86,42 @@ impl Watcher {
         Ok((notify_watcher, (events_channel_clone, events_receiver)))
     }
 
-    /// Begins watching a certain path. Uses its runtime to initialize threads to accept new subscriptions on, accept unsubscriptions on,
-    /// and to watch for new events.
+    /// Begins watching every provided path with a single notify watcher. Uses its runtime to initialize threads to accept
+    /// new subscriptions on, accept unsubscriptions on, and to watch for new events, regardless of how many paths are watched.
+    /// While those run, the config file is reloaded whenever it changes or the process receives SIGHUP, and watch paths
+    /// that don't exist are polled for until they do. Once shutdown is requested every path is unwatched, and subscriptions
+    /// already made are handed over to be flushed or set aside before returning.
     pub async fn start(
         &self,
-        spawn_channel: Sender<(PathBuf, Vec<Script>)>,
+        spawn_channel: Sender<SpawnMessage>,
         unsubscribe_channel: UnsubscribeSender,
-        watch_path: PathBuf,
-        scripts: &Scripts,
+        config_path: PathBuf,
+        scripts_by_watch_path: SharedScripts,
     ) -> Result<(), WatcherError> {
-        info!("now watching path: {}", &watch_path.to_str().unwrap());
+        let emitted_at: EmittedAt = Arc::new(Mutex::new(HashMap::new()));
         let (mut notifier_handle, (events_emitter, events_receiver)) =
-            Self::notifier_task().map_err(EventError::NotifyError)?;
+            Self::notifier_task(self.capacities.events, scripts_by_watch_path.clone(), emitted_at.clone())
+                .map_err(EventError::from)?;
+
+        for (watch_path, scripts) in Self::current_scripts(&scripts_by_watch_path).iter() {
+            Self::attach(&mut notifier_handle, watch_path, scripts.watch_options, &self.statuses)?;
+        }
 
-        notifier_handle
-            .watch(watch_path.as_ref(), RecursiveMode::Recursive)
-            .map_err(EventError::NotifyError)?;
+        let path_subscriber = PathSubscriber::new(
+            self.capacities.messages,
+            self.lag_counter.clone(),
+            self.metrics.clone(),
+            self.shutdown.clone(),
+            self.runs.clone(),
+        )?;
 
-        let path_subscriber = PathSubscriber::new()?;
+        // the subscription task drops its end on shutdown, which the event task would otherwise take as a failure
+        let _rescan_sender = path_subscriber.rescan_channel.0.clone();
+        let mut reload_channel: ReloadChannel = Self::new_channel::<()>(self.capacities.messages);
+        let mut config_watcher = Self::config_watcher(&config_path, reload_channel.0.clone())?;
+        let mut hangups = signal(SignalKind::hangup()).map_err(ThreadError::RuntimeError)?;
+        let mut depth_watch_channel: DepthWatchChannel =
+            Self::new_channel::<DepthWatchRequest>(self.capacities.messages);
 
         let (unsubscribe_task, subscribe_task, events_task) = Self::initialize_watcher_tasks(
             &self,
@@ -75,19 +129,82 @@ impl Watcher {
             events_receiver,
             path_subscriber.paths.clone(),
             path_subscriber,
-            scripts.clone(),
+            scripts_by_watch_path.clone(),
+            emitted_at,
             spawn_channel,
             unsubscribe_channel,
-            watch_path.clone(),
+            depth_watch_channel.0.clone(),
         );
 
-        Self::handle_all_futures(events_task, subscribe_task, unsubscribe_task).await;
+        let still_running = [events_task.abort_handle(), unsubscribe_task.abort_handle()];
+        let all_tasks = Self::handle_all_futures(events_task, subscribe_task, unsubscribe_task);
+        tokio::pin!(all_tasks);
+        let mut tasks_exited = false;
+        let mut poll_timer = interval(self.poll_interval);
+        poll_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = &mut all_tasks => {
+                    tasks_exited = true;
+                    break
+                },
+                _ = self.shutdown.requested() => {
+                    info!("shutting down, no longer taking in events");
+                    break
+                },
+                _ = hangups.recv() => {
+                    info!("received SIGHUP, reloading config");
+                    Self::reload_and_report(&config_path, &mut notifier_handle, &scripts_by_watch_path, &self.statuses);
+                    Self::rewatch_config(&config_path, &reload_channel.0, &mut config_watcher);
+                },
+                _ = reload_channel.1.recv() => {
+                    Self::settle_reload_requests(&mut reload_channel).await;
+                    info!("config file changed, reloading config");
+                    Self::reload_and_report(&config_path, &mut notifier_handle, &scripts_by_watch_path, &self.statuses);
+                    Self::rewatch_config(&config_path, &reload_channel.0, &mut config_watcher);
+                },
+                _ = poll_timer.tick() => {
+                    // the read lock has to be let go of before a reload can take the write lock
+                    let patterns_changed = Self::watch_path_patterns_changed(
+                        &config_path,
+                        &Self::current_scripts(&scripts_by_watch_path),
+                    );
+                    if patterns_changed {
+                        info!("watch path patterns match different paths, reloading config");
+                        Self::reload_and_report(&config_path, &mut notifier_handle, &scripts_by_watch_path, &self.statuses);
+                    }
+                    let scripts = Self::current_scripts(&scripts_by_watch_path);
+                    Self::poll_watch_paths(&mut notifier_handle, &self.statuses, &scripts);
+                },
+                requested = depth_watch_channel.1.recv() => match requested {
+                    Ok((dir, depth, follow_symlinks)) => {
+                        if let Err(e) = Self::watch_within_depth(&mut notifier_handle, &dir, depth, follow_symlinks) {
+                            error!("unable to watch {}: {}", dir.display(), e);
+                        }
+                    }
+                    Err(RecvError::Lagged(num_dropped)) => {
+                        self.lag_counter.record("depth watches", num_dropped);
+                    }
+                    Err(RecvError::Closed) => {}
+                },
+            }
+        }
 
         // cleanup
-        debug!("unwatching path, tasks will close");
-        notifier_handle
-            .unwatch(watch_path.as_ref())
-            .map_err(EventError::NotifyError)?;
+        debug!("unwatching paths, tasks will close");
+        let watch_paths = Self::current_scripts(&scripts_by_watch_path)
+            .iter()
+            .map(|(watch_path, scripts)| (watch_path.clone(), scripts.watch_options))
+            .collect_vec();
+        for (watch_path, options) in watch_paths {
+            Self::detach(&mut notifier_handle, &watch_path, options, &self.statuses);
+        }
+        if !tasks_exited {
+            // the subscription task returns once it's handed over every subscription already sent to it
+            all_tasks.await;
+        }
+        still_running.iter().for_each(|task| task.abort());
 
         Ok(())
     }
@@ -100,12 +217,13 @@ impl Watcher {
         &self,
         events_emitter: Sender<Result<Event, Arc<notify::Error>>>,
         events_receiver: tokio::sync::broadcast::Receiver<Result<Event, Arc<notify::Error>>>,
-        paths: Arc<tokio::sync::Mutex<std::collections::HashMap<u64, (PathBuf, Vec<Script>)>>>,
+        paths: PathsCacheArc,
         path_subscriber: PathSubscriber,
-        scripts: Scripts,
-        spawn_channel: Sender<(PathBuf, Vec<Script>)>,
+        scripts_by_watch_path: SharedScripts,
+        emitted_at: EmittedAt,
+        spawn_channel: Sender<SpawnMessage>,
         unsubscribe_channel: UnsubscribeSender,
-        watch_path: PathBuf,
+        depth_watch_sender: Sender<DepthWatchRequest>,
     ) -> (
         JoinHandle<Result<(), SubscriptionError>>,
         JoinHandle<Result<(), SubscriptionError>>,
@@ -115,34 +233,60 @@ impl Watcher {
         let subscriber_channel_2 = path_subscriber.subscribe_channel.0.clone();
         let paths_clone = path_subscriber.paths.clone();
         let unsubscribe_receiver = unsubscribe_channel.subscribe();
-        let watch_path_clone = watch_path.clone();
+        let rescan_receiver = path_subscriber.rescan_channel.0.subscribe();
+        let events_lag_counter = self.lag_counter.clone();
+        let statuses = self.statuses.clone();
+        let unsubscribe_lag_counter = self.lag_counter.clone();
+        let events_metrics = self.metrics.clone();
+        let subscription_metrics = self.metrics.clone();
+        let unsubscribe_metrics = self.metrics.clone();
 
         // start watching for new events from the notify crate
         let events_task: JoinHandle<Result<(), RecvError>> = self.runtime.spawn(async move {
-            Self::watch_events(
+            events_metrics.task_started("events");
+            let watched = Self::watch_events(
                 events_receiver,
-                watch_path_clone,
-                scripts,
+                rescan_receiver,
+                scripts_by_watch_path,
+                emitted_at,
                 subscriber_channel_2,
+                depth_watch_sender,
+                statuses,
+                events_lag_counter,
+                events_metrics.clone(),
             )
-            .await
+            .await;
+            events_metrics.task_stopped("events");
+            watched
         });
 
         // start watching for new path subscriptions coming from the event watcher
         let subscription_task = self.runtime.spawn(async move {
-            path_subscriber
+            subscription_metrics.task_started("subscribe");
+            let routed = path_subscriber
                 .route_subscriptions(
                     events_emitter.clone(),
                     spawn_channel,
                     subscriber_channel_1,
                     paths_clone,
                 )
-                .await
+                .await;
+            subscription_metrics.task_stopped("subscribe");
+            routed
         });
 
         // start watching for paths to *unsubscribe* from
         let unsubscribe_task = self.runtime.spawn(async move {
-            PathSubscriber::unsubscribe_task(unsubscribe_receiver, paths, watch_path).await
+            unsubscribe_metrics.task_started("unsubscribe");
+            let unsubscribed = PathSubscriber::unsubscribe_task(
+                unsubscribe_receiver,
+                paths,
+                unsubscribe_lag_counter,
+                unsubscribe_metrics.clone(),
+            )
+            .await;
+            unsubscribe_metrics.task_stopped("unsubscribe");
+            unsubscribed
         });
 
         (unsubscribe_task, subscription_task, events_task)
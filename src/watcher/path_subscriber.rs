@@ -1,42 +1,66 @@
-use super::structs::PathSubscriber;
+use super::structs::{PathSubscriber, WaitOutcome};
 use super::types::{PathHash, PathsCache, PathsCacheArc};
+use crate::control::{RunId, Runs};
 use crate::errors::runtime_error::enums::RuntimeError;
 use crate::errors::shared_errors::thread_errors::{ThreadError, UnexpectedAnyhowError};
 use crate::errors::watcher_errors::event_error::EventError;
 use crate::errors::watcher_errors::subscriber_error::SubscriptionError;
+use crate::metrics::Metrics;
 use crate::runner::types::SpawnMessage;
-use crate::scripts::structs::Script;
+use crate::scripts::structs::{Script, ScriptRegistry};
+use crate::shutdown::Shutdown;
+use crate::telemetry::spans;
 use crate::utilities::{
-    thread_types::{BroadcastReceiver, BroadcastSender, EventMessage},
+    lag_counter::LagCounter,
+    thread_types::{BroadcastReceiver, BroadcastSender, EventMessage, RescanRequest},
     traits::Utilities,
 };
+use crate::watcher::structs::Rename;
 use log::{debug, error, info};
 use notify::Event;
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
-use tokio::runtime::{Handle, Runtime};
-use tokio::sync::broadcast::Receiver;
+use opentelemetry::{trace::TraceContextExt, Context, KeyValue};
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::SystemTime};
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::{
+    error::{RecvError, TryRecvError},
+    Receiver,
+};
 use tokio::task::JoinHandle;
 
 impl PathSubscriber {
-    pub fn new() -> Result<Self, SubscriptionError> {
-        let path_cache: HashMap<PathHash, (PathBuf, Vec<Script>)> = HashMap::new();
+    pub fn new(
+        capacity: usize,
+        lag_counter: LagCounter,
+        metrics: Metrics,
+        shutdown: Shutdown,
+        runs: Runs,
+    ) -> Result<Self, SubscriptionError> {
+        let path_cache: HashMap<PathHash, SpawnMessage> = HashMap::new();
         let paths = Arc::new(tokio::sync::Mutex::new(path_cache));
-        let wait_threads = Self::new_runtime(4, &"timer-threads".to_string())?;
         Ok(PathSubscriber {
             paths,
-            subscribe_channel: Self::new_channel::<SpawnMessage>(),
-            unsubscribe_channel: Self::new_channel::<PathBuf>(),
-            wait_threads,
+            subscribe_channel: Self::new_channel::<SpawnMessage>(capacity),
+            unsubscribe_channel: Self::new_channel::<PathBuf>(capacity),
+            rescan_channel: Self::new_channel::<RescanRequest>(capacity),
+            lag_counter,
+            metrics,
+            shutdown,
+            runs,
         })
     }
 
-    pub fn unsubscribe(path: &PathBuf, mut paths: PathsCache<'_>) -> Result<(), SubscriptionError> {
-        let path_string = path
-            .to_str()
-            .unwrap_or("unable to pull string out of path buf");
-        let path_hash = Self::hasher(&path_string.to_string());
+    pub fn unsubscribe(
+        path: &PathBuf,
+        mut paths: PathsCache<'_>,
+        metrics: &Metrics,
+    ) -> Result<(), SubscriptionError> {
+        let path_hash = Self::path_hasher(path);
         match paths.remove_entry(&path_hash) {
-            Some(_) => Ok(()),
+            Some(_) => {
+                metrics.set_active_subscriptions(paths.len());
+                Ok(())
+            }
             None => Err(SubscriptionError::UnsubscribeError(format!(
                 "didn't find path in cache, didnt unsubscribe"
             ))),
@@ -46,29 +70,27 @@ impl PathSubscriber {
     pub async fn unsubscribe_task(
         mut unsubscribe_channel: Receiver<PathBuf>,
         paths: PathsCacheArc,
-        watch_path: PathBuf,
+        lag_counter: LagCounter,
+        metrics: Metrics,
     ) -> Result<(), SubscriptionError> {
         debug!("spawned unsubscribe thread");
+        metrics.track_queue("unsubscribe", &unsubscribe_channel);
         loop {
-            let path = unsubscribe_channel
-                .recv()
-                .await
-                .map_err(ThreadError::RecvError)?;
-            let path_str = path.to_str().unwrap_or("failed path string parse");
-            let watch_path_string = watch_path.to_str().unwrap_or("failed watch path parse");
-            if !Self::path_contains_subdir(&watch_path, &path) {
-                // single runner thread sends unsub messages across possible n watchers. need to filter out irrelevant unsub messages
-                debug!(
-                    "path {} NOT contained within watch path {}. skipping unsubscribe",
-                    path_str, watch_path_string
-                );
-                continue;
-            } else {
-                debug!(
-                    "path {} contained within watch path {}, unsubscribing",
-                    path_str, watch_path_string
-                )
-            }
+            let path = match unsubscribe_channel.recv().await {
+                Ok(path) => {
+                    metrics.set_queue_depth("unsubscribe", unsubscribe_channel.len());
+                    path
+                }
+                Err(RecvError::Lagged(num_dropped)) => {
+                    lag_counter.record("unsubscribe", num_dropped);
+                    error!("paths whose unsubscriptions were dropped stay subscribed, and won't trigger scripts again until restart");
+                    continue;
+                }
+                Err(e) => return Err(ThreadError::RecvError(e).into()),
+            };
+            // every subscription goes through this one subscriber, including those for watch paths removed by a
+            // config reload while their timers ran, so there's no filtering by watch path here
+            debug!("unsubscribing from path {}", path.display());
             let paths = match paths.try_lock() {
                 Ok(p) => p,
                 Err(e) => {
@@ -79,7 +101,7 @@ impl PathSubscriber {
                     continue;
                 }
             };
-            match PathSubscriber::unsubscribe(&path, paths) {
+            match PathSubscriber::unsubscribe(&path, paths, &metrics) {
                 Ok(_) => {
                     let path_display = path.display();
                     let unsubscribe_success_message =
@@ -121,67 +143,130 @@ impl PathSubscriber {
         }
     }
 
+    /// Waits out a subscribed path's timer, registering it with the path's run so it can be flushed. It's cut short if
+    /// shutdown is requested or the run is cancelled first.
     async fn start_waiting(
         original_path: PathBuf,
         events_listener: BroadcastReceiver<EventMessage>,
-    ) -> Result<(), SubscriptionError> {
+        lag_counter: LagCounter,
+        metrics: Metrics,
+        shutdown: &Shutdown,
+        (runs, run_id, cancel): (&Runs, RunId, Arc<Notify>),
+        cx: &Context,
+    ) -> Result<WaitOutcome, SubscriptionError> {
+        let debounce = spans::child(
+            cx,
+            "debounce",
+            vec![KeyValue::new("home_dir", original_path.display().to_string())],
+        );
         // thread that waits for events at particular path to end based on 1 or 2min timer and returns once either the events receiver closes or the timer runs out
         let new_timer = Self::new_timer(10);
         let timer_controller = new_timer.controller.clone();
+        runs.timer_started(run_id, timer_controller.clone());
 
         let handle = Handle::current();
-        let timer_thread = handle.spawn(async move {
+        let mut timer_thread = handle.spawn(async move {
             println!("now using existing Runtime to wait out script timer");
             new_timer.wait().await
         });
-        let events_thread = Self::event_loop(events_listener, original_path, timer_controller);
-        match timer_thread.await.map_err(RuntimeError::JoinError)? {
-            Ok(_) => {
+        let events_thread =
+            Self::event_loop(events_listener, original_path, timer_controller, lag_counter, metrics);
+        let waited = tokio::select! {
+            waited = &mut timer_thread => waited.map_err(RuntimeError::JoinError)?.map(|_| WaitOutcome::RanOut),
+            _ = shutdown.requested() => Ok(WaitOutcome::ShuttingDown),
+            _ = cancel.notified() => Ok(WaitOutcome::Cancelled),
+        };
+        timer_thread.abort();
+        debounce.span().end();
+        match waited {
+            Ok(outcome) => {
                 events_thread.abort();
-                Ok(())
+                Ok(outcome)
             }
             Err(e) => Err(e.into()),
         }
     }
 
+    /// Records a subscription, returning whether the path is new. A path already subscribed to keeps its timer, and picks
+    /// up any scripts it wasn't going to run yet along with the latest rename, so that e.g. a move out and a removal of the
+    /// same home dir both run their scripts once the timer runs out. The subscription stays part of the trace of the event
+    /// that started it.
     fn lock_and_update_paths(
         new_path: PathBuf,
-        paths: Arc<tokio::sync::Mutex<HashMap<PathHash, (PathBuf, Vec<Script>)>>>,
+        paths: PathsCacheArc,
         scripts: Vec<Script>,
+        rename: Option<Rename>,
+        cx: Context,
+        metrics: &Metrics,
     ) -> Result<bool, SubscriptionError> {
         let mut paths_lock = paths.try_lock()?;
 
-        let path_string = new_path
-            .to_str()
-            .unwrap_or("unable to pull string out of path buf");
-        let path_hash = Self::hasher(&path_string.to_string());
-
-        // should only return true if the path isn't found in the datastructure
-        let should_add_path = paths_lock.get(&path_hash).is_none();
-
-        paths_lock.insert(path_hash, (new_path, scripts));
+        // hashed lexically, so a path that was removed before its timer ran out can still be looked up
+        let path_hash = Self::path_hasher(&new_path);
 
-        Ok(should_add_path)
+        match paths_lock.get_mut(&path_hash) {
+            Some((_, subscribed_scripts, subscribed_rename, _)) => {
+                ScriptRegistry::merge_into(subscribed_scripts, scripts);
+                if rename.is_some() {
+                    *subscribed_rename = rename;
+                }
+                Ok(false)
+            }
+            None => {
+                paths_lock.insert(path_hash, (new_path, scripts, rename, cx));
+                metrics.set_active_subscriptions(paths_lock.len());
+                Ok(true)
+            }
+        }
     }
 
     pub async fn route_subscriptions(
         self: &Self,
         events_emitter: BroadcastSender<EventMessage>,
         spawn_channel: BroadcastSender<SpawnMessage>,
-        subscribe_channel: BroadcastSender<(PathBuf, Vec<Script>)>,
+        subscribe_channel: BroadcastSender<SpawnMessage>,
         paths: PathsCacheArc,
     ) -> Result<(), SubscriptionError> {
         debug!("spawned subscribe thread");
         let mut subscription_listener = subscribe_channel.subscribe();
+        self.metrics.track_queue("subscribe", &subscription_listener);
         let mut num_events_errors = 0;
         let mut last_error: Option<SubscriptionError> = None;
+        // dropped subscriptions were all sent after the last moment the listener was fully drained
+        let mut caught_up_at = SystemTime::now();
         loop {
-            let (path, scripts) = match subscription_listener
-                .recv()
-                .await
-                .map_err(ThreadError::RecvError)
-            {
-                Ok(e) => e,
+            // once shutting down, subscriptions already sent are still taken, so that they're flushed or set aside along
+            // with the rest, but nothing's waited for
+            let received = match self.shutdown.is_requested() {
+                false => tokio::select! {
+                    received = subscription_listener.recv() => received,
+                    _ = self.shutdown.requested() => continue,
+                },
+                true => match subscription_listener.try_recv() {
+                    Ok(message) => Ok(message),
+                    Err(TryRecvError::Lagged(num_dropped)) => Err(RecvError::Lagged(num_dropped)),
+                    Err(_) => {
+                        debug!("shutting down, no more subscriptions to take");
+                        return Ok(());
+                    }
+                },
+            };
+            let (path, scripts, rename, cx) = match received.map_err(ThreadError::RecvError) {
+                Ok(e) => {
+                    if subscription_listener.is_empty() {
+                        caught_up_at = SystemTime::now();
+                    }
+                    self.metrics.set_queue_depth("subscribe", subscription_listener.len());
+                    e
+                }
+                Err(ThreadError::RecvError(RecvError::Lagged(num_dropped))) => {
+                    // there's no telling which watch paths the dropped subscriptions belonged to, so ask for all of them to be rescanned
+                    self.lag_counter.record("subscribe", num_dropped);
+                    if let Err(e) = self.rescan_channel.0.send((None, caught_up_at)) {
+                        error!("unable to request a rescan after dropping subscriptions: {}", e);
+                    }
+                    continue;
+                }
                 Err(e) => {
                     num_events_errors += 1;
                     last_error = Some(e.into());
@@ -200,14 +285,27 @@ impl PathSubscriber {
                 return Err(last_error.unwrap_or(new_unexpected_error.into()).into());
             };
 
-            let subscribed_to_new_path =
-                match Self::lock_and_update_paths(path.clone(), paths.clone(), scripts.clone()) {
-                    Ok(subscribed) => subscribed,
-                    Err(e) => {
-                        error!("unable to subscribe to path: {}", e);
-                        continue;
-                    }
-                };
+            let subscribed_to_new_path = match Self::lock_and_update_paths(
+                path.clone(),
+                paths.clone(),
+                scripts,
+                rename,
+                cx.clone(),
+                &self.metrics,
+            ) {
+                Ok(subscribed) => subscribed,
+                Err(e) => {
+                    error!("unable to subscribe to path: {}", e);
+                    continue;
+                }
+            };
+
+            // a subscription joining one that's pending carries on in the pending one's trace
+            spans::dequeued(
+                &cx,
+                "subscribe_queue",
+                vec![KeyValue::new("subscription.new", subscribed_to_new_path)],
+            );
 
             let path_str = path
                 .to_str()
@@ -229,27 +327,86 @@ impl PathSubscriber {
                 let _: JoinHandle<Result<(), SubscriptionError>> = Self::spawn_new_wait_thread(
                     events_emitter.subscribe(),
                     path,
-                    &self.wait_threads,
-                    scripts,
+                    paths.clone(),
                     spawn_channel,
+                    self.lag_counter.clone(),
+                    self.metrics.clone(),
+                    self.shutdown.clone(),
+                    self.runs.clone(),
+                    cx,
                 );
             }
         }
     }
 
+    /// Spawns the timer for a newly subscribed path onto the runtime the subscription task is running on,
+    /// i.e. the runtime shared by every watch path. Once it runs out, whatever the path's subscription
+    /// accumulated in the meantime is sent on to the runner. Should shutdown cut the wait short, it's sent on
+    /// all the same if pending paths are being flushed, and set aside otherwise. Cancelling the run unsubscribes from the
+    /// path without running anything.
     fn spawn_new_wait_thread(
         events: Receiver<Result<Event, Arc<notify::Error>>>,
         path: PathBuf,
-        wait_threads: &Runtime,
-        scripts: Vec<Script>,
-        spawn_channel: tokio::sync::broadcast::Sender<(PathBuf, Vec<Script>)>,
+        paths: PathsCacheArc,
+        spawn_channel: tokio::sync::broadcast::Sender<SpawnMessage>,
+        lag_counter: LagCounter,
+        metrics: Metrics,
+        shutdown: Shutdown,
+        runs: Runs,
+        cx: Context,
     ) -> JoinHandle<Result<(), SubscriptionError>> {
-        wait_threads.spawn(async move {
-            let wait_out_new_events_path = Self::start_waiting(path.clone(), events);
-            wait_out_new_events_path.await?;
-            info!("successfully waited on timer expiration, now running scripts");
-            let stuff_to_send = (path.clone(), scripts);
-            spawn_channel.send(stuff_to_send)?;
+        let handle = Handle::current();
+        // taken before spawning, so the runner never sees a path between being subscribed and waiting
+        let debouncing = shutdown.start_debounce();
+        let (run_id, cancel) = runs.subscribed(&path);
+        handle.spawn(async move {
+            let wait_out_new_events_path = Self::start_waiting(
+                path.clone(),
+                events,
+                lag_counter,
+                metrics.clone(),
+                &shutdown,
+                (&runs, run_id, cancel),
+                &cx,
+            );
+            let outcome = wait_out_new_events_path.await?;
+            match outcome {
+                WaitOutcome::RanOut => info!("successfully waited on timer expiration, now running scripts"),
+                WaitOutcome::ShuttingDown => debug!("shutting down before the timer for {} ran out", path.display()),
+                WaitOutcome::Cancelled => {
+                    info!("run {} on {} was cancelled while debouncing", run_id, path.display());
+                    return Self::unsubscribe(&path, paths.lock().await, &metrics);
+                }
+            }
+            let path_hash = Self::path_hasher(&path);
+            let stuff_to_send = match paths.lock().await.get(&path_hash) {
+                Some((path, scripts, rename, cx)) => {
+                    (path.clone(), scripts.clone(), rename.clone(), spans::queued(cx).with_value(run_id))
+                }
+                None => {
+                    let missing_subscription: ThreadError = ThreadError::new_unexpected_error(format!(
+                        "subscription to {} was gone before its timer ran out",
+                        path.display()
+                    ));
+                    return Err(missing_subscription.into());
+                }
+            };
+            let send_on = outcome == WaitOutcome::RanOut || shutdown.flush;
+            match (send_on, runs.queued(run_id)) {
+                (true, true) => {
+                    spawn_channel.send(stuff_to_send)?;
+                }
+                (false, true) => {
+                    runs.finished(run_id);
+                    shutdown.set_aside(stuff_to_send)
+                }
+                (_, false) => {
+                    info!("run {} on {} was cancelled as its timer ran out", run_id, path.display());
+                    Self::unsubscribe(&path, paths.lock().await, &metrics)?;
+                }
+            }
+            // only stops counting as debouncing once its scripts are queued, or set aside
+            drop(debouncing);
             Ok(())
         })
     }
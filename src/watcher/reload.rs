@@ -0,0 +1,197 @@
+use super::structs::{WatchPathStatus, Watcher};
+use super::types::{ReloadChannel, SharedScripts, SharedWatchPathStatuses};
+use crate::errors::watcher_errors::{event_error::EventError, watcher_error::WatcherError};
+use crate::scripts::structs::{Scripts, ScriptsByWatchPath, ScriptsDiff};
+use crate::utilities::{path_key::PathKey, thread_types::BroadcastSender};
+use log::{debug, error, info};
+use notify::{
+    event::ModifyKind, Config, Event, EventKind, RecommendedWatcher, RecursiveMode,
+    Watcher as NotifyWatcher,
+};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::RwLockReadGuard;
+
+impl Watcher {
+    /// Read access to the scripts configured for each watch path. A poisoned lock only means a reload panicked midway,
+    /// and since reloads swap the whole map in one assignment, the map itself is still coherent.
+    pub fn current_scripts(scripts: &SharedScripts) -> RwLockReadGuard<'_, ScriptsByWatchPath> {
+        scripts.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Creates a notify watcher on the directories of every file the config is made up of, and on its fragments directory,
+    /// that requests a reload whenever one of those files changes or a fragment comes or goes. Directories are watched
+    /// rather than the files because editors commonly save by writing a temp file and renaming it over the original,
+    /// which would otherwise silently end a watch on the file. Includes can change with every reload, so the watcher is
+    /// meant to be created again after each one.
+    pub fn config_watcher(
+        config_path: &Path,
+        reload_channel: BroadcastSender<()>,
+    ) -> Result<RecommendedWatcher, WatcherError> {
+        let config_files: HashSet<PathKey> = Scripts::config_files(config_path)
+            .unwrap_or_else(|_| vec![config_path.to_path_buf()])
+            .iter()
+            .map(PathKey::from)
+            .collect();
+        let fragments_dir = PathKey::new(&Scripts::fragments_dir(config_path));
+        let mut watched_dirs = config_files
+            .iter()
+            .map(|file| {
+                file.as_path()
+                    .parent()
+                    .map(|parent| parent.to_path_buf())
+                    .unwrap_or(PathBuf::from("."))
+            })
+            .collect::<HashSet<PathBuf>>();
+        if fragments_dir.as_path().is_dir() {
+            watched_dirs.insert(fragments_dir.as_path().to_path_buf());
+        }
+
+        let mut config_watcher = RecommendedWatcher::new(
+            move |res: notify::Result<Event>| match res {
+                Ok(event) => {
+                    let touches_config = event.paths.iter().any(|path| {
+                        let path = PathKey::new(path);
+                        let is_fragment = path.as_path().parent() == Some(fragments_dir.as_path())
+                            && Scripts::is_fragment(path.as_path());
+                        config_files.contains(&path) || is_fragment
+                    });
+                    let is_write = match event.kind {
+                        EventKind::Create(_) => true,
+                        // fragments can be removed without anything taking their place
+                        EventKind::Remove(_) => true,
+                        EventKind::Modify(ModifyKind::Metadata(_)) => false,
+                        EventKind::Modify(_) => true,
+                        _ => false,
+                    };
+                    if touches_config && is_write {
+                        debug!("config file changed, requesting reload");
+                        let _ = reload_channel.send(());
+                    }
+                }
+                Err(e) => error!("error watching config file: {:?}", e),
+            },
+            Config::default(),
+        )
+        .map_err(EventError::from)?;
+
+        for dir in watched_dirs {
+            config_watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .map_err(EventError::from)?;
+        }
+
+        Ok(config_watcher)
+    }
+
+    /// Swaps the config watcher for one watching the files the config is made up of now, keeping the old one if that fails.
+    pub fn rewatch_config(
+        config_path: &Path,
+        reload_channel: &BroadcastSender<()>,
+        config_watcher: &mut RecommendedWatcher,
+    ) {
+        match Self::config_watcher(config_path, reload_channel.clone()) {
+            Ok(rewatched) => *config_watcher = rewatched,
+            Err(e) => error!("unable to watch the config files again, still watching the previous ones: {}", e),
+        }
+    }
+
+    /// Reloads the config file and applies the difference to the running watcher: new watch paths are attached, removed
+    /// ones are detached, paths whose depth changed are watched again, and the scripts of every remaining path are
+    /// swapped in place. Subscriptions already waiting out their timers keep the scripts they were subscribed with. If
+    /// the new config fails to load, or any new path that exists can't be watched, nothing is applied and the previous
+    /// config keeps running.
+    pub fn reload(
+        config_path: &Path,
+        notifier_handle: &mut RecommendedWatcher,
+        scripts: &SharedScripts,
+        statuses: &SharedWatchPathStatuses,
+    ) -> Result<ScriptsDiff, WatcherError> {
+        let incoming = Scripts::load_all(config_path)?;
+        let current = Self::current_scripts(scripts).clone();
+        let diff = ScriptsDiff::between(&current, &incoming);
+
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+
+        let mut newly_attached: Vec<&PathKey> = vec![];
+        for watch_path in &diff.added {
+            let options = incoming[watch_path].watch_options;
+            if let Err(e) = Self::attach(notifier_handle, watch_path, options, statuses) {
+                // roll back so the watched paths keep matching the config still in effect
+                for attached in newly_attached {
+                    Self::detach(notifier_handle, attached, incoming[attached].watch_options, statuses);
+                }
+                return Err(e.into());
+            }
+            newly_attached.push(watch_path);
+        }
+
+        for watch_path in &diff.removed {
+            Self::detach(notifier_handle, watch_path, current[watch_path].watch_options, statuses);
+        }
+
+        for watch_path in &diff.updated {
+            let (previous_options, options) =
+                (current[watch_path].watch_options, incoming[watch_path].watch_options);
+            if previous_options != options {
+                Self::detach(notifier_handle, watch_path, previous_options, statuses);
+                if let Err(e) = Self::attach(notifier_handle, watch_path, options, statuses) {
+                    // the rest of the new config is already in place, so this path is left to be polled for
+                    error!("unable to watch {} again with its new options: {}", watch_path.display(), e);
+                    Self::set_status(statuses, watch_path, Some(WatchPathStatus::Missing));
+                }
+            }
+        }
+
+        match scripts.write() {
+            Ok(mut current) => *current = incoming,
+            Err(poisoned) => *poisoned.into_inner() = incoming,
+        }
+
+        Ok(diff)
+    }
+
+    /// Runs [`Watcher::reload`], logging a summary of what changed or why the new config was rejected.
+    pub fn reload_and_report(
+        config_path: &Path,
+        notifier_handle: &mut RecommendedWatcher,
+        scripts: &SharedScripts,
+        statuses: &SharedWatchPathStatuses,
+    ) {
+        match Self::reload(config_path, notifier_handle, scripts, statuses) {
+            Ok(diff) if diff.is_empty() => info!("config reloaded, nothing changed"),
+            Ok(diff) => info!("config reloaded: {}", diff),
+            Err(e) => error!(
+                "rejected updated config, the previous one is still running: {}",
+                e
+            ),
+        }
+    }
+
+    /// Whether the config's watch path patterns now expand to other paths than the ones being watched, e.g. because a
+    /// drive matching `/mnt/disk*` was mounted. Configs that can't be read are left to the config watcher to report.
+    pub fn watch_path_patterns_changed(config_path: &Path, current: &ScriptsByWatchPath) -> bool {
+        if !Scripts::has_watch_path_patterns(config_path).unwrap_or(false) {
+            return false;
+        }
+        match Scripts::all_watch_paths(config_path) {
+            Ok(watch_paths) => {
+                let expanded: HashSet<PathKey> = watch_paths.iter().map(PathKey::from).collect();
+                let watched: HashSet<PathKey> = current.keys().cloned().collect();
+                expanded != watched
+            }
+            Err(e) => {
+                debug!("unable to re-expand watch path patterns: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Waits briefly after a reload request so the several events an editor's save produces collapse into one reload.
+    pub async fn settle_reload_requests(reload_channel: &mut ReloadChannel) {
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        while reload_channel.1.try_recv().is_ok() {}
+    }
+}
@@ -0,0 +1,314 @@
+use super::structs::{Rename, RenameCorrelator, Watcher};
+use super::types::SharedWatchPathStatuses;
+use crate::scripts::structs::{EventTrigger, Script, ScriptRegistry, ScriptsByWatchPath};
+use crate::telemetry::spans;
+use crate::utilities::{path_key::PathKey, thread_types::SubscribeSender};
+use crate::utilities::traits::Utilities;
+use itertools::Itertools;
+use log::{debug, error};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind,
+};
+use opentelemetry::Context;
+use std::{
+    ffi::OsString,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How long the From half of a rename waits for its To half before it's taken to have left every watch path.
+/// notify already holds a From back briefly and emits it right before its To, so this only needs to cover delivery.
+pub const RENAME_PAIRING_WINDOW: Duration = Duration::from_millis(250);
+
+/// Both halves of a rename, as far as they were seen. A missing half happened outside every watch path.
+pub type Move = (Option<PathBuf>, Option<PathBuf>);
+
+impl RenameCorrelator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an event is one half of a rename, and so should be handed to [`RenameCorrelator::correlate`].
+    pub fn is_rename(event: &Event) -> bool {
+        matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)))
+    }
+
+    /// Takes in a rename event and returns the move it completes, if any. From halves are held onto until their To half
+    /// arrives, or until [`RenameCorrelator::expire`] gives up on it.
+    pub fn correlate(&mut self, event: &Event, now: Instant) -> Option<Move> {
+        let EventKind::Modify(ModifyKind::Name(mode)) = event.kind else {
+            return None;
+        };
+        let path = event.paths.first().cloned()?;
+        match (mode, event.tracker()) {
+            (RenameMode::From, Some(tracker)) => {
+                self.pending.insert(tracker, (path, now));
+                None
+            }
+            (RenameMode::To, Some(tracker)) => {
+                let from = self.pending.remove(&tracker).map(|(from, _)| from);
+                Some((from, Some(path)))
+            }
+            // backends that don't track renames only ever report where things ended up
+            (RenameMode::To, None) => Some((None, Some(path))),
+            // Both repeats a pair already joined from its halves, and untracked Froms (e.g. a watch path itself moving) can't be paired
+            _ => None,
+        }
+    }
+
+    /// Gives up on From halves that waited out [`RENAME_PAIRING_WINDOW`], i.e. things moved out of every watch path.
+    pub fn expire(&mut self, now: Instant) -> Vec<Move> {
+        let expired = self
+            .pending
+            .iter()
+            .filter(|(_, (_, seen_at))| now.duration_since(*seen_at) >= RENAME_PAIRING_WINDOW)
+            .map(|(tracker, _)| *tracker)
+            .collect_vec();
+        expired
+            .into_iter()
+            .filter_map(|tracker| self.pending.remove(&tracker))
+            .map(|(from, _)| (Some(from), None))
+            .collect()
+    }
+}
+
+impl Rename {
+    /// Name scripts can tell the kind of rename apart by, exposed as `RUSTY_HOOKS_RENAME`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Rename::Within { .. } => "within",
+            Rename::MovedIn { .. } => "moved_in",
+            Rename::MovedOut { .. } => "moved_out",
+        }
+    }
+
+    /// Environment variables handed to scripts run because of this rename. Paths that happened outside every watch
+    /// path aren't known, so their variables are left unset.
+    pub fn env_vars(&self) -> Vec<(&'static str, OsString)> {
+        let (from, to) = match self {
+            Rename::Within { from, to } => (Some(from), Some(to)),
+            Rename::MovedIn { from, to } => (from.as_ref(), Some(to)),
+            Rename::MovedOut { from, to } => (Some(from), to.as_ref()),
+        };
+        let mut vars = vec![("RUSTY_HOOKS_RENAME", OsString::from(self.kind()))];
+        if let Some(from) = from {
+            vars.push(("RUSTY_HOOKS_OLD_PATH", from.clone().into_os_string()));
+        }
+        if let Some(to) = to {
+            vars.push(("RUSTY_HOOKS_NEW_PATH", to.clone().into_os_string()));
+        }
+        vars
+    }
+
+    /// Triggers whose scripts run on this rename. Anything renamed to a path inside the watch path still counts as a
+    /// `Modify`, as it did before moves were told apart.
+    fn triggers(&self) -> Vec<EventTrigger> {
+        let modify = EventTrigger::Kind(EventKind::Modify(ModifyKind::Name(RenameMode::To)));
+        match self {
+            Rename::Within { .. } => vec![modify],
+            Rename::MovedIn { .. } => vec![EventTrigger::MovedIn, modify],
+            Rename::MovedOut { .. } => vec![EventTrigger::MovedOut],
+        }
+    }
+
+    /// The path inside the watch path the rename touched.
+    fn affected_path(&self) -> &PathBuf {
+        match self {
+            Rename::Within { to, .. } | Rename::MovedIn { to, .. } => to,
+            Rename::MovedOut { from, .. } => from,
+        }
+    }
+}
+
+impl Watcher {
+    /// Works out what a move means to each watch path it touched. Moves between two watch paths leave one and enter the other.
+    fn classify_move(
+        (from, to): Move,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+    ) -> Vec<(PathKey, Rename)> {
+        let from_watch_path = from
+            .as_ref()
+            .and_then(|from| Self::route_to_watch_path(from, scripts_by_watch_path));
+        let to_watch_path = to
+            .as_ref()
+            .and_then(|to| Self::route_to_watch_path(to, scripts_by_watch_path));
+        match (from_watch_path, to_watch_path, from, to) {
+            (Some(from_watch_path), Some(to_watch_path), Some(from), Some(to))
+                if from_watch_path == to_watch_path =>
+            {
+                vec![(from_watch_path.clone(), Rename::Within { from, to })]
+            }
+            (from_watch_path, to_watch_path, from, to) => {
+                // a half outside every watch path stays unknown to the other one
+                let known_from = from.filter(|_| from_watch_path.is_some());
+                let known_to = to.filter(|_| to_watch_path.is_some());
+                let moved_out =
+                    from_watch_path
+                        .zip(known_from.clone())
+                        .map(|(watch_path, from)| {
+                            let rename = Rename::MovedOut {
+                                from,
+                                to: known_to.clone(),
+                            };
+                            (watch_path.clone(), rename)
+                        });
+                let moved_in = to_watch_path.zip(known_to.clone()).map(|(watch_path, to)| {
+                    let rename = Rename::MovedIn {
+                        from: known_from.clone(),
+                        to,
+                    };
+                    (watch_path.clone(), rename)
+                });
+                moved_out.into_iter().chain(moved_in).collect()
+            }
+        }
+    }
+
+    /// Subscribes to the home dir of every watch path a move touched, along with the scripts its kind of rename triggers
+    /// there. Watch paths with no such scripts aren't subscribed to at all.
+    pub fn subscribe_to_move(
+        mv: Move,
+        subscribe_channel: &SubscribeSender,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+        statuses: &SharedWatchPathStatuses,
+        cx: &Context,
+    ) {
+        for (watch_path, rename) in Self::classify_move(mv, scripts_by_watch_path) {
+            let scripts = &scripts_by_watch_path[&watch_path];
+            let depth = Self::depth_below(rename.affected_path(), &watch_path).unwrap_or(0);
+            let through_symlink =
+                scripts.watch_options.follow_symlinks && Self::through_symlink(rename.affected_path(), &watch_path);
+            let mut triggered: Vec<Script> = vec![];
+            for trigger in rename.triggers() {
+                let reached = scripts
+                    .get_by_trigger(&trigger)
+                    .into_iter()
+                    .filter(|script| script.reaches(depth) && script.follows(through_symlink))
+                    .collect();
+                ScriptRegistry::merge_into(&mut triggered, reached);
+            }
+            if triggered.is_empty() {
+                debug!(
+                    "no scripts to run on {} at {}",
+                    rename.kind(),
+                    watch_path.display()
+                );
+                continue;
+            }
+            let home_dir = match Self::event_home_dir(rename.affected_path(), &watch_path, statuses)
+            {
+                Ok(home_dir) => home_dir,
+                Err(_) => {
+                    error!(
+                        "unable to find the directory renamed inside of watch path {}, skipping",
+                        watch_path.display()
+                    );
+                    continue;
+                }
+            };
+            let subscription = (home_dir, triggered, Some(rename), spans::queued(cx));
+            if let Err(e) = subscribe_channel.send(subscription) {
+                error!("{:?}", e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RENAME_PAIRING_WINDOW;
+    use crate::scripts::structs::{Scripts, ScriptsByWatchPath, WatchOptions};
+    use crate::utilities::path_key::PathKey;
+    use crate::watcher::structs::{Rename, RenameCorrelator, Watcher};
+    use notify::{
+        event::{ModifyKind, RenameMode},
+        Event, EventKind,
+    };
+    use std::{
+        path::{Path, PathBuf},
+        time::Instant,
+    };
+
+    fn key(path: &str) -> PathKey {
+        PathKey::new(Path::new(path))
+    }
+
+    fn rename_event(mode: RenameMode, path: &str, tracker: usize) -> Event {
+        Event::new(EventKind::Modify(ModifyKind::Name(mode)))
+            .add_path(PathBuf::from(path))
+            .set_tracker(tracker)
+    }
+
+    fn watch_paths(paths: Vec<&str>) -> ScriptsByWatchPath {
+        paths
+            .into_iter()
+            .map(|path| {
+                let scripts = Scripts::for_test(&[Path::new(path)], WatchOptions::default());
+                (PathKey::new(Path::new(path)), scripts)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pairs_halves_by_tracker_and_expires_the_rest() {
+        let mut renames = RenameCorrelator::new();
+        let now = Instant::now();
+
+        assert_eq!(
+            renames.correlate(&rename_event(RenameMode::From, "/a/old", 1), now),
+            None
+        );
+        assert_eq!(
+            renames.correlate(&rename_event(RenameMode::From, "/a/gone", 2), now),
+            None
+        );
+        assert_eq!(
+            renames.correlate(&rename_event(RenameMode::To, "/a/new", 1), now),
+            Some((Some(PathBuf::from("/a/old")), Some(PathBuf::from("/a/new"))))
+        );
+
+        assert!(renames.expire(now).is_empty());
+        assert_eq!(
+            renames.expire(now + RENAME_PAIRING_WINDOW),
+            vec![(Some(PathBuf::from("/a/gone")), None)]
+        );
+    }
+
+    #[test]
+    fn moves_between_watch_paths_leave_one_and_enter_the_other() {
+        let scripts_by_watch_path = watch_paths(vec!["/a", "/b"]);
+        let within = (Some(PathBuf::from("/a/x/old")), Some(PathBuf::from("/a/y")));
+        let across = (Some(PathBuf::from("/a/x")), Some(PathBuf::from("/b/x")));
+
+        assert_eq!(
+            Watcher::classify_move(within, &scripts_by_watch_path),
+            vec![(
+                key("/a"),
+                Rename::Within {
+                    from: PathBuf::from("/a/x/old"),
+                    to: PathBuf::from("/a/y"),
+                }
+            )]
+        );
+        assert_eq!(
+            Watcher::classify_move(across, &scripts_by_watch_path),
+            vec![
+                (
+                    key("/a"),
+                    Rename::MovedOut {
+                        from: PathBuf::from("/a/x"),
+                        to: Some(PathBuf::from("/b/x")),
+                    }
+                ),
+                (
+                    key("/b"),
+                    Rename::MovedIn {
+                        from: Some(PathBuf::from("/a/x")),
+                        to: PathBuf::from("/b/x"),
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use super::structs::{RescanQueue, Watcher};
+use super::types::{EmittedAt, SharedScripts};
+use crate::scripts::structs::{Scripts, ScriptsByWatchPath};
+use crate::telemetry::spans;
+use crate::utilities::{path_key::PathKey, thread_types::SubscribeSender};
+use log::{error, info};
+use notify::{event::ModifyKind, event::RenameMode, Event, EventKind};
+use opentelemetry::{trace::TraceContextExt, Context, KeyValue};
+use std::os::unix::fs::MetadataExt;
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task;
+
+/// Least time between the start of one batch of rescans and the next, so that lags in quick succession are merged into one.
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Changes land on disk slightly before notify reports them, so rescans look back a little further than requested.
+const RESCAN_SLACK: Duration = Duration::from_secs(5);
+
+impl Watcher {
+    /// Rescans each watch path for anything changed since its own time, less some slack. See [`Watcher::rescan`].
+    /// Walks the file system, so it's meant to be run on a blocking thread, see [`RescanQueue::start`].
+    pub fn rescan_watch_paths(to_rescan: Vec<(PathBuf, Scripts, SystemTime)>, subscribe_channel: &SubscribeSender) {
+        for (path, scripts, since) in to_rescan {
+            let since = since.checked_sub(RESCAN_SLACK).unwrap_or(UNIX_EPOCH);
+            let cx = spans::root("rescan", vec![KeyValue::new("watch_path", path.display().to_string())]);
+            let num_resubscribed = Self::rescan(&path, &scripts, subscribe_channel, since, &cx);
+            cx.span().end();
+            info!(
+                "rescanned watch path {} after dropped events, resubscribed {} paths",
+                path.display(),
+                num_resubscribed
+            );
+        }
+    }
+
+    /// Notes the watch path beneath which each of an event's paths sits as having just had an event emitted for it.
+    /// Called by the notify callback before sending the event on, so that a lag only rescans the watch paths it could have touched.
+    pub fn record_emitted(event: &Event, scripts_by_watch_path: &SharedScripts, emitted_at: &EmittedAt) {
+        let now = SystemTime::now();
+        let scripts_by_watch_path = Self::current_scripts(scripts_by_watch_path);
+        let mut emitted_at = emitted_at.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for watch_path in event
+            .paths
+            .iter()
+            .filter_map(|path| Self::owning_watch_path(path, &scripts_by_watch_path))
+        {
+            emitted_at.insert(watch_path.to_path_buf(), now);
+        }
+    }
+
+    /// Watch paths an event was emitted for since the provided time, i.e. the ones events dropped after it could belong to.
+    pub fn emitted_since(emitted_at: &EmittedAt, since: SystemTime) -> Vec<PathBuf> {
+        let emitted_at = emitted_at.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        emitted_at
+            .iter()
+            .filter(|(_, emitted)| **emitted >= since)
+            .map(|(watch_path, _)| watch_path.clone())
+            .collect()
+    }
+
+    /// Subscribes every top level entry of a watch path that changed since `since`, as if a fresh event had arrived for it.
+    /// Used to recover from dropped events, since there's no telling which paths those events touched.
+    pub fn rescan(
+        watch_path: &PathBuf,
+        scripts: &Scripts,
+        subscribe_channel: &SubscribeSender,
+        since: SystemTime,
+        cx: &Context,
+    ) -> usize {
+        // a watch path that's a file is its own only entry
+        let entries: Vec<PathBuf> = match watch_path.is_file() {
+            true => vec![watch_path.clone()],
+            false => match watch_path.read_dir() {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .collect(),
+                Err(e) => {
+                    error!("unable to rescan watch path {}: {}", watch_path.display(), e);
+                    return 0;
+                }
+            },
+        };
+        let rescan_scripts = scripts.get_by_event(&Self::rescan_event_kind());
+        entries
+            .into_iter()
+            .filter(|event_home_dir| Self::changed_since(event_home_dir, since))
+            .filter(|event_home_dir| {
+                match subscribe_channel.send((event_home_dir.clone(), rescan_scripts.clone(), None, spans::queued(cx))) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        error!("error resubscribing to rescanned path: {:?}", e);
+                        false
+                    }
+                }
+            })
+            .count()
+    }
+
+    /// The kind of event `Modify` scripts are triggered by, and so the one a rescanned path is treated as having received.
+    pub fn rescan_event_kind() -> EventKind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::To))
+    }
+
+    /// Whether the path, or anything beneath it, changed since the provided time. Symlinks aren't followed.
+    fn changed_since(path: &Path, since: SystemTime) -> bool {
+        let Ok(metadata) = path.symlink_metadata() else {
+            return false;
+        };
+        if Self::last_changed(&metadata) >= since {
+            return true;
+        }
+        if !metadata.is_dir() {
+            return false;
+        }
+        let Ok(entries) = path.read_dir() else {
+            return false;
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .any(|entry| Self::changed_since(&entry.path(), since))
+    }
+
+    /// Later of the modification and status change times. Renames leave mtime untouched but bump ctime,
+    /// which matters because files moved into a watch path are exactly what rusty-hooks reacts to.
+    fn last_changed(metadata: &Metadata) -> SystemTime {
+        let status_changed = UNIX_EPOCH
+            + Duration::new(
+                metadata.ctime().max(0) as u64,
+                metadata.ctime_nsec().max(0) as u32,
+            );
+        match metadata.modified() {
+            Ok(modified) => modified.max(status_changed),
+            Err(_) => status_changed,
+        }
+    }
+}
+
+impl RescanQueue {
+    /// Queues a rescan of each watch path, going back to the earlier of `since` and any rescan of it already queued.
+    pub fn request(&mut self, watch_paths: impl IntoIterator<Item = PathBuf>, since: SystemTime) {
+        for watch_path in watch_paths {
+            self.pending
+                .entry(watch_path)
+                .and_modify(|queued| *queued = (*queued).min(since))
+                .or_insert(since);
+        }
+    }
+
+    /// Whether rescans are queued and no batch is still running.
+    pub fn is_ready(&self) -> bool {
+        !self.pending.is_empty() && self.running.is_none()
+    }
+
+    /// Starts rescanning every queued watch path on a blocking thread, each with a copy of its scripts so that the
+    /// shared ones aren't held on to during the walk. Watch paths that have since been removed from the config are skipped.
+    pub fn start(&mut self, scripts_by_watch_path: &ScriptsByWatchPath, subscribe_channel: &SubscribeSender) {
+        let to_rescan: Vec<(PathBuf, Scripts, SystemTime)> = self
+            .pending
+            .drain()
+            .filter_map(|(watch_path, since)| {
+                let scripts = scripts_by_watch_path.get(&PathKey::new(&watch_path))?.clone();
+                Some((watch_path, scripts, since))
+            })
+            .collect();
+        let subscribe_channel = subscribe_channel.clone();
+        self.running = Some(task::spawn_blocking(move || {
+            Watcher::rescan_watch_paths(to_rescan, &subscribe_channel)
+        }));
+    }
+
+    /// Waits for the running batch, if any, to finish. Cancel safe, so it can be raced against other events.
+    pub async fn finished(&mut self) {
+        if let Some(running) = self.running.as_mut() {
+            if let Err(e) = running.await {
+                error!("rescan failed: {}", e);
+            }
+        }
+        self.running = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watcher::structs::RescanQueue;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn merges_requests_into_the_earliest_time_per_watch_path() {
+        let mut rescans = RescanQueue::default();
+        let earlier = UNIX_EPOCH + Duration::from_secs(10);
+        let later = UNIX_EPOCH + Duration::from_secs(20);
+
+        rescans.request([PathBuf::from("/a")], later);
+        rescans.request([PathBuf::from("/a"), PathBuf::from("/b")], earlier);
+        rescans.request([PathBuf::from("/b")], later);
+
+        assert_eq!(rescans.pending.len(), 2);
+        assert_eq!(rescans.pending[&PathBuf::from("/a")], earlier);
+        assert_eq!(rescans.pending[&PathBuf::from("/b")], earlier);
+        assert!(rescans.is_ready());
+    }
+}
@@ -1,30 +1,125 @@
-use super::types::PathHash;
-use crate::scripts::structs::Script;
+use super::types::{PathsCacheArc, SharedWatchPathStatuses};
+use crate::control::Runs;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
 use crate::utilities::{
-    thread_types::{SubscribeChannel, UnsubscribeChannel},
+    lag_counter::LagCounter,
+    thread_types::{ChannelCapacities, RescanChannel, SubscribeChannel, UnsubscribeChannel},
     traits::Utilities,
 };
-use std::sync::Arc;
-use std::{collections::HashMap, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime};
+use std::{collections::HashMap, fmt, path::PathBuf};
 use tokio::runtime::Runtime;
+use tokio::task::JoinHandle;
 
-/// Watches for events at a given path and executes scripts hooked to that path when appropriate.
+/// Watches for events at every configured watch path and executes scripts hooked to those paths when appropriate.
+/// A single notify watcher multiplexes all paths; events are routed back to their watch path by ancestry.
 #[derive(Debug)]
 pub struct Watcher {
-    /// Runtime used to spawn the tasks need to watch for new subscriptions, unsubscriptions, and events.
+    /// Runtime used to spawn the tasks need to watch for new subscriptions, unsubscriptions, and events,
+    /// as well as the timers waiting out each subscribed path.
     pub runtime: Runtime,
+    /// Buffer sizes for the events channel and the channels between tasks.
+    pub capacities: ChannelCapacities,
+    /// Counts messages dropped by lagging receivers across every task the watcher spawns.
+    pub lag_counter: LagCounter,
+    /// Counts events, subscriptions and timer resets across every task the watcher spawns.
+    pub metrics: Metrics,
+    /// How often watch paths are checked for having appeared or disappeared.
+    pub poll_interval: Duration,
+    /// Whether each configured watch path is currently being watched, shared so it can be reported on.
+    pub statuses: SharedWatchPathStatuses,
+    /// Stops events from being taken in once requested, see [`Watcher::start`].
+    pub shutdown: Shutdown,
+    /// Runs of subscribed paths, registered as they're subscribed to so they can be listed, flushed and cancelled.
+    pub runs: Runs,
+}
+
+/// Whether a configured watch path is attached to the notify watcher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchPathStatus {
+    /// The path exists and notify is watching it.
+    Watching,
+    /// The path is a file, watched through its parent directory so that the watch survives the file being replaced,
+    /// e.g. by an editor saving through a rename.
+    WatchingFile,
+    /// The path doesn't exist or can't be read, e.g. an unmounted drive. It's polled for until it comes back.
+    Missing,
+}
+
+impl WatchPathStatus {
+    /// Whether events for the path are being received.
+    pub fn is_attached(&self) -> bool {
+        *self != WatchPathStatus::Missing
+    }
+}
+
+impl fmt::Display for WatchPathStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchPathStatus::Watching => write!(f, "watching"),
+            WatchPathStatus::WatchingFile => write!(f, "watching file"),
+            WatchPathStatus::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// A rename or move, as seen from the watch path whose scripts it's handed to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Rename {
+    /// Renamed without leaving the watch path.
+    Within { from: PathBuf, to: PathBuf },
+    /// Moved into the watch path. Where from is only known if it left another watch path.
+    MovedIn { from: Option<PathBuf>, to: PathBuf },
+    /// Moved out of the watch path. Where to is only known if it entered another watch path.
+    MovedOut { from: PathBuf, to: Option<PathBuf> },
+}
+
+/// Pairs up the halves of renames notify reports as separate From and To events, which share a tracker (the inotify cookie).
+#[derive(Debug, Default)]
+pub struct RenameCorrelator {
+    /// Paths renamed away from whose To half hasn't arrived yet, by tracker, with when they were seen.
+    pub pending: HashMap<usize, (PathBuf, Instant)>,
+}
+
+/// Rescans waiting to be run, by watch path, along with the earliest time events beneath it may have been dropped since.
+/// Requests arriving while a batch runs are merged into the next one, which keeps repeated lags from piling rescans up.
+#[derive(Debug, Default)]
+pub struct RescanQueue {
+    pub pending: HashMap<PathBuf, SystemTime>,
+    /// The batch currently walking its watch paths on a blocking thread.
+    pub running: Option<JoinHandle<()>>,
 }
 
 #[derive(Debug)]
 pub struct PathSubscriber {
     /// Concurrently updated data structure containing all paths currently subscribed to.
-    pub paths: Arc<tokio::sync::Mutex<HashMap<PathHash, (PathBuf, Vec<Script>)>>>,
+    pub paths: PathsCacheArc,
     /// MPSC channel over which new path subscriptions are sent.
     pub subscribe_channel: SubscribeChannel,
     /// MPSC channel by which paths are unsubscribed from.
     pub unsubscribe_channel: UnsubscribeChannel,
-    /// Runtime used to run timers in parallel.
-    pub wait_threads: Runtime,
+    /// Channel used to ask the event watching task to rescan watch paths after subscriptions were dropped.
+    pub rescan_channel: RescanChannel,
+    /// Counts messages dropped by lagging receivers.
+    pub lag_counter: LagCounter,
+    /// Tracks how many paths are subscribed to and how often their timers are reset.
+    pub metrics: Metrics,
+    /// Cuts timers short when shutting down, flushing or setting aside their paths.
+    pub shutdown: Shutdown,
+    /// Where each subscription's run is registered, along with its timer.
+    pub runs: Runs,
+}
+
+/// How waiting out a subscribed path's timer ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitOutcome {
+    RanOut,
+    /// Shutdown was requested before the timer ran out.
+    ShuttingDown,
+    /// The run was cancelled over the control socket.
+    Cancelled,
 }
 
 impl Utilities for PathSubscriber {}
@@ -1,8 +1,24 @@
-use crate::{runner::types::SpawnMessage, scripts::structs::Script};
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use super::structs::WatchPathStatus;
+use crate::{
+    runner::types::SpawnMessage,
+    scripts::structs::ScriptsByWatchPath,
+    utilities::thread_types::Channel,
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
 use tokio::sync::MutexGuard;
 use u64 as path_hash;
 
 pub type PathHash = path_hash;
 pub type PathsCache<'a> = MutexGuard<'a, HashMap<PathHash, SpawnMessage>>;
-pub type PathsCacheArc = Arc<tokio::sync::Mutex<HashMap<PathHash, (PathBuf, Vec<Script>)>>>;
+pub type PathsCacheArc = Arc<tokio::sync::Mutex<HashMap<PathHash, SpawnMessage>>>;
+pub type SharedScripts = Arc<RwLock<ScriptsByWatchPath>>; // swapped out wholesale when the config file is reloaded
+pub type ReloadChannel = Channel<()>;
+pub type DepthWatchRequest = (PathBuf, usize, bool); // directory that appeared in a watch path walked by hand, how many levels below it to watch, and whether to follow symlinks
+pub type DepthWatchChannel = Channel<DepthWatchRequest>;
+pub type SharedWatchPathStatuses = Arc<RwLock<HashMap<PathBuf, WatchPathStatus>>>;
+pub type EmittedAt = Arc<Mutex<HashMap<PathBuf, SystemTime>>>; // when notify last reported an event beneath each watch path, to tell which ones a lag could have touched
@@ -1,70 +1,236 @@
-use super::structs::Watcher;
-use crate::scripts::structs::Scripts;
-use crate::utilities::thread_types::{EventsReceiver, SubscribeSender};
-use crate::{scripts::structs::Script, utilities::traits::Utilities};
+use super::rename::RENAME_PAIRING_WINDOW;
+use super::rescan::RESCAN_INTERVAL;
+use super::structs::{RenameCorrelator, RescanQueue, Watcher};
+use super::structs::WatchPathStatus;
+use super::types::{DepthWatchRequest, EmittedAt, SharedScripts, SharedWatchPathStatuses};
+use crate::errors::watcher_errors::path_error::PathError;
+use crate::metrics::Metrics;
+use crate::scripts::structs::{EventTrigger, Scripts, ScriptsByWatchPath};
+use crate::telemetry::spans;
+use crate::utilities::{lag_counter::LagCounter, path_key::PathKey};
+use crate::utilities::thread_types::{
+    BroadcastReceiver, BroadcastSender, EventsReceiver, RescanRequest, SubscribeSender,
+};
+use crate::{runner::types::SpawnMessage, utilities::traits::Utilities};
 use itertools::Itertools;
 use log::{debug, error};
-use notify::{event::ModifyKind, Event, EventKind};
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    Event, EventKind,
+};
+use opentelemetry::{
+    trace::{Status, TraceContextExt},
+    Context, KeyValue,
+};
 use std::sync::Arc;
-use std::{collections::HashSet, path::PathBuf};
+use std::time::{Instant, SystemTime};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 use tokio::sync::broadcast::error::{RecvError, SendError};
+use tokio::time::{interval, MissedTickBehavior};
 
 impl Watcher {
-    /// Awaits events emitted by notify. See [`notify::event`].
+    /// Awaits events emitted by notify. See [`notify::event`]. If the events channel lags, or another task reports losing
+    /// subscriptions, the watch paths events were emitted for since they were last caught up on are rescanned for anything
+    /// that changed in the meantime. Rescans run on a blocking thread, one batch at a time and at most once per
+    /// [`RESCAN_INTERVAL`], with requests arriving in between merged into the next batch.
+    /// Rename events are paired up by their tracker, and the halves left unpaired are expired on a timer.
     pub async fn watch_events(
         mut events_receiver: EventsReceiver,
-        root_dir: PathBuf,
-        scripts: Scripts,
+        mut rescan_receiver: BroadcastReceiver<RescanRequest>,
+        scripts_by_watch_path: SharedScripts,
+        emitted_at: EmittedAt,
         subscribe_channel: SubscribeSender,
+        depth_watch_sender: BroadcastSender<DepthWatchRequest>,
+        statuses: SharedWatchPathStatuses,
+        lag_counter: LagCounter,
+        metrics: Metrics,
     ) -> Result<(), RecvError> {
         debug!("spawned event watching thread");
+        metrics.track_queue("events", &events_receiver);
+        // any event dropped from the channel arrived after the last moment it was fully drained
+        let started_at = SystemTime::now();
+        let mut caught_up_at = started_at;
+        let mut renames = RenameCorrelator::new();
+        let mut rename_timer = interval(RENAME_PAIRING_WINDOW);
+        rename_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut rescans = RescanQueue::default();
+        let mut rescan_timer = interval(RESCAN_INTERVAL);
+        rescan_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
-            match events_receiver.recv().await {
-                Ok(res) => {
-                    Self::evaluate_event(res, &root_dir, &subscribe_channel, &scripts);
-                }
-                Err(e) => {
-                    error!("Error encountered while a receiving a new event: {:?}", e);
-                }
-            };
+            tokio::select! {
+                received = events_receiver.recv() => match received {
+                    Ok(res) => {
+                        if events_receiver.is_empty() {
+                            caught_up_at = SystemTime::now();
+                        }
+                        metrics.set_queue_depth("events", events_receiver.len());
+                        let scripts_by_watch_path = Self::current_scripts(&scripts_by_watch_path);
+                        Self::evaluate_event(res, &subscribe_channel, &depth_watch_sender, &scripts_by_watch_path, &statuses, &mut renames, &metrics);
+                    }
+                    Err(RecvError::Lagged(num_dropped)) => {
+                        lag_counter.record("events", num_dropped);
+                        rescans.request(Self::emitted_since(&emitted_at, caught_up_at), caught_up_at);
+                    }
+                    Err(e) => {
+                        error!("Error encountered while a receiving a new event: {:?}", e);
+                        return Err(e);
+                    }
+                },
+                requested = rescan_receiver.recv() => match requested {
+                    Ok((Some(watch_path), since)) => rescans.request([watch_path], since),
+                    Ok((None, since)) => rescans.request(Self::emitted_since(&emitted_at, since), since),
+                    Err(RecvError::Lagged(num_dropped)) => {
+                        // the dropped requests' windows are unknown, so fall back to everything since startup
+                        lag_counter.record("rescan", num_dropped);
+                        rescans.request(Self::emitted_since(&emitted_at, started_at), started_at);
+                    }
+                    Err(e) => {
+                        error!("Error encountered while receiving a rescan request: {:?}", e);
+                        return Err(e);
+                    }
+                },
+                _ = rescan_timer.tick(), if rescans.is_ready() => {
+                    let scripts_by_watch_path = Self::current_scripts(&scripts_by_watch_path);
+                    rescans.start(&scripts_by_watch_path, &subscribe_channel);
+                },
+                _ = rescans.finished(), if rescans.running.is_some() => {},
+                _ = rename_timer.tick(), if !renames.pending.is_empty() => {
+                    let scripts_by_watch_path = Self::current_scripts(&scripts_by_watch_path);
+                    for moved_out in renames.expire(Instant::now()) {
+                        let cx = spans::root("expire_rename", vec![]);
+                        Self::subscribe_to_move(moved_out, &subscribe_channel, &scripts_by_watch_path, &statuses, &cx);
+                        cx.span().end();
+                    }
+                },
+            }
         }
     }
 
     fn evaluate_event(
         res: Result<Event, Arc<notify::Error>>,
-        root_dir: &PathBuf,
         subscribe_channel: &SubscribeSender,
-        scripts: &Scripts,
+        depth_watch_sender: &BroadcastSender<DepthWatchRequest>,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+        statuses: &SharedWatchPathStatuses,
+        renames: &mut RenameCorrelator,
+        metrics: &Metrics,
     ) {
+        // the trace every home dir the event subscribes to, and every script run because of it, is part of
+        let attributes = match &res {
+            Ok(event) => vec![
+                KeyValue::new("event.kind", format!("{:?}", event.kind)),
+                KeyValue::new("event.paths", event.paths.len() as i64),
+            ],
+            Err(_) => vec![],
+        };
+        let cx = spans::root("evaluate_event", attributes);
+        if let Ok(event) = &res {
+            Self::count_event(event, scripts_by_watch_path, metrics);
+            Self::request_depth_watches(event, depth_watch_sender, scripts_by_watch_path);
+        }
         match res {
+            Ok(event) if RenameCorrelator::is_rename(&event) => {
+                if let Some(mv) = renames.correlate(&event, Instant::now()) {
+                    Self::subscribe_to_move(mv, subscribe_channel, scripts_by_watch_path, statuses, &cx);
+                }
+            }
             Ok(event) => {
                 let subscription_errors =
-                    Self::decide_to_subscribe(&event, &root_dir, &subscribe_channel, &scripts);
+                    Self::decide_to_subscribe(&event, subscribe_channel, scripts_by_watch_path, statuses, &cx, metrics);
                 for error in &subscription_errors {
                     error!("{:?}", error)
                 }
             }
             Err(e) => {
                 error!("notify error: {:?}", e);
+                metrics.notify_error();
+                cx.span().set_status(Status::error(e.to_string()));
+            }
+        }
+        // what it subscribed to carries on in spans of its own
+        cx.span().end();
+    }
+
+    /// Counts an event under every watch path it was routed to, or as ignored if it wasn't routed to any.
+    fn count_event(event: &Event, scripts_by_watch_path: &ScriptsByWatchPath, metrics: &Metrics) {
+        let watch_paths = event
+            .paths
+            .iter()
+            .filter_map(|path| Self::route_to_watch_path(path, scripts_by_watch_path))
+            .unique()
+            .collect_vec();
+        if watch_paths.is_empty() {
+            metrics.event_ignored("outside_watch_paths");
+        }
+        for watch_path in watch_paths {
+            metrics.event_received(&event.kind, watch_path);
+        }
+    }
+
+    /// Asks for directories that appear inside a watch path walked by hand to be watched as well, if they're shallow enough for
+    /// their entries to count. notify already does this by itself for recursively watched paths.
+    fn request_depth_watches(
+        event: &Event,
+        depth_watch_sender: &BroadcastSender<DepthWatchRequest>,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+    ) {
+        let appeared = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+        );
+        if !appeared {
+            return;
+        }
+        for path in &event.paths {
+            let Some(watch_path) = Self::route_to_watch_path(path, scripts_by_watch_path) else {
+                continue;
+            };
+            let options = scripts_by_watch_path[watch_path].watch_options;
+            let Some(max_depth) = options.walked_depth() else {
+                continue;
+            };
+            let depth = Self::depth_below(path, watch_path).unwrap_or(0);
+            let is_dir = match options.follow_symlinks {
+                true => path.metadata(),
+                false => path.symlink_metadata(),
+            }
+            .is_ok_and(|metadata| metadata.is_dir());
+            if is_dir && depth < max_depth {
+                let request = (path.clone(), max_depth - depth, options.follow_symlinks);
+                if let Err(e) = depth_watch_sender.send(request) {
+                    error!("unable to ask for {} to be watched: {}", path.display(), e);
+                }
             }
         }
     }
 
     fn decide_to_subscribe(
         event: &Event,
-        root_dir: &PathBuf,
         subscribe_channel: &SubscribeSender,
-        scripts: &Scripts,
-    ) -> Vec<tokio::sync::broadcast::error::SendError<(PathBuf, Vec<Script>)>> {
-        match Self::ignore(&event) {
-            true => vec![],
+        scripts_by_watch_path: &ScriptsByWatchPath,
+        statuses: &SharedWatchPathStatuses,
+        cx: &Context,
+        metrics: &Metrics,
+    ) -> Vec<SendError<SpawnMessage>> {
+        let file_write = Self::as_file_write(event, scripts_by_watch_path, statuses);
+        match file_write.is_none() && Self::ignore(event) {
+            true => {
+                metrics.event_ignored("kind");
+                vec![]
+            }
             false => {
+                let event = file_write.as_ref().unwrap_or(event);
                 let unique_event_home_dirs =
-                    Self::get_unique_event_home_dirs(&event, root_dir.clone());
+                    Self::get_unique_event_home_dirs(event, scripts_by_watch_path, statuses);
                 unique_event_home_dirs
                     .iter()
-                    .map(|event_home_dir| {
-                        Self::send_new_event(&event, event_home_dir, &scripts, &subscribe_channel)
+                    .map(|((event_home_dir, watch_path), (depth, through_symlink))| {
+                        // every routed home dir came from a key of scripts_by_watch_path
+                        let scripts = &scripts_by_watch_path[watch_path];
+                        Self::send_new_event(event, event_home_dir, *depth, *through_symlink, scripts, subscribe_channel, cx)
                     })
                     .filter_map(|f| f.err())
                     .collect_vec()
@@ -72,17 +238,101 @@ impl Watcher {
         }
     }
 
-    /// Accepts events of kind Modify, finds *their* root dirs, i.e. the uppermost affected directory relative to the root watched path, and sends those to the subscribe runtime.
+    /// Writes to a watch path that's a file count as modifying it, the same as replacing it by a rename would. Returns the
+    /// event as a `Modify` if that's what it is. Writes inside watched directories are still left to settle into a rename.
+    fn as_file_write(
+        event: &Event,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+        statuses: &SharedWatchPathStatuses,
+    ) -> Option<Event> {
+        let is_write = matches!(
+            event.kind,
+            EventKind::Modify(ModifyKind::Data(_))
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        );
+        let all_watched_files = event.paths.iter().all(|path| {
+            Self::route_to_watch_path(path, scripts_by_watch_path).is_some_and(|watch_path| {
+                Self::status_of(statuses, watch_path) == Some(WatchPathStatus::WatchingFile)
+            })
+        });
+        (is_write && all_watched_files && !event.paths.is_empty()).then(|| Event {
+            kind: Self::rescan_event_kind(),
+            ..event.clone()
+        })
+    }
+
+    /// Finds the watch path an event path belongs to. Watch paths may be nested inside one another, in which case the
+    /// deepest one wins so that the event is handled by the scripts configured closest to it. Paths reached through a
+    /// symlinked directory belong to no watch path at all when the watch path they're in doesn't follow symlinks.
+    pub fn route_to_watch_path<'a>(
+        path: &PathBuf,
+        scripts_by_watch_path: &'a ScriptsByWatchPath,
+    ) -> Option<&'a PathKey> {
+        Self::owning_watch_path(path, scripts_by_watch_path).filter(|watch_path| {
+                scripts_by_watch_path[*watch_path].watch_options.follow_symlinks
+                    || !Self::through_symlink(path, watch_path)
+            })
+    }
+
+    /// The deepest watch path the path sits beneath, whether or not it's reached through a symlink.
+    pub fn owning_watch_path<'a>(path: &PathBuf, scripts_by_watch_path: &'a ScriptsByWatchPath) -> Option<&'a PathKey> {
+        scripts_by_watch_path
+            .keys()
+            .filter(|watch_path| Self::path_contains_subdir(watch_path, path))
+            .max_by_key(|watch_path| watch_path.components().count())
+    }
+
+    /// Whether any directory between the watch path and the path is a symlink. The watch path itself, and the path's own
+    /// entry, don't count: the watch path is resolved as configured, and the entry of a symlink lives in a real directory.
+    pub fn through_symlink(path: &Path, watch_path: &Path) -> bool {
+        let watch_path = PathKey::new(watch_path);
+        PathKey::new(path)
+            .as_path()
+            .ancestors()
+            .skip(1)
+            .take_while(|ancestor| *ancestor != watch_path.as_path())
+            .any(|ancestor| ancestor.is_symlink())
+    }
+
+    /// Finds the home dir of a path routed to a watch path. A watch path that's a file is its own home dir, so that
+    /// its scripts receive the file itself.
+    pub fn event_home_dir(
+        path: &PathBuf,
+        watch_path: &PathBuf,
+        statuses: &SharedWatchPathStatuses,
+    ) -> Result<PathBuf, PathError> {
+        match Self::status_of(statuses, watch_path) {
+            Some(WatchPathStatus::WatchingFile) => Ok(watch_path.clone()),
+            _ => Self::walk_up_to_event_home_dir(path.clone(), watch_path.clone()),
+        }
+    }
+
+    /// Accepts events of kind Modify, finds *their* root dirs, i.e. the uppermost affected directory relative to the watch path the event was
+    /// routed to, and pairs each with that watch path so the correct scripts are sent to the subscribe runtime, along with how deep
+    /// below the watch path the shallowest of the event's paths in it was. Paths deeper than their watch path is watched are skipped.
     /// Example: If a watch path derived from the user-provided scripts.yml is /home/user/script_1_watch_path, and the incoming event occurred
     /// at /home/user/script_1/very/very/very/nested, /home/user/script_1/very will be returned.
-    fn get_unique_event_home_dirs(event: &Event, root_dir: PathBuf) -> HashSet<PathBuf> {
-        let root_dir = root_dir.clone();
+    fn get_unique_event_home_dirs(
+        event: &Event,
+        scripts_by_watch_path: &ScriptsByWatchPath,
+        statuses: &SharedWatchPathStatuses,
+    ) -> HashMap<(PathBuf, PathKey), (usize, bool)> {
         let event_clone = event.clone();
         let paths = event_clone.paths;
-        let acc: HashSet<PathBuf> = HashSet::new();
-        // convert to hashset to enforce unique values
-        paths.iter().fold(acc, |mut acc:HashSet<PathBuf>, path| {
-            let events_root_dir = match Self::walk_up_to_event_home_dir(path.clone(), root_dir.clone()) {
+        let acc: HashMap<(PathBuf, PathKey), (usize, bool)> = HashMap::new();
+        // convert to hashmap to enforce unique values
+        paths.iter().fold(acc, |mut acc:HashMap<(PathBuf, PathKey), (usize, bool)>, path| {
+            let Some(watch_path) = Self::route_to_watch_path(path, scripts_by_watch_path) else {
+                debug!("event path {} isn't inside any watch path, skipping", path.display());
+                return acc
+            };
+            let depth = Self::depth_below(path, watch_path).unwrap_or(0);
+            let options = scripts_by_watch_path[watch_path].watch_options;
+            if options.max_depth.is_some_and(|max_depth| depth > max_depth) {
+                debug!("event path {} is deeper than its watch path is watched, skipping", path.display());
+                return acc
+            }
+            let events_root_dir = match Self::event_home_dir(path, watch_path, statuses) {
                 Ok(event_root) => event_root,
                 Err(_) => {
                     // TODO: cache errored paths to retry later?
@@ -90,34 +340,222 @@ impl Watcher {
                     return acc
                 }
             };
-            acc.insert(events_root_dir.clone()); // returns true  or false based on whether or not it already existed, but we dont care
+            // routing already turned the path away if none of the watch path's scripts follow symlinks
+            let through_symlink = options.follow_symlinks && Self::through_symlink(path, watch_path);
+            let (shallowest, only_through_symlinks) =
+                acc.entry((events_root_dir, watch_path.clone())).or_insert((depth, through_symlink));
+            *shallowest = depth.min(*shallowest);
+            *only_through_symlinks &= through_symlink;
             acc
         })
     }
 
-    /// Sends a new event to the PathSubscriber runtime, along with its related scripts (based on the directory in question)
+    /// Sends a new event to the PathSubscriber runtime, along with its related scripts (based on the directory in question, how deep
+    /// the event was and whether it was only reached through symlinked directories)
     fn send_new_event(
         event: &Event,
         event_dir: &PathBuf,
+        depth: usize,
+        through_symlink: bool,
         scripts: &Scripts,
         subscribe_channel: &SubscribeSender,
-    ) -> Result<(), SendError<(PathBuf, Vec<Script>)>> {
-        match subscribe_channel.send((event_dir.clone(), scripts.get_by_event(&event.kind))) {
+        cx: &Context,
+    ) -> Result<(), SendError<SpawnMessage>> {
+        let scripts = scripts
+            .get_by_event(&event.kind)
+            .into_iter()
+            .filter(|script| script.reaches(depth) && script.follows(through_symlink))
+            .collect_vec();
+        match subscribe_channel.send((event_dir.clone(), scripts, None, spans::queued(cx))) {
             Ok(_) => Ok(()),
             Err(e) => Err(e),
         }
     }
 
+    /// Whether scripts on a trigger can ever run. Renames run `MovedIn`, `MovedOut` and `Modify` scripts, writes to watched
+    /// files and rescans run `Modify` scripts too, and any other kind of event has to get past [`Watcher::ignore`].
+    pub fn reaches_scripts(trigger: &EventTrigger) -> bool {
+        match trigger {
+            EventTrigger::MovedIn | EventTrigger::MovedOut => true,
+            EventTrigger::Kind(kind) if *kind == Self::rescan_event_kind() => true,
+            EventTrigger::Kind(kind) => !Self::ignore(&Event::new(*kind)),
+        }
+    }
+
+    /// Renames never reach here, they're paired up first. See [`RenameCorrelator::correlate`].
     fn ignore(event: &notify::Event) -> bool {
         match &event.kind {
-            EventKind::Modify(e) => {
-                match e {
-                    // type of event that takes place when the file finishes and its .tmp extension is removed
-                    ModifyKind::Name(notify::event::RenameMode::To) => false,
-                    _ => true,
-                }
-            }
+            // the removed path is resolved lexically, so it still maps to a home dir once it's gone
+            EventKind::Remove(_) => false,
             _ => true,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::Metrics;
+    use crate::scripts::structs::{EventTrigger, Script, Scripts, ScriptsByWatchPath, WatchOptions};
+    use crate::utilities::{lag_counter::LagCounter, path_key::PathKey};
+    use crate::watcher::structs::{RenameCorrelator, WatchPathStatus, Watcher};
+    use itertools::Itertools;
+    use notify::{
+        event::{DataChange, ModifyKind, RemoveKind, RenameMode},
+        Event, EventKind,
+    };
+    use opentelemetry::Context;
+    use std::sync::{Arc, RwLock};
+    use std::{
+        collections::HashMap,
+        fs,
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    };
+    use tempdir::TempDir;
+
+    fn watch_path(path: &Path, follow_symlinks: bool) -> ScriptsByWatchPath {
+        let watch_options = WatchOptions { max_depth: None, follow_symlinks };
+        HashMap::from([(PathKey::new(path), Scripts::for_test(&[path], watch_options))])
+    }
+
+    fn script(name: &str, watch_path: &Path, follow_symlinks: bool) -> Script {
+        Script { follow_symlinks, ..Script::for_test(name, watch_path) }
+    }
+
+    #[test]
+    fn only_routes_through_symlinks_when_following_them() {
+        let outside = TempDir::new("outside").unwrap();
+        let watched = TempDir::new("watched").unwrap();
+        let root = watched.path().to_path_buf();
+        symlink(outside.path(), root.join("link")).unwrap();
+        let through_link = root.join("link").join("file");
+        let link_itself = root.join("link");
+
+        let following = watch_path(&root, true);
+        let not_following = watch_path(&root, false);
+        let routed = |path: &PathBuf, scripts: &ScriptsByWatchPath| {
+            Watcher::route_to_watch_path(path, scripts).map(|watch_path| watch_path.to_path_buf())
+        };
+
+        assert_eq!(routed(&through_link, &following), Some(root.clone()));
+        assert_eq!(routed(&through_link, &not_following), None);
+        assert_eq!(routed(&link_itself, &not_following), Some(root.clone()));
+    }
+
+    #[test]
+    fn leaves_out_scripts_not_following_symlinks_when_another_on_the_watch_path_does() {
+        let outside = TempDir::new("outside").unwrap();
+        let watched = TempDir::new("watched").unwrap();
+        let root = watched.path().to_path_buf();
+        symlink(outside.path(), root.join("link")).unwrap();
+        let mut scripts_by_watch_path = watch_path(&root, true);
+        let registry = &mut scripts_by_watch_path.get_mut(&PathKey::new(&root)).unwrap().registry;
+        let remove = EventTrigger::Kind(EventKind::Remove(RemoveKind::Any));
+        registry.register(remove, script("follows", &root, true));
+        registry.register(remove, script("stays", &root, false));
+        let statuses = Arc::new(RwLock::new(HashMap::new()));
+        let (subscribe_sender, mut subscribe_receiver) = tokio::sync::broadcast::channel(4);
+
+        let mut triggered = |path: PathBuf| {
+            let event = Event::new(EventKind::Remove(RemoveKind::Any)).add_path(path);
+            let home_dirs = Watcher::get_unique_event_home_dirs(&event, &scripts_by_watch_path, &statuses);
+            for ((home_dir, watch_path), (depth, through_symlink)) in home_dirs {
+                let scripts = &scripts_by_watch_path[&watch_path];
+                Watcher::send_new_event(&event, &home_dir, depth, through_symlink, scripts, &subscribe_sender, &Context::new())
+                    .unwrap();
+            }
+            let (_, scripts, _, _) = subscribe_receiver.try_recv().unwrap();
+            scripts.into_iter().map(|script| script.name).sorted().collect_vec()
+        };
+
+        assert_eq!(triggered(root.join("link").join("file")), vec!["follows"]);
+        assert_eq!(triggered(root.join("file")), vec!["follows", "stays"]);
+    }
+
+    #[test]
+    fn writes_and_renames_over_a_watched_file_reach_its_scripts_with_the_file() {
+        let dir = TempDir::new("file").unwrap();
+        let file = dir.path().join("config.yml");
+        fs::write(&file, "").unwrap();
+        let mut scripts_by_watch_path = watch_path(&file, true);
+        let registry = &mut scripts_by_watch_path.get_mut(&PathKey::new(&file)).unwrap().registry;
+        registry.register(EventTrigger::parse("Modify").unwrap(), script("reload", &file, true));
+        let statuses = Arc::new(RwLock::new(HashMap::from([(file.clone(), WatchPathStatus::WatchingFile)])));
+        let (subscribe_sender, mut subscribe_receiver) = tokio::sync::broadcast::channel(4);
+        let (depth_watch_sender, _) = tokio::sync::broadcast::channel(4);
+        let metrics = Metrics::new(LagCounter::new());
+        let mut renames = RenameCorrelator::new();
+
+        let mut subscribed = |event: Event| {
+            Watcher::evaluate_event(
+                Ok(event),
+                &subscribe_sender,
+                &depth_watch_sender,
+                &scripts_by_watch_path,
+                &statuses,
+                &mut renames,
+                &metrics,
+            );
+            let (path, scripts, _, _) = subscribe_receiver.try_recv().ok()?;
+            Some((path, scripts.into_iter().map(|script| script.name).collect_vec()))
+        };
+        let reloaded = Some((file.clone(), vec!["reload".to_string()]));
+
+        // an editor saving by writing beside the file, then renaming what it wrote over it
+        let swap_file = dir.path().join(".config.yml.swp");
+        let from = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::From))).add_path(swap_file).set_tracker(1);
+        let to = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::To))).add_path(file.clone()).set_tracker(1);
+        assert_eq!(subscribed(from), None);
+        assert_eq!(subscribed(to), reloaded);
+        let write = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content))).add_path(file.clone());
+        assert_eq!(subscribed(write), reloaded);
+    }
+
+    #[tokio::test]
+    async fn routes_events_from_every_watch_path_through_one_loop_to_their_own_scripts() {
+        let watched = ["first", "second"].map(|name| TempDir::new(name).unwrap());
+        let roots = watched.each_ref().map(|dir| dir.path().to_path_buf());
+        let remove = EventTrigger::Kind(EventKind::Remove(RemoveKind::Any));
+        let mut scripts_by_watch_path = roots.iter().flat_map(|root| watch_path(root, true)).collect::<ScriptsByWatchPath>();
+        for (root, name) in roots.iter().zip(["first", "second"]) {
+            let registry = &mut scripts_by_watch_path.get_mut(&PathKey::new(root)).unwrap().registry;
+            registry.register(remove, script(name, root, true));
+        }
+        let (events_sender, events_receiver) = tokio::sync::broadcast::channel(4);
+        let (_rescan_sender, rescan_receiver) = tokio::sync::broadcast::channel(4);
+        let (subscribe_sender, mut subscribe_receiver) = tokio::sync::broadcast::channel(4);
+        let (depth_watch_sender, _) = tokio::sync::broadcast::channel(4);
+        let lag_counter = LagCounter::new();
+        let watching = tokio::spawn(Watcher::watch_events(
+            events_receiver,
+            rescan_receiver,
+            Arc::new(RwLock::new(scripts_by_watch_path)),
+            Arc::new(Mutex::new(HashMap::new())),
+            subscribe_sender,
+            depth_watch_sender,
+            Arc::new(RwLock::new(HashMap::new())),
+            lag_counter.clone(),
+            Metrics::new(lag_counter),
+        ));
+
+        for root in roots.iter().rev() {
+            events_sender.send(Ok(Event::new(EventKind::Remove(RemoveKind::Any)).add_path(root.join("file")))).unwrap();
+        }
+        // the loop only ends once every event sent before the channel closed has been evaluated
+        drop(events_sender);
+        assert!(watching.await.unwrap().is_err());
+
+        let mut subscribed = HashMap::new();
+        while let Ok((path, scripts, _, _)) = subscribe_receiver.try_recv() {
+            subscribed.insert(path, scripts.into_iter().map(|script| script.name).collect_vec());
+        }
+        assert_eq!(
+            subscribed,
+            HashMap::from([
+                (roots[0].join("file"), vec!["first".to_string()]),
+                (roots[1].join("file"), vec!["second".to_string()]),
+            ])
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::{Output, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use serde_json::Value;
+use tempdir::TempDir;
+
+/// Starts rusty-hooks on a config with a single script run on removals from a directory in the temp dir, and waits for
+/// it to be watching. The script's run delay outlasts the test, so a path whose debounce runs out is still waiting in
+/// the runner at shutdown, and is set aside as unfinished all the same once the grace period is up.
+fn start(dir: &Path) -> std::process::Child {
+    let script = dir.join("noop.sh");
+    fs::write(&script, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let config = dir.join("scripts.yml");
+    fs::write(
+        &config,
+        format!(
+            "scripts:\n  - {{name: noop, description: d, file_name: noop.sh, watch_path: {}, enabled: true, run_delay: 255, event_triggers: [Remove], dependencies: []}}\n",
+            dir.join("watched").display()
+        ),
+    )
+    .unwrap();
+    let pid_file = dir.join("instance.pid");
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("rusty-hooks"))
+        .args(["--config", &config.display().to_string()])
+        .args(["--unfinished-file", &dir.join("unfinished.json").display().to_string()])
+        .args(["--pid-file", &pid_file.display().to_string()])
+        .args(["--shutdown-grace-period", "1", "--channel-capacity", "1024"])
+        .args(sockets(dir))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_until(|| fs::read_to_string(&pid_file).ok() == Some(child.id().to_string()), "the pid file to be locked");
+    wait_until(|| rusty_hooks(dir, "status").status.success(), "rusty hooks to be ready");
+    wait_until(|| listing(dir)["watch_paths"][0]["status"] == "watching", "the watch path to be watched");
+    child
+}
+
+fn sockets(dir: &Path) -> Vec<String> {
+    vec![
+        "--health-socket".to_string(),
+        dir.join("health.sock").display().to_string(),
+        "--control-socket".to_string(),
+        dir.join("control.sock").display().to_string(),
+    ]
+}
+
+fn rusty_hooks(dir: &Path, command: &str) -> Output {
+    std::process::Command::new(assert_cmd::cargo::cargo_bin("rusty-hooks"))
+        .args(sockets(dir))
+        .args(command.split_whitespace())
+        .output()
+        .unwrap()
+}
+
+/// What `list --json` answers with, or null while the control socket can't be reached.
+fn listing(dir: &Path) -> Value {
+    serde_json::from_slice(&rusty_hooks(dir, "list --json").stdout).unwrap_or(Value::Null)
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool, what: &str) {
+    let started = Instant::now();
+    while !condition() {
+        assert!(started.elapsed() < Duration::from_secs(60), "gave up waiting for {}", what);
+        sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn paths_still_debouncing_at_shutdown_are_saved_as_unfinished() {
+    let dir = TempDir::new("rusty_hooks_shutdown").unwrap();
+    let removed: Vec<_> = (0..300).map(|n| dir.path().join("watched").join(format!("removed-{}", n))).collect();
+    removed.iter().for_each(|path| fs::create_dir_all(path).unwrap());
+    let mut instance = start(dir.path());
+    removed.iter().for_each(|path| fs::remove_dir(path).unwrap());
+    wait_until(
+        || listing(dir.path())["runs"].as_array().is_some_and(|runs| runs.len() == removed.len()),
+        "every removal to be subscribed to",
+    );
+
+    unsafe { libc::kill(instance.id() as libc::pid_t, libc::SIGTERM) };
+    // 3 if a debounce ran out first and its run had to be killed while it sat out its run delay, set aside all the same
+    let exit_code = instance.wait().unwrap().code();
+    assert!(matches!(exit_code, Some(0) | Some(3)), "exited with {:?}", exit_code);
+    let unfinished = fs::read_to_string(dir.path().join("unfinished.json")).unwrap();
+    for path in removed {
+        assert!(unfinished.contains(&format!("{}\"", path.display())), "{} wasn't saved in {}", path.display(), unfinished);
+    }
+}
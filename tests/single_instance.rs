@@ -0,0 +1,149 @@
+use assert_cmd::Command;
+use predicates::str::contains;
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Child, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+use tempdir::TempDir;
+
+/// A rusty-hooks instance watching a temp dir, stopped with SIGTERM when dropped.
+struct Instance(Child);
+
+impl Instance {
+    fn start(dir: &Path, extra_args: &[&str]) -> Self {
+        let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("rusty-hooks"))
+            .args(args(dir))
+            .args(extra_args)
+            .env("XDG_RUNTIME_DIR", dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        Instance(child)
+    }
+
+    /// Waits for the instance to write its pid to the pid file, which it only does once it holds the lock.
+    fn wait_for_lock(&self, pid_file: &Path) {
+        let started = Instant::now();
+        while fs::read_to_string(pid_file).ok() != Some(self.0.id().to_string()) {
+            assert!(started.elapsed() < Duration::from_secs(30), "{} was never locked", pid_file.display());
+            sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe { libc::kill(self.0.id() as libc::pid_t, libc::SIGTERM) };
+        let _ = self.0.wait();
+    }
+}
+
+/// Writes a config with a single script watching a directory in the temp dir.
+fn config(dir: &Path) -> PathBuf {
+    let watched = dir.join("watched");
+    fs::create_dir_all(&watched).unwrap();
+    let script = dir.join("noop.sh");
+    fs::write(&script, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let config = dir.join("scripts.yml");
+    fs::write(
+        &config,
+        format!(
+            "scripts:\n  - {{name: noop, description: d, file_name: noop.sh, watch_path: {}, enabled: true, run_delay: 0, event_triggers: [Remove], dependencies: []}}\n",
+            watched.display()
+        ),
+    )
+    .unwrap();
+    config
+}
+
+fn args(dir: &Path) -> Vec<String> {
+    vec![
+        "--config".to_string(),
+        config(dir).display().to_string(),
+        "--unfinished-file".to_string(),
+        dir.join("unfinished.json").display().to_string(),
+    ]
+}
+
+#[test]
+fn second_instance_fails_while_the_first_holds_the_pid_file() {
+    let dir = TempDir::new("rusty_hooks_single_instance").unwrap();
+    let pid_file = dir.path().join("instance.pid");
+    let pid_file_arg = pid_file.display().to_string();
+    let first = Instance::start(dir.path(), &["--pid-file", &pid_file_arg]);
+    first.wait_for_lock(&pid_file);
+
+    Command::cargo_bin("rusty-hooks")
+        .unwrap()
+        .args(args(dir.path()))
+        .args(["--pid-file", &pid_file_arg])
+        .timeout(Duration::from_secs(30))
+        .assert()
+        .code(1)
+        .stderr(contains(format!("rusty hooks is already running as pid {}", first.0.id())));
+}
+
+#[test]
+fn named_instances_run_side_by_side() {
+    let dir = TempDir::new("rusty_hooks_named_instances").unwrap();
+    let runtime_dir = dir.path().join("rusty-hooks");
+    let first = Instance::start(dir.path(), &[]);
+    first.wait_for_lock(&runtime_dir.join("rusty-hooks.pid"));
+    let second = Instance::start(dir.path(), &["--instance", "other"]);
+    second.wait_for_lock(&runtime_dir.join("rusty-hooks-other.pid"));
+
+    Command::cargo_bin("rusty-hooks")
+        .unwrap()
+        .args(args(dir.path()))
+        .args(["--instance", "other"])
+        .env("XDG_RUNTIME_DIR", dir.path())
+        .timeout(Duration::from_secs(30))
+        .assert()
+        .code(1)
+        .stderr(contains("already running"));
+}
+
+#[test]
+fn daemon_runs_in_the_background_until_stopped_or_restarted() {
+    let dir = TempDir::new("rusty_hooks_daemon").unwrap();
+    let pid_file = dir.path().join("daemon.pid");
+    let pid_file_arg = pid_file.display().to_string();
+    let output = dir.path().join("daemon.out").display().to_string();
+    let rusty_hooks = |extra_args: &[&str]| {
+        let mut command = Command::cargo_bin("rusty-hooks").unwrap();
+        command
+            .args(args(dir.path()))
+            .args(["--pid-file", &pid_file_arg, "--daemon-output", &output])
+            .args(extra_args)
+            .timeout(Duration::from_secs(60));
+        command
+    };
+
+    rusty_hooks(&["--daemon"])
+        .assert()
+        .success()
+        .stdout(contains("rusty hooks started in the background as pid"));
+    let first_pid = fs::read_to_string(&pid_file).unwrap();
+    rusty_hooks(&["--daemon"]).assert().code(1).stderr(contains(format!("already running as pid {}", first_pid)));
+
+    rusty_hooks(&["restart"])
+        .assert()
+        .success()
+        .stdout(contains(format!("stopped rusty hooks, which was running as pid {}", first_pid)));
+    let second_pid = fs::read_to_string(&pid_file).unwrap();
+    assert_ne!(first_pid, second_pid);
+
+    rusty_hooks(&["stop"])
+        .assert()
+        .success()
+        .stdout(contains(format!("stopped rusty hooks, which was running as pid {}", second_pid)));
+    // released on the way out, before stop saw it exit
+    assert!(!pid_file.exists());
+    rusty_hooks(&["stop"]).assert().success().stdout(contains("rusty hooks isn't running"));
+}